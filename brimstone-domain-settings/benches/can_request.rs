@@ -0,0 +1,77 @@
+
+//! Compares the previous per-request SQL lookups against the rule index.
+//!
+//! Run with `cargo bench -p brimstone-domain-settings`.
+
+extern crate brimstone_domain_settings as domain_settings;
+
+use std::time;
+
+use domain_settings::{Settings, Host, ResourceType};
+
+const SOURCES: usize = 500;
+const TARGETS_PER_SOURCE: usize = 10;
+const REQUESTS: usize = 500;
+const ROUNDS: usize = 5;
+
+fn domain(name: String) -> Host { Host::new(&name, true) }
+
+fn sql_can_request(settings: &Settings, source: &Host, target: &Host) -> bool {
+    if source.to_expanded().iter().any(|source| settings.has_always_entry(source)) {
+        return true;
+    }
+    target.to_expanded().iter().any(|target| settings.has_entry(source, target, None))
+}
+
+fn measure<F>(name: &str, requests: &[(Host, Host)], check: F) -> usize
+where F: Fn(&Host, &Host) -> bool {
+    let started = time::Instant::now();
+    let mut allowed = 0;
+    for _ in 0..ROUNDS {
+        for &(ref source, ref target) in requests {
+            if check(source, target) {
+                allowed += 1;
+            }
+        }
+    }
+    let elapsed = started.elapsed();
+    let nanos = elapsed.as_secs() * 1_000_000_000 + u64::from(elapsed.subsec_nanos());
+    println!(
+        "{:>6}: {} requests in {}.{:03} ms ({} ns/request)",
+        name,
+        requests.len() * ROUNDS,
+        nanos / 1_000_000,
+        (nanos / 1_000) % 1_000,
+        nanos / (requests.len() * ROUNDS) as u64,
+    );
+    allowed
+}
+
+fn main() {
+    let settings = Settings::open_in_memory().expect("in-memory domain settings");
+    for source in 0..SOURCES {
+        let source_host = domain(format!("source{}.com", source));
+        if source % 50 == 0 {
+            settings.insert_always_entry(&source_host);
+        }
+        for target in 0..TARGETS_PER_SOURCE {
+            let target_host = domain(format!("cdn{}.target{}.net", target, source + target));
+            settings.insert_entry(&source_host, &target_host, None);
+        }
+    }
+
+    let requests = (0..REQUESTS)
+        .map(|index| (
+            domain(format!("www.source{}.com", (index * 7) % SOURCES)),
+            domain(format!("a.cdn{}.target{}.net", index % TARGETS_PER_SOURCE, index % SOURCES)),
+        ))
+        .collect::<Vec<_>>();
+
+    let sql = measure("sql", &requests, |source, target| {
+        sql_can_request(&settings, source, target)
+    });
+    let index = measure("index", &requests, |source, target| {
+        settings.can_request(source, target, ResourceType::Other)
+    });
+    assert_eq!(sql, index, "both lookups agree");
+}
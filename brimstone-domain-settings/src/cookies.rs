@@ -0,0 +1,42 @@
+
+/// Which cookies are accepted and sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CookiePolicy {
+    Always,
+    /// Only cookies of the same site as the page.
+    NoThirdParty,
+    Never,
+}
+
+impl CookiePolicy {
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            CookiePolicy::Always => "always",
+            CookiePolicy::NoThirdParty => "no-third-party",
+            CookiePolicy::Never => "never",
+        }
+    }
+
+    pub fn from_str(value: &str) -> Option<CookiePolicy> {
+        match value {
+            "always" => Some(CookiePolicy::Always),
+            "no-third-party" => Some(CookiePolicy::NoThirdParty),
+            "never" => Some(CookiePolicy::Never),
+            _ => None,
+        }
+    }
+
+    pub fn allows(&self, is_third_party: bool) -> bool {
+        match *self {
+            CookiePolicy::Always => true,
+            CookiePolicy::NoThirdParty => !is_third_party,
+            CookiePolicy::Never => false,
+        }
+    }
+}
+
+impl Default for CookiePolicy {
+
+    fn default() -> CookiePolicy { CookiePolicy::NoThirdParty }
+}
@@ -0,0 +1,438 @@
+
+//! Adblock Plus / EasyList network filters.
+//!
+//! Supported are `||` host anchors, `|` start and end anchors, `*`
+//! wildcards, `^` separators, `@@` exceptions and the `third-party`,
+//! `domain`, `match-case` and resource type options. Element hiding
+//! rules, regular expression filters and filters with other options are
+//! skipped.
+
+use std::collections;
+
+use url;
+
+use Host;
+use ResourceType;
+
+const MIN_TOKEN_LEN: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Anchor {
+    None,
+    Start,
+    Host,
+}
+
+#[derive(Debug, Clone)]
+pub struct Filter {
+    is_exception: bool,
+    anchor: Anchor,
+    pattern: String,
+    is_end_anchored: bool,
+    is_match_case: bool,
+    third_party: Option<bool>,
+    types: Vec<ResourceType>,
+    excluded_types: Vec<ResourceType>,
+    domains: Vec<String>,
+    excluded_domains: Vec<String>,
+}
+
+fn parse_type(name: &str) -> Option<ResourceType> {
+    Some(match name {
+        "script" => ResourceType::Script,
+        "image" => ResourceType::Image,
+        "stylesheet" => ResourceType::Stylesheet,
+        "font" => ResourceType::Font,
+        "media" => ResourceType::Media,
+        "xmlhttprequest" => ResourceType::Xhr,
+        "subdocument" => ResourceType::Frame,
+        "other" => ResourceType::Other,
+        _ => return None,
+    })
+}
+
+fn is_separator(byte: u8) -> bool {
+    match byte {
+        b'a'...b'z' | b'A'...b'Z' | b'0'...b'9' | b'_' | b'-' | b'.' | b'%' => false,
+        _ => true,
+    }
+}
+
+fn is_token_byte(byte: u8) -> bool {
+    match byte {
+        b'a'...b'z' | b'0'...b'9' | b'%' => true,
+        _ => false,
+    }
+}
+
+/// Length of the text matched by a pattern segment without wildcards at
+/// the start of the text. `^` matches a single separator byte or the end
+/// of the text.
+fn match_segment(segment: &[u8], text: &[u8]) -> Option<usize> {
+    for (index, &byte) in segment.iter().enumerate() {
+        match text.get(index) {
+            Some(&other) if other == byte || (byte == b'^' && is_separator(other)) => (),
+            Some(_) => return None,
+            None => {
+                return if segment[index..].iter().all(|&byte| byte == b'^') {
+                    Some(text.len())
+                } else {
+                    None
+                };
+            },
+        }
+    }
+    Some(segment.len())
+}
+
+/// End of the first occurrence of a segment in the text.
+fn find_segment(segment: &[u8], text: &[u8]) -> Option<usize> {
+    (0..text.len() + 1)
+        .filter_map(|pos| match_segment(segment, &text[pos..]).map(|len| pos + len))
+        .next()
+}
+
+/// Matches the pattern by finding its `*` separated segments in order.
+///
+/// Taking the first occurrence of every segment is enough, since the
+/// following segments only get more room that way.
+fn matches_pattern(
+    pattern: &[u8],
+    text: &[u8],
+    is_start_anchored: bool,
+    is_end_anchored: bool,
+) -> bool {
+    let mut segments = pattern.split(|&byte| byte == b'*').collect::<Vec<_>>();
+    if !is_start_anchored {
+        segments.insert(0, &[][..]);
+    }
+    let (first, rest) = segments.split_first().expect("at least one pattern segment");
+
+    let mut pos = match match_segment(first, text) {
+        Some(end) => end,
+        None => return false,
+    };
+    let (last, middle) = match rest.split_last() {
+        Some(split) => split,
+        None => return !is_end_anchored || pos == text.len(),
+    };
+
+    for segment in middle {
+        match find_segment(segment, &text[pos..]) {
+            Some(end) => pos += end,
+            None => return false,
+        }
+    }
+
+    if is_end_anchored {
+        (pos..text.len() + 1)
+            .any(|start| match_segment(last, &text[start..]) == Some(text.len() - start))
+    } else {
+        find_segment(last, &text[pos..]).is_some()
+    }
+}
+
+fn is_domain_or_parent(host: &str, domain: &str) -> bool {
+    host == domain
+        || (host.ends_with(domain) && host[..host.len() - domain.len()].ends_with('.'))
+}
+
+impl Filter {
+
+    /// Parses a single line of a filter list.
+    ///
+    /// Returns `None` for comments, element hiding rules and filters using
+    /// unsupported syntax.
+    pub fn parse(line: &str) -> Option<Filter> {
+        let line = line.trim();
+        if line.is_empty()
+            || line.starts_with('!')
+            || line.starts_with('[')
+            || line.contains("##")
+            || line.contains("#@#")
+            || line.contains("#?#")
+            || line.contains("#$#")
+        {
+            return None;
+        }
+
+        let (is_exception, line) =
+            if line.starts_with("@@") { (true, &line[2..]) } else { (false, line) };
+
+        let mut filter = Filter {
+            is_exception,
+            anchor: Anchor::None,
+            pattern: String::new(),
+            is_end_anchored: false,
+            is_match_case: false,
+            third_party: None,
+            types: Vec::new(),
+            excluded_types: Vec::new(),
+            domains: Vec::new(),
+            excluded_domains: Vec::new(),
+        };
+
+        let (pattern, options) = match line.rfind('$') {
+            Some(pos) => (&line[..pos], Some(&line[pos + 1..])),
+            None => (line, None),
+        };
+
+        if let Some(options) = options {
+            for option in options.split(',') {
+                let option = option.trim().to_ascii_lowercase();
+                let (is_negated, name) =
+                    if option.starts_with('~') { (true, &option[1..]) } else { (false, &option[..]) };
+                if name == "third-party" || name == "3p" {
+                    filter.third_party = Some(!is_negated);
+                } else if name == "first-party" || name == "1p" {
+                    filter.third_party = Some(is_negated);
+                } else if name == "match-case" && !is_negated {
+                    filter.is_match_case = true;
+                } else if name.starts_with("domain=") && !is_negated {
+                    for domain in name["domain=".len()..].split('|') {
+                        if domain.starts_with('~') {
+                            filter.excluded_domains.push(domain[1..].into());
+                        } else if !domain.is_empty() {
+                            filter.domains.push(domain.into());
+                        }
+                    }
+                } else if let Some(kind) = parse_type(name) {
+                    if is_negated {
+                        filter.excluded_types.push(kind);
+                    } else {
+                        filter.types.push(kind);
+                    }
+                } else {
+                    return None;
+                }
+            }
+        }
+
+        if pattern.len() > 1 && pattern.starts_with('/') && pattern.ends_with('/') {
+            return None;
+        }
+
+        let mut pattern = pattern;
+        if pattern.starts_with("||") {
+            filter.anchor = Anchor::Host;
+            pattern = &pattern[2..];
+        } else if pattern.starts_with('|') {
+            filter.anchor = Anchor::Start;
+            pattern = &pattern[1..];
+        }
+        if pattern.ends_with('|') {
+            filter.is_end_anchored = true;
+            pattern = &pattern[..pattern.len() - 1];
+        }
+
+        // leading and trailing wildcards are implied
+        if filter.anchor == Anchor::None {
+            pattern = pattern.trim_left_matches('*');
+        }
+        if !filter.is_end_anchored {
+            pattern = pattern.trim_right_matches('*');
+        }
+
+        filter.pattern =
+            if filter.is_match_case { pattern.into() } else { pattern.to_ascii_lowercase() };
+
+        if filter.pattern.is_empty() && filter.anchor == Anchor::None && filter.domains.is_empty() {
+            return None;
+        }
+
+        Some(filter)
+    }
+
+    pub fn is_exception(&self) -> bool { self.is_exception }
+
+    /// The most specific literal part of the pattern that has to show up
+    /// as a complete token in every matching URL.
+    fn token(&self) -> Option<String> {
+        let pattern = self.pattern.to_ascii_lowercase();
+        let bytes = pattern.as_bytes();
+        let mut best: Option<&[u8]> = None;
+        let mut start = 0;
+        while start < bytes.len() {
+            if !is_token_byte(bytes[start]) {
+                start += 1;
+                continue;
+            }
+            let mut end = start;
+            while end < bytes.len() && is_token_byte(bytes[end]) {
+                end += 1;
+            }
+            let is_bounded_left =
+                if start == 0 {
+                    self.anchor != Anchor::None
+                } else {
+                    bytes[start - 1] != b'*'
+                };
+            let is_bounded_right =
+                if end == bytes.len() {
+                    self.is_end_anchored
+                } else {
+                    bytes[end] != b'*'
+                };
+            let candidate = &bytes[start..end];
+            let is_better = best.map(|best| candidate.len() > best.len()).unwrap_or(true);
+            if is_bounded_left && is_bounded_right && candidate.len() >= MIN_TOKEN_LEN && is_better {
+                best = Some(candidate);
+            }
+            start = end;
+        }
+        best.map(|token| String::from_utf8_lossy(token).into_owned())
+    }
+
+    fn matches_options(&self, request: &Request) -> bool {
+
+        if let Some(third_party) = self.third_party {
+            if third_party != request.is_third_party {
+                return false;
+            }
+        }
+
+        if !self.types.is_empty() && !self.types.contains(&request.kind) {
+            return false;
+        }
+        if self.excluded_types.contains(&request.kind) {
+            return false;
+        }
+
+        if !self.domains.is_empty() || !self.excluded_domains.is_empty() {
+            let source = request.source.as_ref().map(|source| source.as_str()).unwrap_or("");
+            if self.excluded_domains.iter().any(|domain| is_domain_or_parent(source, domain)) {
+                return false;
+            }
+            if !self.domains.is_empty()
+                && !self.domains.iter().any(|domain| is_domain_or_parent(source, domain))
+            {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    fn matches(&self, request: &Request) -> bool {
+
+        if !self.matches_options(request) {
+            return false;
+        }
+
+        let text = if self.is_match_case { request.url.as_str() } else { &request.url_lower };
+        let text = text.as_bytes();
+        let pattern = self.pattern.as_bytes();
+
+        match self.anchor {
+            Anchor::Start => matches_pattern(pattern, text, true, self.is_end_anchored),
+            Anchor::Host => {
+                let (host_start, host_end) = match request.host_range {
+                    Some(range) => range,
+                    None => return false,
+                };
+                (host_start..host_end)
+                    .filter(|&pos| pos == host_start || text[pos - 1] == b'.')
+                    .any(|pos| matches_pattern(pattern, &text[pos..], true, self.is_end_anchored))
+            },
+            Anchor::None => matches_pattern(pattern, text, false, self.is_end_anchored),
+        }
+    }
+}
+
+/// A request as seen by the filters.
+pub struct Request<'a> {
+    url: &'a url::Url,
+    url_lower: String,
+    host_range: Option<(usize, usize)>,
+    source: Option<&'a Host>,
+    is_third_party: bool,
+    kind: ResourceType,
+}
+
+impl<'a> Request<'a> {
+
+    pub fn new(url: &'a url::Url, source: Option<&'a Host>, kind: ResourceType) -> Request<'a> {
+        let target = Host::from_uri(url);
+        let is_third_party = match (source, target.as_ref()) {
+            (Some(source), Some(target)) => !source.is_same_site(target),
+            _ => false,
+        };
+        let host_range = url.host_str().and_then(|host| {
+            let after_scheme = url.as_str().find("://").map(|pos| pos + 3).unwrap_or(0);
+            url.as_str()[after_scheme..].find(host).map(|pos| {
+                let start = after_scheme + pos;
+                (start, start + host.len())
+            })
+        });
+        Request {
+            url,
+            url_lower: url.as_str().to_ascii_lowercase(),
+            host_range,
+            source,
+            is_third_party,
+            kind,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct FilterSet {
+    by_token: collections::HashMap<String, Vec<Filter>>,
+    generic: Vec<Filter>,
+}
+
+impl FilterSet {
+
+    fn insert(&mut self, filter: Filter) {
+        match filter.token() {
+            Some(token) => self.by_token.entry(token).or_insert_with(Vec::new).push(filter),
+            None => self.generic.push(filter),
+        }
+    }
+
+    fn matches(&self, request: &Request) -> bool {
+        if self.generic.iter().any(|filter| filter.matches(request)) {
+            return true;
+        }
+        let mut seen = collections::HashSet::new();
+        request.url_lower
+            .split(|c: char| !c.is_ascii() || !is_token_byte(c as u8))
+            .filter(|token| token.len() >= MIN_TOKEN_LEN && seen.insert(*token))
+            .filter_map(|token| self.by_token.get(token))
+            .any(|filters| filters.iter().any(|filter| filter.matches(request)))
+    }
+}
+
+/// Compiled filters from any number of lists.
+#[derive(Debug, Default)]
+pub struct Matcher {
+    blocking: FilterSet,
+    exceptions: FilterSet,
+    len: usize,
+}
+
+impl Matcher {
+
+    pub fn new() -> Matcher { Matcher::default() }
+
+    /// Adds all supported filters from a list, returning their count.
+    pub fn add_list(&mut self, content: &str) -> usize {
+        let mut count = 0;
+        for filter in content.lines().filter_map(Filter::parse) {
+            if filter.is_exception {
+                self.exceptions.insert(filter);
+            } else {
+                self.blocking.insert(filter);
+            }
+            count += 1;
+        }
+        self.len += count;
+        count
+    }
+
+    pub fn len(&self) -> usize { self.len }
+
+    pub fn is_blocked(&self, request: &Request) -> bool {
+        self.blocking.matches(request) && !self.exceptions.matches(request)
+    }
+}
@@ -0,0 +1,247 @@
+
+use std::collections;
+use std::net;
+
+use ip;
+use Host;
+use ResourceType;
+
+#[derive(Debug)]
+struct Node<T> {
+    value: Option<T>,
+    children: collections::HashMap<String, Node<T>>,
+}
+
+impl<T> Node<T> {
+
+    fn new() -> Node<T> {
+        Node {
+            value: None,
+            children: collections::HashMap::new(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct HostTrie<T> {
+    domains: Node<T>,
+    ips: collections::HashMap<(net::IpAddr, u8), T>,
+}
+
+impl<T> HostTrie<T> {
+
+    pub fn new() -> HostTrie<T> {
+        HostTrie {
+            domains: Node::new(),
+            ips: collections::HashMap::new(),
+        }
+    }
+
+    pub fn get(&self, host: &Host) -> Option<&T> {
+        match *host {
+            Host::Ip(ref range) => self.ips.get(&(range.addr(), range.prefix_len())),
+            Host::Domain(_) => {
+                let mut node = &self.domains;
+                for label in host.as_str().rsplit('.') {
+                    node = node.children.get(label)?;
+                }
+                node.value.as_ref()
+            },
+        }
+    }
+
+    pub fn get_or_insert_with<F>(&mut self, host: &Host, create: F) -> &mut T
+    where F: FnOnce() -> T {
+        match *host {
+            Host::Ip(ref range) =>
+                self.ips.entry((range.addr(), range.prefix_len())).or_insert_with(create),
+            Host::Domain(_) => {
+                let mut node = &mut self.domains;
+                for label in host.as_str().rsplit('.') {
+                    node = {node}.children.entry(label.into()).or_insert_with(Node::new);
+                }
+                if node.value.is_none() {
+                    node.value = Some(create());
+                }
+                node.value.as_mut().expect("inserted trie value")
+            },
+        }
+    }
+
+    pub fn get_mut(&mut self, host: &Host) -> Option<&mut T> {
+        match *host {
+            Host::Ip(ref range) => self.ips.get_mut(&(range.addr(), range.prefix_len())),
+            Host::Domain(_) => {
+                let mut node = &mut self.domains;
+                for label in host.as_str().rsplit('.') {
+                    node = {node}.children.get_mut(label)?;
+                }
+                node.value.as_mut()
+            },
+        }
+    }
+
+    /// Checks the values stored for the host and all of its parents.
+    ///
+    /// For domains the walk follows the same parent chain as
+    /// `Host::to_expanded`, so values stored above the registrable domain
+    /// are never considered. For IP hosts every stored range containing
+    /// the host counts as a parent.
+    pub fn any_along<F>(&self, host: &Host, check: F) -> bool
+    where F: Fn(&T) -> bool {
+        match *host {
+            Host::Ip(ref range) => {
+                if self.ips.is_empty() {
+                    return false;
+                }
+                (0..range.prefix_len() + 1).rev().any(|prefix_len| {
+                    let key = (ip::mask(range.addr(), prefix_len), prefix_len);
+                    self.ips.get(&key).map(|value| check(value)).unwrap_or(false)
+                })
+            },
+            Host::Domain(ref domain) => {
+                let labels = host.as_str().rsplit('.').collect::<Vec<_>>();
+                let min_depth = labels.len() - domain.sub_count();
+                let mut node = &self.domains;
+                for (index, label) in labels.into_iter().enumerate() {
+                    node = match node.children.get(label) {
+                        Some(child) => child,
+                        None => return false,
+                    };
+                    if index + 1 >= min_depth {
+                        if let Some(ref value) = node.value {
+                            if check(value) {
+                                return true;
+                            }
+                        }
+                    }
+                }
+                false
+            },
+        }
+    }
+}
+
+/// The resource types a rule applies to.
+#[derive(Debug, Clone, Default)]
+struct ResourceSet {
+    any: bool,
+    types: Vec<ResourceType>,
+}
+
+impl ResourceSet {
+
+    fn insert(&mut self, kind: Option<ResourceType>) {
+        match kind {
+            None => self.any = true,
+            Some(kind) => if !self.types.contains(&kind) {
+                self.types.push(kind);
+            },
+        }
+    }
+
+    fn remove(&mut self, kind: Option<ResourceType>) {
+        match kind {
+            None => self.any = false,
+            Some(kind) => self.types.retain(|other| *other != kind),
+        }
+    }
+
+    fn matches(&self, kind: ResourceType) -> bool {
+        self.any || self.types.contains(&kind)
+    }
+}
+
+#[derive(Debug)]
+struct SourceRules {
+    always: bool,
+    targets: HostTrie<ResourceSet>,
+    denied: HostTrie<ResourceSet>,
+}
+
+impl SourceRules {
+
+    fn new() -> SourceRules {
+        SourceRules {
+            always: false,
+            targets: HostTrie::new(),
+            denied: HostTrie::new(),
+        }
+    }
+}
+
+/// In-memory view of the `third_party_target` table.
+#[derive(Debug)]
+pub struct RuleIndex {
+    sources: HostTrie<SourceRules>,
+}
+
+impl RuleIndex {
+
+    pub fn new() -> RuleIndex {
+        RuleIndex {
+            sources: HostTrie::new(),
+        }
+    }
+
+    pub fn insert_always_entry(&mut self, source: &Host) {
+        self.sources.get_or_insert_with(source, SourceRules::new).always = true;
+    }
+
+    pub fn insert_entry(&mut self, source: &Host, target: &Host, kind: Option<ResourceType>) {
+        let rules = self.sources.get_or_insert_with(source, SourceRules::new);
+        if let Some(denied) = rules.denied.get_mut(target) {
+            denied.remove(kind);
+        }
+        rules.targets.get_or_insert_with(target, ResourceSet::default).insert(kind);
+    }
+
+    pub fn insert_deny_entry(&mut self, source: &Host, target: &Host, kind: Option<ResourceType>) {
+        let rules = self.sources.get_or_insert_with(source, SourceRules::new);
+        if let Some(allowed) = rules.targets.get_mut(target) {
+            allowed.remove(kind);
+        }
+        rules.denied.get_or_insert_with(target, ResourceSet::default).insert(kind);
+    }
+
+    pub fn remove_always_entry(&mut self, source: &Host) {
+        if let Some(rules) = self.sources.get_mut(source) {
+            rules.always = false;
+        }
+    }
+
+    pub fn remove_entry(&mut self, source: &Host, target: &Host, kind: Option<ResourceType>) {
+        if let Some(rules) = self.sources.get_mut(source) {
+            if let Some(allowed) = rules.targets.get_mut(target) {
+                allowed.remove(kind);
+            }
+        }
+    }
+
+    pub fn remove_deny_entry(&mut self, source: &Host, target: &Host, kind: Option<ResourceType>) {
+        if let Some(rules) = self.sources.get_mut(source) {
+            if let Some(denied) = rules.denied.get_mut(target) {
+                denied.remove(kind);
+            }
+        }
+    }
+
+    pub fn can_request(&self, source: &Host, target: &Host, kind: ResourceType) -> bool {
+
+        let is_denied = self.sources.any_along(source, |rules| {
+            rules.denied.any_along(target, |denied| denied.matches(kind))
+        });
+        if is_denied {
+            return false;
+        }
+
+        let is_allowed = self.sources.get(source)
+            .map(|rules| rules.targets.any_along(target, |allowed| allowed.matches(kind)))
+            .unwrap_or(false);
+        if is_allowed {
+            return true;
+        }
+
+        self.sources.any_along(source, |rules| rules.always)
+    }
+}
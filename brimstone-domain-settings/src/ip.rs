@@ -0,0 +1,118 @@
+
+use std::net;
+
+/// An IP address or CIDR network.
+///
+/// Ranges are normalized to their network address, IPv4-mapped IPv6
+/// addresses to their IPv4 form and single addresses are written without
+/// brackets or prefix length.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct IpRange {
+    addr: net::IpAddr,
+    prefix_len: u8,
+    text: String,
+}
+
+pub fn max_prefix_len(addr: &net::IpAddr) -> u8 {
+    match *addr {
+        net::IpAddr::V4(_) => 32,
+        net::IpAddr::V6(_) => 128,
+    }
+}
+
+/// Clears all bits of the address beyond the prefix length.
+pub fn mask(addr: net::IpAddr, prefix_len: u8) -> net::IpAddr {
+    match addr {
+        net::IpAddr::V4(addr) => {
+            let mask = if prefix_len == 0 { 0 } else { !0u32 << (32 - prefix_len as u32) };
+            net::IpAddr::V4(net::Ipv4Addr::from(u32::from(addr) & mask))
+        },
+        net::IpAddr::V6(addr) => {
+            let mask = if prefix_len == 0 { 0 } else { !0u128 << (128 - prefix_len as u32) };
+            net::IpAddr::V6(net::Ipv6Addr::from(u128::from(addr) & mask))
+        },
+    }
+}
+
+fn unmap(addr: &net::IpAddr) -> Option<net::Ipv4Addr> {
+    match *addr {
+        net::IpAddr::V6(ref addr) => {
+            let segments = addr.segments();
+            if segments[..5].iter().all(|segment| *segment == 0) && segments[5] == 0xffff {
+                let octets = addr.octets();
+                Some(net::Ipv4Addr::new(octets[12], octets[13], octets[14], octets[15]))
+            } else {
+                None
+            }
+        },
+        net::IpAddr::V4(_) => None,
+    }
+}
+
+impl IpRange {
+
+    fn new(addr: net::IpAddr, prefix_len: u8) -> IpRange {
+        let (addr, prefix_len) = match unmap(&addr) {
+            Some(addr) if prefix_len >= 96 => (net::IpAddr::V4(addr), prefix_len - 96),
+            _ => (addr, prefix_len),
+        };
+        let addr = mask(addr, prefix_len);
+        let text =
+            if prefix_len == max_prefix_len(&addr) {
+                addr.to_string()
+            } else {
+                format!("{}/{}", addr, prefix_len)
+            };
+        IpRange { addr, prefix_len, text }
+    }
+
+    /// Parses an address like `10.0.0.1` or `[::1]`, or a network like
+    /// `10.0.0.0/8` or `fd00::/8`.
+    pub fn parse(value: &str) -> Option<IpRange> {
+        let (addr, prefix_len) = match value.find('/') {
+            Some(pos) => (&value[..pos], Some(&value[pos + 1..])),
+            None => (value, None),
+        };
+        let addr =
+            if addr.starts_with('[') && addr.ends_with(']') {
+                &addr[1..addr.len() - 1]
+            } else {
+                addr
+            };
+        let addr: net::IpAddr = match addr.parse() {
+            Ok(addr) => addr,
+            Err(_) => return None,
+        };
+        let max = max_prefix_len(&addr);
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => match prefix_len.parse::<u8>() {
+                Ok(prefix_len) if prefix_len <= max => prefix_len,
+                _ => return None,
+            },
+            None => max,
+        };
+        Some(IpRange::new(addr, prefix_len))
+    }
+
+    /// The network address.
+    pub fn addr(&self) -> net::IpAddr { self.addr }
+
+    pub fn prefix_len(&self) -> u8 { self.prefix_len }
+
+    pub fn is_single(&self) -> bool { self.prefix_len == max_prefix_len(&self.addr) }
+
+    pub fn contains(&self, other: &IpRange) -> bool {
+        max_prefix_len(&self.addr) == max_prefix_len(&other.addr)
+            && other.prefix_len >= self.prefix_len
+            && mask(other.addr, self.prefix_len) == self.addr
+    }
+
+    pub fn as_str(&self) -> &str { &self.text }
+}
+
+impl From<net::IpAddr> for IpRange {
+
+    fn from(addr: net::IpAddr) -> IpRange {
+        IpRange::new(addr, max_prefix_len(&addr))
+    }
+}
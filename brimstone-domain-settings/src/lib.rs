@@ -1,14 +1,42 @@
 
 use std::path;
 use std::cmp;
+use std::cell;
+use std::net;
+use std::io;
+use std::fs;
+use std::collections;
 
 extern crate brimstone_storage as storage;
 
+extern crate idna;
+extern crate serde;
+extern crate serde_json;
 extern crate tendril;
 extern crate url;
 
+#[macro_use]
+extern crate serde_derive;
+
+#[macro_use]
+extern crate lazy_static;
+
 use storage::rusqlite;
 
+mod cookies;
+mod index;
+mod ip;
+mod preferences;
+mod resource;
+pub mod suffix;
+pub mod filter;
+pub mod portable;
+
+pub use cookies::CookiePolicy;
+pub use ip::IpRange;
+pub use preferences::{ SitePreferences, Autoplay };
+pub use resource::ResourceType;
+
 fn init_storage(conn: &mut rusqlite::Connection) -> Result<(), rusqlite::Error> {
     conn.execute("
         CREATE TABLE third_party_target (
@@ -23,76 +51,403 @@ fn init_storage(conn: &mut rusqlite::Connection) -> Result<(), rusqlite::Error>
     Ok(())
 }
 
+fn add_deny_entries(tx: &mut rusqlite::Transaction) -> Result<(), rusqlite::Error> {
+    tx.execute("
+        ALTER TABLE third_party_target
+        ADD COLUMN is_denied INTEGER NOT NULL DEFAULT 0
+    ", &[])?;
+    Ok(())
+}
+
+fn add_resource_types(tx: &mut rusqlite::Transaction) -> Result<(), rusqlite::Error> {
+    tx.execute("
+        ALTER TABLE third_party_target
+        ADD COLUMN resource_type TEXT NOT NULL DEFAULT ''
+    ", &[])?;
+    tx.execute("DROP INDEX idx_third_party_target", &[])?;
+    tx.execute("
+        CREATE UNIQUE INDEX idx_third_party_target
+        ON third_party_target (source_domain, target_domain, resource_type)
+    ", &[])?;
+    Ok(())
+}
+
+fn add_filter_lists(tx: &mut rusqlite::Transaction) -> Result<(), rusqlite::Error> {
+    tx.execute("
+        CREATE TABLE filter_list (
+            name TEXT NOT NULL PRIMARY KEY,
+            content TEXT NOT NULL
+        )
+    ", &[])?;
+    Ok(())
+}
+
+fn normalize_host(value: &str) -> String {
+    IpRange::parse(value)
+        .map(|range| range.as_str().into())
+        .unwrap_or_else(|| value.into())
+}
+
+fn normalize_ip_hosts(tx: &mut rusqlite::Transaction) -> Result<(), rusqlite::Error> {
+    let mut changes = Vec::new();
+    {
+        let mut stmt = tx.prepare("
+            SELECT rowid, source_domain, target_domain
+            FROM third_party_target
+        ")?;
+        let mut rows = stmt.query(&[])?;
+        while let Some(row) = rows.next() {
+            let row = row?;
+            let id: i64 = row.get_checked(0)?;
+            let source: String = row.get_checked(1)?;
+            let target: String = row.get_checked(2)?;
+            let normalized = (normalize_host(&source), normalize_host(&target));
+            if normalized != (source, target) {
+                changes.push((id, normalized.0, normalized.1));
+            }
+        }
+    }
+    for (id, source, target) in changes {
+        tx.execute("
+            UPDATE OR REPLACE third_party_target
+            SET source_domain = ?, target_domain = ?
+            WHERE rowid = ?
+        ", &[&source, &target, &id])?;
+    }
+    Ok(())
+}
+
+fn add_site_preferences(tx: &mut rusqlite::Transaction) -> Result<(), rusqlite::Error> {
+    tx.execute("
+        CREATE TABLE site_preference (
+            host TEXT NOT NULL PRIMARY KEY,
+            javascript INTEGER,
+            images INTEGER,
+            zoom_level REAL,
+            user_agent TEXT,
+            autoplay TEXT
+        )
+    ", &[])?;
+    Ok(())
+}
+
+fn add_cookie_policies(tx: &mut rusqlite::Transaction) -> Result<(), rusqlite::Error> {
+    tx.execute("
+        CREATE TABLE cookie_policy (
+            host TEXT NOT NULL PRIMARY KEY,
+            policy TEXT NOT NULL
+        )
+    ", &[])?;
+    Ok(())
+}
+
+const MIGRATIONS: &[storage::Migration] = &[
+    add_deny_entries,
+    add_resource_types,
+    add_filter_lists,
+    normalize_ip_hosts,
+    add_site_preferences,
+    add_cookie_policies,
+];
+
+fn resource_column(kind: Option<ResourceType>) -> &'static str {
+    kind.map(|kind| kind.as_str()).unwrap_or("")
+}
+
 pub struct Settings {
     storage: storage::Storage,
+    index: cell::RefCell<index::RuleIndex>,
+    filters: cell::RefCell<filter::Matcher>,
+    cookie_policies: cell::RefCell<collections::HashMap<String, CookiePolicy>>,
+    change_callbacks: cell::RefCell<Vec<Box<Fn()>>>,
+}
+
+fn load_filters(storage: &storage::Storage) -> Result<filter::Matcher, storage::Error> {
+    storage.with_connection(|conn| {
+        let mut matcher = filter::Matcher::new();
+        let mut stmt = conn.prepare("SELECT content FROM filter_list")?;
+        let mut rows = stmt.query(&[])?;
+        while let Some(row) = rows.next() {
+            let content: String = row?.get_checked(0)?;
+            matcher.add_list(&content);
+        }
+        Ok(matcher)
+    })
+}
+
+/// Loads the cookie policies by host. The global policy is stored with an
+/// empty host.
+fn load_cookie_policies(storage: &storage::Storage)
+-> Result<collections::HashMap<String, CookiePolicy>, storage::Error> {
+    storage.with_connection(|conn| {
+        let mut policies = collections::HashMap::new();
+        let mut stmt = conn.prepare("SELECT host, policy FROM cookie_policy")?;
+        let mut rows = stmt.query(&[])?;
+        while let Some(row) = rows.next() {
+            let row = row?;
+            let host: String = row.get_checked(0)?;
+            let policy: String = row.get_checked(1)?;
+            if let Some(policy) = CookiePolicy::from_str(&policy) {
+                policies.insert(host, policy);
+            }
+        }
+        Ok(policies)
+    })
+}
+
+fn load_entries(
+    storage: &storage::Storage,
+    source: Option<&Host>,
+) -> Result<Vec<Entry>, storage::Error> {
+    storage.with_connection(|conn| {
+        let mut stmt = conn.prepare("
+            SELECT source_domain, target_domain, is_denied, resource_type
+            FROM third_party_target
+            WHERE ? IS NULL OR source_domain = ?
+            ORDER BY source_domain, target_domain, resource_type
+        ")?;
+        let source = source.map(|source| source.as_str());
+        let mut rows = stmt.query(&[&source, &source])?;
+        let mut entries = Vec::new();
+        while let Some(row) = rows.next() {
+            let row = row?;
+            let source: String = row.get_checked(0)?;
+            let target: String = row.get_checked(1)?;
+            let is_denied: bool = row.get_checked(2)?;
+            let kind: String = row.get_checked(3)?;
+            let kind = match ResourceType::from_str(&kind) {
+                Some(kind) => Some(kind),
+                None if kind.is_empty() => None,
+                None => continue,
+            };
+            let source = Host::parse(&source);
+            entries.push(if target.is_empty() {
+                Entry::Always { source }
+            } else if is_denied {
+                Entry::Deny { source, target: Host::parse(&target), kind }
+            } else {
+                Entry::Allow { source, target: Host::parse(&target), kind }
+            });
+        }
+        Ok(entries)
+    })
+}
+
+fn load_index(storage: &storage::Storage) -> Result<index::RuleIndex, storage::Error> {
+    let mut index = index::RuleIndex::new();
+    for entry in load_entries(storage, None)? {
+        match entry {
+            Entry::Always { ref source } =>
+                index.insert_always_entry(source),
+            Entry::Allow { ref source, ref target, kind } =>
+                index.insert_entry(source, target, kind),
+            Entry::Deny { ref source, ref target, kind } =>
+                index.insert_deny_entry(source, target, kind),
+        }
+    }
+    Ok(index)
+}
+
+fn store_entry(conn: &rusqlite::Connection, entry: &Entry) -> Result<(), rusqlite::Error> {
+    match *entry {
+        Entry::Always { ref source } => {
+            conn.execute("
+                INSERT OR IGNORE
+                INTO third_party_target (source_domain, target_domain)
+                VALUES (?, ?)
+            ", &[&source.as_str(), &""])?;
+        },
+        Entry::Allow { ref source, ref target, kind }
+        | Entry::Deny { ref source, ref target, kind } => {
+            conn.execute("
+                INSERT OR REPLACE
+                INTO third_party_target (source_domain, target_domain, is_denied, resource_type)
+                VALUES (?, ?, ?, ?)
+            ", &[
+                &source.as_str(),
+                &target.as_str(),
+                &entry.is_denied(),
+                &resource_column(kind),
+            ])?;
+        },
+    }
+    Ok(())
+}
+
+/// A stored rule.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Entry {
+    /// Allows the source to request from all targets.
+    Always { source: Host },
+    Allow { source: Host, target: Host, kind: Option<ResourceType> },
+    Deny { source: Host, target: Host, kind: Option<ResourceType> },
+}
+
+impl Entry {
+
+    pub fn source(&self) -> &Host {
+        match *self {
+            Entry::Always { ref source } => source,
+            Entry::Allow { ref source, .. } => source,
+            Entry::Deny { ref source, .. } => source,
+        }
+    }
+
+    pub fn target(&self) -> Option<&Host> {
+        match *self {
+            Entry::Always { .. } => None,
+            Entry::Allow { ref target, .. } => Some(target),
+            Entry::Deny { ref target, .. } => Some(target),
+        }
+    }
+
+    /// The resource type the entry is restricted to, if any.
+    pub fn kind(&self) -> Option<ResourceType> {
+        match *self {
+            Entry::Always { .. } => None,
+            Entry::Allow { kind, .. } => kind,
+            Entry::Deny { kind, .. } => kind,
+        }
+    }
+
+    pub fn is_denied(&self) -> bool {
+        match *self {
+            Entry::Deny { .. } => true,
+            _ => false,
+        }
+    }
 }
 
 impl Settings {
 
-    pub fn open_in_memory() -> Result<Self, storage::Error> {
+    fn from_storage(storage: storage::Storage) -> Result<Self, storage::Error> {
+        let index = load_index(&storage)?;
+        let filters = load_filters(&storage)?;
+        let cookie_policies = load_cookie_policies(&storage)?;
         Ok(Settings {
-            storage: storage::Storage::open_in_memory(init_storage)?,
+            storage,
+            index: cell::RefCell::new(index),
+            filters: cell::RefCell::new(filters),
+            cookie_policies: cell::RefCell::new(cookie_policies),
+            change_callbacks: cell::RefCell::new(Vec::new()),
         })
     }
 
+    /// Registers a callback invoked after every rule change made through
+    /// this instance.
+    pub fn connect_changed<F>(&self, callback: F)
+    where F: Fn() + 'static {
+        self.change_callbacks.borrow_mut().push(Box::new(callback));
+    }
+
+    fn notify_changed(&self) {
+        for callback in self.change_callbacks.borrow().iter() {
+            callback();
+        }
+    }
+
+    /// Rebuilds the rule index from storage, picking up changes made by
+    /// other processes.
+    pub fn reload(&self) -> Result<(), storage::Error> {
+        let index = load_index(&self.storage)?;
+        let filters = load_filters(&self.storage)?;
+        let cookie_policies = load_cookie_policies(&self.storage)?;
+        *self.index.borrow_mut() = index;
+        *self.filters.borrow_mut() = filters;
+        *self.cookie_policies.borrow_mut() = cookie_policies;
+        Ok(())
+    }
+
+    pub fn open_in_memory() -> Result<Self, storage::Error> {
+        Settings::from_storage(storage::Storage::open_in_memory(init_storage, MIGRATIONS)?)
+    }
+
     pub fn open<P>(path: P) -> Result<Self, storage::Error>
     where P: AsRef<path::Path> {
-        Ok(Settings {
-            storage: storage::Storage::open(path, |_conn| Ok(()))?,
-        })
+        Settings::from_storage(storage::Storage::open(path, MIGRATIONS, |_conn| Ok(()))?)
     }
 
     pub fn open_or_create<P>(path: P) -> Result<Self, storage::Error>
     where P: AsRef<path::Path> {
-        Ok(Settings {
-            storage: storage::Storage::open_or_create(
-                path,
-                init_storage,
-                storage::do_nothing,
-            )?,
-        })
+        Settings::from_storage(storage::Storage::open_or_create(
+            path,
+            init_storage,
+            MIGRATIONS,
+            storage::do_nothing,
+        )?)
+    }
+
+    pub fn backup_to<P>(&self, path: P) -> Result<(), storage::Error>
+    where P: AsRef<path::Path> {
+        self.storage.backup_to(path)
     }
 
+    pub fn recovery(&self) -> Option<&storage::Recovery> { self.storage.recovery() }
+
     pub fn insert_always_entry(&self, source: &Host) {
         self.storage.with_transaction(|tx| {
-            tx.execute("
-                INSERT OR IGNORE
-                INTO third_party_target (source_domain, target_domain)
-                VALUES (?, ?)
-            ", &[&source.as_str(), &""])?;
-            Ok(())
+            store_entry(tx, &Entry::Always { source: source.clone() })
         }).unwrap();
+        self.index.borrow_mut().insert_always_entry(source);
+        self.notify_changed();
     }
 
-    pub fn insert_entry(&self, source: &Host, target: &Host) {
+    /// Allows requests from the source to the target, replacing a deny
+    /// entry for the same pair.
+    pub fn insert_entry(&self, source: &Host, target: &Host, kind: Option<ResourceType>) {
         self.storage.with_transaction(|tx| {
-            tx.execute("
-                INSERT OR IGNORE
-                INTO third_party_target (source_domain, target_domain)
-                VALUES (?, ?)
-            ", &[&source.as_str(), &target.as_str()])?;
-            Ok(())
+            store_entry(tx, &Entry::Allow { source: source.clone(), target: target.clone(), kind })
+        }).unwrap();
+        self.index.borrow_mut().insert_entry(source, target, kind);
+        self.notify_changed();
+    }
+
+    /// Denies requests from the source to the target, replacing an allow
+    /// entry for the same pair.
+    pub fn insert_deny_entry(&self, source: &Host, target: &Host, kind: Option<ResourceType>) {
+        self.storage.with_transaction(|tx| {
+            store_entry(tx, &Entry::Deny { source: source.clone(), target: target.clone(), kind })
         }).unwrap();
+        self.index.borrow_mut().insert_deny_entry(source, target, kind);
+        self.notify_changed();
     }
 
     pub fn remove_always_entry(&self, source: &Host) {
         self.storage.with_transaction(|tx| {
             tx.execute("
                 DELETE FROM third_party_target
-                WHERE source_domain = ? AND target_domain = ?
+                WHERE source_domain = ? AND target_domain = ? AND resource_type = ''
             ", &[&source.as_str(), &""])?;
             Ok(())
         }).unwrap();
+        self.index.borrow_mut().remove_always_entry(source);
+        self.notify_changed();
     }
 
-    pub fn remove_entry(&self, source: &Host, target: &Host) {
+    pub fn remove_entry(&self, source: &Host, target: &Host, kind: Option<ResourceType>) {
         self.storage.with_transaction(|tx| {
             tx.execute("
                 DELETE FROM third_party_target
-                WHERE source_domain = ? AND target_domain = ?
-            ", &[&source.as_str(), &target.as_str()])?;
+                WHERE source_domain = ? AND target_domain = ? AND is_denied = 0
+                    AND resource_type = ?
+            ", &[&source.as_str(), &target.as_str(), &resource_column(kind)])?;
             Ok(())
         }).unwrap();
+        self.index.borrow_mut().remove_entry(source, target, kind);
+        self.notify_changed();
+    }
+
+    pub fn remove_deny_entry(&self, source: &Host, target: &Host, kind: Option<ResourceType>) {
+        self.storage.with_transaction(|tx| {
+            tx.execute("
+                DELETE FROM third_party_target
+                WHERE source_domain = ? AND target_domain = ? AND is_denied = 1
+                    AND resource_type = ?
+            ", &[&source.as_str(), &target.as_str(), &resource_column(kind)])?;
+            Ok(())
+        }).unwrap();
+        self.index.borrow_mut().remove_deny_entry(source, target, kind);
+        self.notify_changed();
     }
 
     pub fn has_always_entry(&self, source: &Host) -> bool {
@@ -106,44 +461,317 @@ impl Settings {
         }).unwrap()
     }
 
-    pub fn has_entry(&self, source: &Host, target: &Host) -> bool {
+    pub fn has_entry(&self, source: &Host, target: &Host, kind: Option<ResourceType>) -> bool {
         self.storage.with_connection(|conn| {
             let count: u32 = conn.query_row("
                 SELECT COUNT(target_domain)
                 FROM third_party_target
-                WHERE source_domain LIKE ? AND target_domain LIKE ?
-            ", &[&source.as_str(), &target.as_str()], |row| row.get(0))?;
+                WHERE source_domain LIKE ? AND target_domain LIKE ? AND is_denied = 0
+                    AND resource_type = ?
+            ", &[&source.as_str(), &target.as_str(), &resource_column(kind)], |row| row.get(0))?;
             Ok(count > 0)
         }).unwrap()
     }
 
-    pub fn can_request(&self, source: &Host, target: &Host) -> bool {
+    pub fn has_deny_entry(&self, source: &Host, target: &Host, kind: Option<ResourceType>) -> bool {
+        self.storage.with_connection(|conn| {
+            let count: u32 = conn.query_row("
+                SELECT COUNT(target_domain)
+                FROM third_party_target
+                WHERE source_domain LIKE ? AND target_domain LIKE ? AND is_denied = 1
+                    AND resource_type = ?
+            ", &[&source.as_str(), &target.as_str(), &resource_column(kind)], |row| row.get(0))?;
+            Ok(count > 0)
+        }).unwrap()
+    }
 
-        if self.has_always_entry(source) {
-            return true;
+    pub fn insert(&self, entry: &Entry) {
+        match *entry {
+            Entry::Always { ref source } =>
+                self.insert_always_entry(source),
+            Entry::Allow { ref source, ref target, kind } =>
+                self.insert_entry(source, target, kind),
+            Entry::Deny { ref source, ref target, kind } =>
+                self.insert_deny_entry(source, target, kind),
         }
+    }
 
-        let mut current = source.clone();
-        while let Some(parent) = current.parent() {
-            if self.has_always_entry(&parent) {
-                return true;
-            }
-            current = parent;
+    pub fn remove(&self, entry: &Entry) {
+        match *entry {
+            Entry::Always { ref source } =>
+                self.remove_always_entry(source),
+            Entry::Allow { ref source, ref target, kind } =>
+                self.remove_entry(source, target, kind),
+            Entry::Deny { ref source, ref target, kind } =>
+                self.remove_deny_entry(source, target, kind),
         }
+    }
 
-        if self.has_entry(source, target) {
-            return true;
-        }
+    /// Removes every entry of the source, returning how many were removed.
+    pub fn remove_source(&self, source: &Host) -> usize {
+        let count = self.storage.with_transaction(|tx| {
+            let count = tx.execute("
+                DELETE FROM third_party_target
+                WHERE source_domain = ?
+            ", &[&source.as_str()])?;
+            Ok(count as usize)
+        }).unwrap();
+        *self.index.borrow_mut() = load_index(&self.storage).unwrap();
+        self.notify_changed();
+        count
+    }
+
+    /// All sources having at least one entry, in order.
+    pub fn sources(&self) -> Vec<Host> {
+        self.storage.with_connection(|conn| {
+            let mut stmt = conn.prepare("
+                SELECT DISTINCT source_domain
+                FROM third_party_target
+                ORDER BY source_domain
+            ")?;
+            let mut rows = stmt.query(&[])?;
+            let mut sources = Vec::new();
+            while let Some(row) = rows.next() {
+                let source: String = row?.get_checked(0)?;
+                sources.push(Host::parse(&source));
+            }
+            Ok(sources)
+        }).unwrap()
+    }
+
+    /// All entries, ordered by source, target and resource type.
+    pub fn entries(&self) -> Vec<Entry> {
+        load_entries(&self.storage, None).unwrap()
+    }
+
+    pub fn source_entries(&self, source: &Host) -> Vec<Entry> {
+        load_entries(&self.storage, Some(source)).unwrap()
+    }
+
+    /// Serializes all entries as a `portable` document.
+    pub fn export_rules(&self) -> String {
+        portable::Document::from_entries(&self.entries()).to_string()
+    }
+
+    pub fn export_rules_file<P>(&self, path: P) -> Result<usize, io::Error>
+    where P: AsRef<path::Path> {
+        let entries = self.entries();
+        fs::write(path, portable::Document::from_entries(&entries).to_string())?;
+        Ok(entries.len())
+    }
+
+    /// Stores the rules of a `portable` document in a single transaction.
+    pub fn import_rules(&self, content: &str, mode: portable::Mode)
+    -> Result<portable::Report, portable::Error> {
+        let document = portable::Document::parse(content)?;
+        let existing = match mode {
+            portable::Mode::Merge => self.entries(),
+            portable::Mode::Replace => Vec::new(),
+        };
+        let (entries, report) = document.resolve(&existing);
+        self.storage.with_transaction(|tx| {
+            if mode == portable::Mode::Replace {
+                tx.execute("DELETE FROM third_party_target", &[])?;
+            }
+            for entry in &entries {
+                store_entry(tx, entry)?;
+            }
+            Ok(())
+        }).unwrap();
+        *self.index.borrow_mut() = load_index(&self.storage).unwrap();
+        self.notify_changed();
+        Ok(report)
+    }
+
+    pub fn import_rules_file<P>(&self, path: P, mode: portable::Mode)
+    -> Result<portable::Report, portable::Error>
+    where P: AsRef<path::Path> {
+        let content = fs::read_to_string(path)?;
+        self.import_rules(&content, mode)
+    }
+
+    /// The preferences stored for exactly this host.
+    pub fn site_preferences(&self, host: &Host) -> SitePreferences {
+        self.storage.with_connection(|conn| {
+            let mut stmt = conn.prepare("
+                SELECT javascript, images, zoom_level, user_agent, autoplay
+                FROM site_preference
+                WHERE host = ?
+            ")?;
+            let mut rows = stmt.query(&[&host.as_str()])?;
+            let row = match rows.next() {
+                Some(row) => row?,
+                None => return Ok(SitePreferences::default()),
+            };
+            let autoplay: Option<String> = row.get_checked(4)?;
+            Ok(SitePreferences {
+                javascript: row.get_checked(0)?,
+                images: row.get_checked(1)?,
+                zoom_level: row.get_checked(2)?,
+                user_agent: row.get_checked(3)?,
+                autoplay: autoplay.and_then(|autoplay| Autoplay::from_str(&autoplay)),
+            })
+        }).unwrap()
+    }
 
-        let mut current = target.clone();
-        while let Some(parent) = current.parent() {
-            if self.has_entry(source, &parent) {
-                return true;
+    /// Stores the preferences for the host, removing them when all values
+    /// are unset.
+    pub fn set_site_preferences(&self, host: &Host, preferences: &SitePreferences) {
+        self.storage.with_transaction(|tx| {
+            if preferences.is_empty() {
+                tx.execute("DELETE FROM site_preference WHERE host = ?", &[&host.as_str()])?;
+                return Ok(());
             }
-            current = parent;
+            let autoplay = preferences.autoplay.map(|autoplay| autoplay.as_str());
+            tx.execute("
+                INSERT OR REPLACE
+                INTO site_preference (host, javascript, images, zoom_level, user_agent, autoplay)
+                VALUES (?, ?, ?, ?, ?, ?)
+            ", &[
+                &host.as_str(),
+                &preferences.javascript,
+                &preferences.images,
+                &preferences.zoom_level,
+                &preferences.user_agent,
+                &autoplay,
+            ])?;
+            Ok(())
+        }).unwrap();
+    }
+
+    /// The preferences applying to the host, with unset values inherited
+    /// from its parent domains.
+    pub fn effective_site_preferences(&self, host: &Host) -> SitePreferences {
+        let mut preferences = SitePreferences::default();
+        for host in host.to_expanded() {
+            preferences.inherit_from(&self.site_preferences(&host));
         }
+        preferences
+    }
+
+    fn store_cookie_policy(&self, host: &str, policy: Option<CookiePolicy>) {
+        self.storage.with_transaction(|tx| {
+            match policy {
+                Some(policy) => tx.execute("
+                    INSERT OR REPLACE
+                    INTO cookie_policy (host, policy)
+                    VALUES (?, ?)
+                ", &[&host, &policy.as_str()])?,
+                None => tx.execute("DELETE FROM cookie_policy WHERE host = ?", &[&host])?,
+            };
+            Ok(())
+        }).unwrap();
+        *self.cookie_policies.borrow_mut() = load_cookie_policies(&self.storage).unwrap();
+        self.notify_changed();
+    }
+
+    /// The policy applying to hosts without an override.
+    pub fn cookie_policy(&self) -> CookiePolicy {
+        self.cookie_policies.borrow().get("").cloned().unwrap_or_default()
+    }
+
+    pub fn set_cookie_policy(&self, policy: CookiePolicy) {
+        self.store_cookie_policy("", Some(policy));
+    }
+
+    /// The override stored for exactly this host.
+    pub fn host_cookie_policy(&self, host: &Host) -> Option<CookiePolicy> {
+        self.cookie_policies.borrow().get(host.as_str()).cloned()
+    }
+
+    /// Stores an override for the host, or removes it when `None`.
+    pub fn set_host_cookie_policy(&self, host: &Host, policy: Option<CookiePolicy>) {
+        self.store_cookie_policy(host.as_str(), policy);
+    }
+
+    /// All hosts with an override.
+    pub fn cookie_policy_hosts(&self) -> Vec<(Host, CookiePolicy)> {
+        let mut hosts: Vec<_> = self.cookie_policies.borrow().iter()
+            .filter(|&(host, _)| !host.is_empty())
+            .map(|(host, policy)| (Host::parse(host), *policy))
+            .collect();
+        hosts.sort_by(|a, b| a.0.as_str().cmp(b.0.as_str()));
+        hosts
+    }
+
+    /// The override of the host or its closest parent, falling back to the
+    /// global policy.
+    pub fn effective_cookie_policy(&self, host: &Host) -> CookiePolicy {
+        host.to_expanded().iter()
+            .filter_map(|host| self.host_cookie_policy(host))
+            .next()
+            .unwrap_or_else(|| self.cookie_policy())
+    }
+
+    /// Whether requests from a page on the source host may carry cookies
+    /// for the target host.
+    pub fn sends_cookies(&self, source: &Host, target: &Host) -> bool {
+        self.effective_cookie_policy(source).allows(!source.is_same_site(target))
+    }
+
+    /// Stores a filter list under the given name, replacing an earlier list
+    /// of the same name. Returns the number of supported filters.
+    pub fn import_filter_list(&self, name: &str, content: &str) -> usize {
+        self.storage.with_transaction(|tx| {
+            tx.execute("
+                INSERT OR REPLACE
+                INTO filter_list (name, content)
+                VALUES (?, ?)
+            ", &[&name, &content])?;
+            Ok(())
+        }).unwrap();
+        *self.filters.borrow_mut() = load_filters(&self.storage).unwrap();
+        self.notify_changed();
+        content.lines().filter(|line| filter::Filter::parse(line).is_some()).count()
+    }
 
-        false
+    pub fn import_filter_list_file<P>(&self, name: &str, path: P) -> Result<usize, io::Error>
+    where P: AsRef<path::Path> {
+        let content = fs::read_to_string(path)?;
+        Ok(self.import_filter_list(name, &content))
+    }
+
+    pub fn remove_filter_list(&self, name: &str) {
+        self.storage.with_transaction(|tx| {
+            tx.execute("DELETE FROM filter_list WHERE name = ?", &[&name])?;
+            Ok(())
+        }).unwrap();
+        *self.filters.borrow_mut() = load_filters(&self.storage).unwrap();
+        self.notify_changed();
+    }
+
+    pub fn filter_lists(&self) -> Vec<String> {
+        self.storage.with_connection(|conn| {
+            let mut stmt = conn.prepare("SELECT name FROM filter_list ORDER BY name")?;
+            let mut rows = stmt.query(&[])?;
+            let mut names = Vec::new();
+            while let Some(row) = rows.next() {
+                names.push(row?.get_checked(0)?);
+            }
+            Ok(names)
+        }).unwrap()
+    }
+
+    /// Whether any imported filter list blocks the request. Applies to
+    /// first and third party requests alike.
+    pub fn is_filtered(&self, request: &filter::Request) -> bool {
+        self.filters.borrow().is_blocked(request)
+    }
+
+    /// Decides whether the source may request a resource of the given type
+    /// from the target. Entries without a resource type apply to all types.
+    ///
+    /// In order of precedence:
+    ///
+    /// 1. A deny entry for the source or one of its parents, matching the
+    ///    target or one of its parents, blocks the request.
+    /// 2. An allow entry for the exact source, matching the target or one of
+    ///    its parents, allows the request.
+    /// 3. An always entry for the source or one of its parents allows the
+    ///    request.
+    /// 4. Everything else is blocked.
+    pub fn can_request(&self, source: &Host, target: &Host, kind: ResourceType) -> bool {
+        self.index.borrow().can_request(source, target, kind)
     }
 }
 
@@ -202,22 +830,31 @@ impl PartialOrd for Domain {
 impl Domain {
 
     pub fn new(content: &str) -> Domain {
-        let mut parts = parse_parts(content);
+        let content = content.to_ascii_lowercase();
+        let mut parts = parse_parts(&content);
         assert!(parts.len() > 0);
+        let suffix_len = {
+            let labels = parts.iter()
+                .map(|&(start, end)| &content[start..end])
+                .collect::<Vec<_>>();
+            suffix::active().suffix_len(&labels)
+        };
+        // a host that is a public suffix itself keeps its first label as main
+        let suffix_len = cmp::min(suffix_len, parts.len() - 1);
         let tld =
-            if parts.len() > 2 && looks_like_tld(&parts[parts.len() - 2..]) {
-                let end = parts.pop().unwrap().1;
-                let start = parts.pop().unwrap().0;
+            if suffix_len > 0 {
+                let start = parts[parts.len() - suffix_len].0;
+                let end = parts[parts.len() - 1].1;
+                let keep = parts.len() - suffix_len;
+                parts.truncate(keep);
                 Some((start, end))
-            } else if parts.len() > 1 && looks_like_tld(&parts[parts.len() - 1..]) {
-                Some(parts.pop().unwrap())
             } else {
                 None
             };
         let main = parts.pop().unwrap();
         let sub = parts;
         Domain {
-            content: content.to_ascii_lowercase().into(),
+            content: content.into(),
             tld,
             main,
             sub,
@@ -228,6 +865,8 @@ impl Domain {
     fn main(&self) -> &str { self.resolve(self.main) }
     fn sub(&self) -> Vec<&str> { self.sub.iter().map(|sub| self.resolve(*sub)).collect() }
 
+    fn sub_count(&self) -> usize { self.sub.len() }
+
     fn resolve(&self, (start, end): (usize, usize)) -> &str {
         &self.content[start..end]
     }
@@ -245,6 +884,23 @@ impl Domain {
         }
     }
 
+    pub fn public_suffix(&self) -> Option<&str> { self.tld() }
+
+    /// The domain directly below the public suffix, e.g. `example.co.uk`
+    /// for `www.example.co.uk`.
+    pub fn registrable(&self) -> Domain {
+        Domain {
+            content: self.content.clone(),
+            tld: self.tld,
+            main: self.main,
+            sub: Vec::new(),
+        }
+    }
+
+    pub fn is_same_site(&self, other: &Domain) -> bool {
+        self.tld() == other.tld() && self.main() == other.main()
+    }
+
     pub fn is_parent_of(&self, other: &Domain) -> bool {
         self.tld() == other.tld()
             && self.main() == other.main()
@@ -274,7 +930,7 @@ impl Domain {
 
 #[derive(Debug, Clone, Eq, Ord)]
 pub enum Host {
-    Ip(tendril::StrTendril),
+    Ip(IpRange),
     Domain(Domain),
 }
 
@@ -309,21 +965,51 @@ impl PartialEq for Host {
 
 impl Host {
 
+    /// Values that aren't valid IP addresses or ranges are taken as domains.
     pub fn new(value: &str, is_domain: bool) -> Host {
         if is_domain {
             Host::Domain(Domain::new(value))
         } else {
-            Host::Ip(value.into())
+            match IpRange::parse(value) {
+                Some(range) => Host::Ip(range),
+                None => Host::Domain(Domain::new(value)),
+            }
+        }
+    }
+
+    pub fn parse(value: &str) -> Host {
+        Host::new(value, false)
+    }
+
+    /// Whether the value is an IP address, a CIDR range or a well-formed
+    /// ASCII domain name.
+    pub fn is_valid(value: &str) -> bool {
+        if IpRange::parse(value).is_some() {
+            return true;
         }
+        !value.is_empty()
+            && value.len() <= 253
+            && value.split('.').all(|label| {
+                !label.is_empty()
+                    && label.len() <= 63
+                    && !label.starts_with('-')
+                    && !label.ends_with('-')
+                    && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            })
     }
-    
+
     pub fn from_uri(uri: &url::Url) -> Option<Host> {
         uri.host().and_then(|host| match host {
             url::Host::Domain(domain) => Some(Host::Domain(Domain::new(&domain))),
-            _ => uri.host_str().map(|host| Host::Ip(host.into())),
+            url::Host::Ipv4(addr) => Some(Host::Ip(net::IpAddr::V4(addr).into())),
+            url::Host::Ipv6(addr) => Some(Host::Ip(net::IpAddr::V6(addr).into())),
         })
     }
 
+    pub fn from_uri_str(uri: &str) -> Option<Host> {
+        url::Url::parse(uri).ok().and_then(|uri| Host::from_uri(&uri))
+    }
+
     pub fn parent(&self) -> Option<Host> {
         match *self {
             Host::Ip(_) => None,
@@ -344,10 +1030,22 @@ impl Host {
         || other.is_parent_of(self)
     }
 
+    /// Whether both hosts share a registrable domain, or are the same IP.
+    pub fn is_same_site(&self, other: &Host) -> bool {
+        match (self, other) {
+            (&Host::Domain(ref self_domain), &Host::Domain(ref other_domain)) =>
+                self_domain.is_same_site(other_domain),
+            _ => self == other,
+        }
+    }
+
+    /// For IP hosts, whether this is a range containing the other host.
     pub fn is_parent_of(&self, other: &Host) -> bool {
         match (self, other) {
             (&Host::Domain(ref self_domain), &Host::Domain(ref other_domain)) =>
                 self_domain.is_parent_of(other_domain),
+            (&Host::Ip(ref self_ip), &Host::Ip(ref other_ip)) =>
+                self_ip != other_ip && self_ip.contains(other_ip),
             _ => false,
         }
     }
@@ -369,26 +1067,12 @@ impl Host {
 
     pub fn as_str(&self) -> &str {
         match *self {
-            Host::Ip(ref ip) => ip,
+            Host::Ip(ref ip) => ip.as_str(),
             Host::Domain(ref domain) => domain.as_str(),
         }
     }
 }
 
-fn looks_like_tld(parts: &[(usize, usize)]) -> bool {
-    if parts.len() == 0 {
-        true
-    } else if parts.len() == 1 {
-        parts[0].1 - parts[0].0 <= 3
-    } else if parts.len() == 2 {
-        parts[0].1 - parts[0].0 <= 2
-        &&
-        parts[1].1 - parts[1].0 <= 2
-    } else {
-        false
-    }
-}
-
 fn parse_parts(domain: &str) -> Vec<(usize, usize)> {
     let mut parts = Vec::new();
     let mut offset = 0;
@@ -417,6 +1101,8 @@ mod tests {
         Host::new(host, true)
     }
 
+    const KIND: ResourceType = ResourceType::Other;
+
     #[test]
     fn storage() {
         let domains = Settings::open_in_memory().unwrap();
@@ -424,19 +1110,408 @@ mod tests {
         let always = make_domain("www.always.com");
         domains.insert_always_entry(&always);
         assert!(domains.has_always_entry(&always));
-        assert!(domains.can_request(&always, &always));
-        assert!(domains.can_request(&always, &make_domain("www2.always.com")));
-        assert!(domains.can_request(&always, &make_domain("www.other.co.uk")));
+        assert!(domains.can_request(&always, &always, KIND));
+        assert!(domains.can_request(&always, &make_domain("www2.always.com"), KIND));
+        assert!(domains.can_request(&always, &make_domain("www.other.co.uk"), KIND));
 
         let some_source = make_domain("source.com");
         let some_target = make_domain("target.com");
-        domains.insert_entry(&some_source, &some_target);
-        assert!(domains.has_entry(&some_source, &some_target));
+        domains.insert_entry(&some_source, &some_target, None);
+        assert!(domains.has_entry(&some_source, &some_target, None));
         assert!(!domains.has_always_entry(&some_source));
-        assert!(domains.can_request(&some_source, &some_target));
-        assert!(domains.can_request(&some_source, &make_domain("www.target.com")));
-        assert!(domains.can_request(&some_source, &make_domain("foo.www.target.com")));
-        assert!(!domains.can_request(&some_source, &always));
+        assert!(domains.can_request(&some_source, &some_target, KIND));
+        assert!(domains.can_request(&some_source, &make_domain("www.target.com"), KIND));
+        assert!(domains.can_request(&some_source, &make_domain("foo.www.target.com"), KIND));
+        assert!(!domains.can_request(&some_source, &always, KIND));
+    }
+
+    #[test]
+    fn index() {
+        let domains = Settings::open_in_memory().unwrap();
+
+        domains.insert_always_entry(&make_domain("always.com"));
+        assert!(domains.can_request(&make_domain("www.always.com"), &make_domain("other.com"), KIND));
+        assert!(!domains.can_request(&make_domain("always.com.evil.net"), &make_domain("x.com"), KIND));
+        domains.remove_always_entry(&make_domain("always.com"));
+        assert!(!domains.can_request(&make_domain("www.always.com"), &make_domain("other.com"), KIND));
+
+        let source = make_domain("www.source.com");
+        domains.insert_entry(&source, &make_domain("cdn.target.co.uk"), None);
+        assert!(domains.can_request(&source, &make_domain("a.cdn.target.co.uk"), KIND));
+        assert!(!domains.can_request(&source, &make_domain("target.co.uk"), KIND));
+        assert!(!domains.can_request(&make_domain("source.com"), &make_domain("cdn.target.co.uk"), KIND));
+        domains.remove_entry(&source, &make_domain("cdn.target.co.uk"), None);
+        assert!(!domains.can_request(&source, &make_domain("a.cdn.target.co.uk"), KIND));
+
+        let ip = make_host("http://127.0.0.1");
+        domains.insert_entry(&source, &ip, None);
+        assert!(domains.can_request(&source, &ip, KIND));
+        assert!(!domains.can_request(&source, &make_host("http://127.0.0.2"), KIND));
+    }
+
+    #[test]
+    fn deny_precedence() {
+        let domains = Settings::open_in_memory().unwrap();
+        let news = make_domain("news.example");
+        let www_news = make_domain("www.news.example");
+        let tracker = make_domain("tracker.example");
+        let cdn = make_domain("cdn.example");
+
+        // deny overrides an always entry, also for subdomains of the source
+        domains.insert_always_entry(&news);
+        domains.insert_deny_entry(&news, &tracker, None);
+        assert!(domains.can_request(&news, &cdn, KIND));
+        assert!(!domains.can_request(&news, &tracker, KIND));
+        assert!(!domains.can_request(&news, &make_domain("px.tracker.example"), KIND));
+        assert!(domains.can_request(&www_news, &cdn, KIND));
+        assert!(!domains.can_request(&www_news, &tracker, KIND));
+
+        // deny on a parent source overrides an exact allow entry
+        domains.insert_entry(&www_news, &tracker, None);
+        assert!(!domains.can_request(&www_news, &tracker, KIND));
+
+        // deny on a parent target overrides an allow entry for a subdomain
+        domains.insert_entry(&news, &make_domain("px.tracker.example"), None);
+        assert!(domains.has_deny_entry(&news, &tracker, None));
+        assert!(!domains.can_request(&news, &make_domain("px.tracker.example"), KIND));
+
+        // allowing the same pair replaces the deny entry
+        domains.insert_entry(&news, &tracker, None);
+        assert!(domains.has_entry(&news, &tracker, None));
+        assert!(!domains.has_deny_entry(&news, &tracker, None));
+        assert!(domains.can_request(&news, &tracker, KIND));
+
+        // and denying it again replaces the allow entry
+        domains.insert_deny_entry(&news, &tracker, None);
+        assert!(!domains.has_entry(&news, &tracker, None));
+        assert!(domains.has_deny_entry(&news, &tracker, None));
+        assert!(!domains.can_request(&news, &tracker, KIND));
+
+        domains.remove_deny_entry(&news, &tracker, None);
+        assert!(domains.can_request(&news, &tracker, KIND));
+        assert!(domains.can_request(&www_news, &tracker, KIND));
+    }
+
+    #[test]
+    fn resource_types() {
+        let domains = Settings::open_in_memory().unwrap();
+        let source = make_domain("source.com");
+        let cdn = make_domain("cdn.example");
+
+        domains.insert_entry(&source, &cdn, Some(ResourceType::Image));
+        domains.insert_entry(&source, &cdn, Some(ResourceType::Font));
+        assert!(domains.has_entry(&source, &cdn, Some(ResourceType::Image)));
+        assert!(!domains.has_entry(&source, &cdn, None));
+        assert!(domains.can_request(&source, &cdn, ResourceType::Image));
+        assert!(domains.can_request(&source, &make_domain("img.cdn.example"), ResourceType::Font));
+        assert!(!domains.can_request(&source, &cdn, ResourceType::Script));
+
+        // an untyped entry covers all types, a typed deny entry carves one out
+        domains.insert_entry(&source, &cdn, None);
+        domains.insert_deny_entry(&source, &cdn, Some(ResourceType::Script));
+        assert!(domains.can_request(&source, &cdn, ResourceType::Xhr));
+        assert!(!domains.can_request(&source, &cdn, ResourceType::Script));
+
+        // removing a typed entry leaves the others in place
+        domains.remove_entry(&source, &cdn, None);
+        domains.remove_entry(&source, &cdn, Some(ResourceType::Font));
+        assert!(domains.can_request(&source, &cdn, ResourceType::Image));
+        assert!(!domains.can_request(&source, &cdn, ResourceType::Font));
+        assert!(!domains.can_request(&source, &cdn, ResourceType::Xhr));
+    }
+
+    #[test]
+    fn entries() {
+        let domains = Settings::open_in_memory().unwrap();
+        let news = make_domain("news.example");
+        let blog = make_domain("blog.example");
+        let cdn = make_domain("cdn.example");
+        let tracker = make_domain("tracker.example");
+
+        domains.insert(&Entry::Always { source: blog.clone() });
+        domains.insert(&Entry::Allow { source: news.clone(), target: cdn.clone(), kind: None });
+        domains.insert(&Entry::Deny {
+            source: news.clone(),
+            target: tracker.clone(),
+            kind: Some(ResourceType::Script),
+        });
+        assert_eq!(domains.sources(), vec![blog.clone(), news.clone()]);
+        assert_eq!(domains.entries().len(), 3);
+        assert_eq!(domains.source_entries(&news), vec![
+            Entry::Allow { source: news.clone(), target: cdn.clone(), kind: None },
+            Entry::Deny {
+                source: news.clone(),
+                target: tracker.clone(),
+                kind: Some(ResourceType::Script),
+            },
+        ]);
+
+        domains.remove(&Entry::Allow { source: news.clone(), target: cdn.clone(), kind: None });
+        assert!(!domains.can_request(&news, &cdn, KIND));
+        assert_eq!(domains.source_entries(&news).len(), 1);
+
+        assert_eq!(domains.remove_source(&news), 1);
+        assert_eq!(domains.sources(), vec![blog.clone()]);
+        assert!(!domains.can_request(&news, &tracker, ResourceType::Script));
+        assert!(domains.can_request(&blog, &tracker, ResourceType::Script));
+    }
+
+    #[test]
+    fn rules_export_import() {
+        let domains = Settings::open_in_memory().unwrap();
+        let news = make_domain("news.example");
+        let cdn = make_domain("cdn.example");
+        domains.insert_always_entry(&make_domain("blog.example"));
+        domains.insert_entry(&news, &cdn, Some(ResourceType::Image));
+        domains.insert_deny_entry(&news, &make_domain("ads.example"), None);
+
+        let exported = domains.export_rules();
+        let copy = Settings::open_in_memory().unwrap();
+        let report = copy.import_rules(&exported, portable::Mode::Merge).unwrap();
+        assert_eq!(report.added, 3);
+        assert_eq!(copy.entries(), domains.entries());
+        assert!(copy.can_request(&news, &cdn, ResourceType::Image));
+
+        // importing again changes nothing
+        let report = copy.import_rules(&exported, portable::Mode::Merge).unwrap();
+        assert_eq!((report.added, report.unchanged), (0, 3));
+
+        let document = r#"{
+            "version": 1,
+            "rules": [
+                { "source": "news.example", "target": "cdn.example", "type": "image", "deny": true },
+                { "source": "news.example", "target": "fonts.example", "type": "font" },
+                { "source": "bad host", "target": "cdn.example" },
+                { "source": "news.example", "target": "cdn.example", "type": "sound" },
+                { "source": "news.example", "deny": true }
+            ]
+        }"#;
+        let report = copy.import_rules(document, portable::Mode::Merge).unwrap();
+        assert_eq!(report.added, 1);
+        assert_eq!(report.conflicts.len(), 1);
+        assert!(!report.conflicts[0].existing.is_denied());
+        assert_eq!(report.invalid.iter().map(|invalid| invalid.0).collect::<Vec<_>>(), vec![2, 3, 4]);
+        assert!(copy.can_request(&news, &cdn, ResourceType::Image));
+
+        let report = copy.import_rules(document, portable::Mode::Replace).unwrap();
+        assert_eq!((report.added, report.conflicts.len()), (2, 0));
+        assert!(!copy.can_request(&news, &cdn, ResourceType::Image));
+        assert!(!copy.has_always_entry(&make_domain("blog.example")));
+
+        match copy.import_rules(r#"{ "version": 2, "rules": [] }"#, portable::Mode::Merge) {
+            Err(portable::Error::UnsupportedVersion(2)) => (),
+            other => panic!("unexpected import result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn ip_ranges() {
+        let parse = |value| IpRange::parse(value).map(|range| range.as_str().to_string());
+        assert_eq!(parse("127.0.0.1"), Some("127.0.0.1".into()));
+        assert_eq!(parse("[::1]"), Some("::1".into()));
+        assert_eq!(parse("::ffff:192.168.0.1"), Some("192.168.0.1".into()));
+        assert_eq!(parse("10.1.2.3/8"), Some("10.0.0.0/8".into()));
+        assert_eq!(parse("[fd12:3456::1]/16"), Some("fd12::/16".into()));
+        assert_eq!(parse("0.0.0.0/0"), Some("0.0.0.0/0".into()));
+        assert_eq!(parse("10.0.0.0/33"), None);
+        assert_eq!(parse("10.0.0.0/"), None);
+        assert_eq!(parse("example.com"), None);
+
+        assert_eq!(make_host("http://[::1]/"), Host::parse("::1"));
+        assert_eq!(make_host("http://[::ffff:7f00:1]/"), make_host("http://127.0.0.1/"));
+
+        let network = Host::parse("10.0.0.0/8");
+        assert_eq!(Host::parse("::ffff:a00:0/104"), network);
+        assert!(network.is_parent_of(&make_host("http://10.20.30.40")));
+        assert!(network.is_related_to(&Host::parse("10.1.0.0/16")));
+        assert!(!network.is_parent_of(&make_host("http://11.0.0.1")));
+        assert!(!network.is_parent_of(&make_host("http://[::1]")));
+    }
+
+    #[test]
+    fn ip_range_rules() {
+        let domains = Settings::open_in_memory().unwrap();
+        let source = make_domain("intranet.example");
+
+        domains.insert_entry(&source, &Host::parse("10.0.0.0/8"), None);
+        domains.insert_deny_entry(&source, &Host::parse("10.66.0.0/16"), None);
+        domains.insert_entry(&source, &Host::parse("fd00::/8"), Some(ResourceType::Xhr));
+        assert!(domains.can_request(&source, &make_host("http://10.1.2.3"), KIND));
+        assert!(!domains.can_request(&source, &make_host("http://10.66.2.3"), KIND));
+        assert!(!domains.can_request(&source, &make_host("http://192.168.0.1"), KIND));
+        assert!(domains.can_request(&source, &make_host("http://[fd12::1]"), ResourceType::Xhr));
+        assert!(!domains.can_request(&source, &make_host("http://[fd12::1]"), ResourceType::Script));
+        assert!(!domains.can_request(&source, &make_host("http://[fe80::1]"), ResourceType::Xhr));
+
+        // ranges work as sources too
+        domains.insert_always_entry(&Host::parse("192.168.0.0/16"));
+        assert!(domains.can_request(&make_host("http://192.168.1.1"), &source, KIND));
+        assert!(!domains.can_request(&make_host("http://192.169.1.1"), &source, KIND));
+
+        domains.remove_entry(&source, &Host::parse("10.0.0.0/8"), None);
+        assert!(!domains.can_request(&source, &make_host("http://10.1.2.3"), KIND));
+    }
+
+    #[test]
+    fn site_preferences() {
+        let domains = Settings::open_in_memory().unwrap();
+        let example = make_domain("example.com");
+        let www = make_domain("www.example.com");
+
+        assert!(domains.effective_site_preferences(&www).is_empty());
+
+        domains.set_site_preferences(&example, &SitePreferences {
+            javascript: Some(false),
+            zoom_level: Some(1.5),
+            autoplay: Some(Autoplay::RequireGesture),
+            .. SitePreferences::default()
+        });
+        domains.set_site_preferences(&www, &SitePreferences {
+            javascript: Some(true),
+            user_agent: Some("Test Agent".into()),
+            .. SitePreferences::default()
+        });
+        assert_eq!(domains.site_preferences(&www).zoom_level, None);
+        assert_eq!(domains.effective_site_preferences(&www), SitePreferences {
+            javascript: Some(true),
+            images: None,
+            zoom_level: Some(1.5),
+            user_agent: Some("Test Agent".into()),
+            autoplay: Some(Autoplay::RequireGesture),
+        });
+        let cdn = make_domain("cdn.example.com");
+        assert_eq!(domains.effective_site_preferences(&cdn).javascript, Some(false));
+        assert!(domains.effective_site_preferences(&make_domain("example.org")).is_empty());
+
+        // storing empty preferences removes them
+        domains.set_site_preferences(&example, &SitePreferences::default());
+        assert_eq!(domains.effective_site_preferences(&www).zoom_level, None);
+    }
+
+    #[test]
+    fn cookie_policies() {
+        let domains = Settings::open_in_memory().unwrap();
+        let example = make_domain("example.com");
+        let www = make_domain("www.example.com");
+        let cdn = make_domain("cdn.example.com");
+        let tracker = make_domain("tracker.example");
+
+        assert_eq!(domains.cookie_policy(), CookiePolicy::NoThirdParty);
+        assert!(domains.sends_cookies(&www, &cdn));
+        assert!(!domains.sends_cookies(&www, &tracker));
+
+        domains.set_cookie_policy(CookiePolicy::Always);
+        assert!(domains.sends_cookies(&www, &tracker));
+
+        domains.set_host_cookie_policy(&example, Some(CookiePolicy::Never));
+        assert_eq!(domains.host_cookie_policy(&www), None);
+        assert_eq!(domains.effective_cookie_policy(&www), CookiePolicy::Never);
+        assert!(!domains.sends_cookies(&www, &cdn));
+        assert!(domains.sends_cookies(&tracker, &www));
+
+        domains.set_host_cookie_policy(&www, Some(CookiePolicy::NoThirdParty));
+        assert!(domains.sends_cookies(&www, &cdn));
+        assert!(!domains.sends_cookies(&www, &tracker));
+        assert_eq!(domains.cookie_policy_hosts(), vec![
+            (example.clone(), CookiePolicy::Never),
+            (www.clone(), CookiePolicy::NoThirdParty),
+        ]);
+
+        domains.set_host_cookie_policy(&example, None);
+        domains.set_host_cookie_policy(&www, None);
+        assert_eq!(domains.effective_cookie_policy(&www), CookiePolicy::Always);
+        assert!(domains.cookie_policy_hosts().is_empty());
+    }
+
+    #[test]
+    fn host_valid() {
+        assert!(Host::is_valid("www.example.com"));
+        assert!(Host::is_valid("localhost"));
+        assert!(Host::is_valid("127.0.0.1"));
+        assert!(Host::is_valid("[::1]"));
+        assert!(Host::is_valid("10.0.0.0/8"));
+        assert!(!Host::is_valid(""));
+        assert!(!Host::is_valid("example..com"));
+        assert!(!Host::is_valid("-example.com"));
+        assert!(!Host::is_valid("example.com/path"));
+        assert!(!Host::is_valid("exa mple.com"));
+    }
+
+    #[test]
+    fn resource_classify() {
+        let classify = ResourceType::classify;
+        assert_eq!(classify(None, Some("XMLHttpRequest"), "/api"), ResourceType::Xhr);
+        assert_eq!(classify(Some("text/html,application/xhtml+xml"), None, "/"), ResourceType::Frame);
+        assert_eq!(classify(Some("text/css,*/*;q=0.1"), None, "/a"), ResourceType::Stylesheet);
+        assert_eq!(classify(Some("image/webp,image/*;q=0.8"), None, "/a"), ResourceType::Image);
+        assert_eq!(classify(Some("*/*"), None, "/lib/app.min.JS"), ResourceType::Script);
+        assert_eq!(classify(Some("*/*"), None, "/fonts/sans.woff2"), ResourceType::Font);
+        assert_eq!(classify(None, None, "/dir.d/file"), ResourceType::Other);
+        assert_eq!(ResourceType::from_str("font"), Some(ResourceType::Font));
+        assert_eq!(ResourceType::from_str(""), None);
+    }
+
+    #[test]
+    fn index_loaded_from_storage() {
+        let path = ::std::env::temp_dir()
+            .join(format!("brimstone-domain-settings-test-{}.sqlite", ::std::process::id()));
+        let _ = ::std::fs::remove_file(&path);
+        {
+            let domains = Settings::open_or_create(&path).unwrap();
+            domains.insert_always_entry(&make_domain("always.com"));
+            domains.insert_entry(&make_domain("source.com"), &make_domain("target.com"), None);
+            domains.insert_entry(&make_domain("source.com"), &make_host("http://[::1]"), None);
+            domains.insert_deny_entry(&make_domain("always.com"), &make_domain("tracker.com"), None);
+            domains.insert_entry(&make_domain("source.com"), &make_domain("cdn.com"), Some(ResourceType::Image));
+        }
+        let domains = Settings::open(&path).unwrap();
+        assert!(domains.can_request(&make_domain("www.always.com"), &make_domain("x.com"), KIND));
+        assert!(domains.can_request(&make_domain("source.com"), &make_domain("www.target.com"), KIND));
+        assert!(domains.can_request(&make_domain("source.com"), &make_host("http://[::1]"), KIND));
+        assert!(!domains.can_request(&make_domain("source.com"), &make_domain("x.com"), KIND));
+        assert!(!domains.can_request(&make_domain("www.always.com"), &make_domain("tracker.com"), KIND));
+        assert!(domains.can_request(&make_domain("source.com"), &make_domain("cdn.com"), ResourceType::Image));
+        assert!(!domains.can_request(&make_domain("source.com"), &make_domain("cdn.com"), ResourceType::Script));
+        ::std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn reload() {
+        let path = ::std::env::temp_dir()
+            .join(format!("brimstone-domain-settings-reload-{}.sqlite", ::std::process::id()));
+        let _ = ::std::fs::remove_file(&path);
+        let writer = Settings::open_or_create(&path).unwrap();
+        let reader = Settings::open(&path).unwrap();
+
+        let changes = ::std::rc::Rc::new(::std::cell::Cell::new(0));
+        writer.connect_changed({
+            let changes = changes.clone();
+            move || changes.set(changes.get() + 1)
+        });
+
+        let source = make_domain("source.com");
+        let target = make_domain("target.com");
+        writer.insert_entry(&source, &target, None);
+        assert_eq!(changes.get(), 1);
+        assert!(!reader.can_request(&source, &target, KIND));
+        reader.reload().unwrap();
+        assert!(reader.can_request(&source, &target, KIND));
+
+        writer.remove_entry(&source, &target, None);
+        assert_eq!(changes.get(), 2);
+        reader.reload().unwrap();
+        assert!(!reader.can_request(&source, &target, KIND));
+
+        drop(reader);
+        drop(writer);
+        ::std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn host_parse_stored() {
+        assert!(Host::parse("www.example.com").is_domain());
+        assert!(!Host::parse("127.0.0.1").is_domain());
+        assert!(!Host::parse("[::1]").is_domain());
     }
 
     #[test]
@@ -486,12 +1561,76 @@ mod tests {
             Host::new("example.co.uk", true),
         ]);
 
+        // unlisted suffixes use the last label, which isn't a site of its own
         let host = make_host("http://www.localhost");
         let expanded = host.to_expanded();
         assert_eq!(&expanded, &[
             Host::new("www.localhost", true),
-            Host::new("localhost", true),
         ]);
+
+        let host = make_host("http://a.b.foo.github.io");
+        let expanded = host.to_expanded();
+        assert_eq!(&expanded, &[
+            Host::new("a.b.foo.github.io", true),
+            Host::new("b.foo.github.io", true),
+            Host::new("foo.github.io", true),
+        ]);
+    }
+
+    #[test]
+    fn public_suffix() {
+        let split = |name: &str| {
+            let domain = Domain::new(name);
+            (domain.tld().map(String::from), String::from(domain.main()), domain.sub().len())
+        };
+        let some = |tld: &str, main: &str, sub| (Some(tld.into()), main.into(), sub);
+
+        assert_eq!(split("foo.github.io"), some("github.io", "foo", 0));
+        assert_eq!(split("a.b.io"), some("io", "b", 1));
+        assert_eq!(split("www.example.museum"), some("museum", "example", 1));
+        assert_eq!(split("shop.example.online"), some("online", "example", 1));
+        assert_eq!(split("WWW.Example.CO.UK"), some("co.uk", "example", 1));
+        assert_eq!(split("www.example.xn--55qx5d.cn"), some("xn--55qx5d.cn", "example", 1));
+
+        // wildcard and exception rules: *.ck and !www.ck
+        assert_eq!(split("a.foo.ck"), some("foo.ck", "a", 0));
+        assert_eq!(split("a.www.ck"), some("ck", "www", 1));
+
+        // hosts not covered by the list use the last label as suffix
+        assert_eq!(split("a.b.unlisted"), some("unlisted", "b", 1));
+        // a public suffix on its own keeps its first label as main
+        assert_eq!(split("co.uk"), some("uk", "co", 0));
+        assert_eq!(split("localhost"), (None, "localhost".into(), 0));
+    }
+
+    #[test]
+    fn same_site() {
+        assert!(make_domain("cdn.example.co.uk").is_same_site(&make_domain("www.example.co.uk")));
+        assert!(!make_domain("foo.github.io").is_same_site(&make_domain("bar.github.io")));
+        assert!(!make_domain("example.com").is_same_site(&make_domain("example.org")));
+        assert!(make_host("http://127.0.0.1").is_same_site(&make_host("http://127.0.0.1")));
+        assert!(!make_host("http://127.0.0.1").is_same_site(&make_host("http://127.0.0.2")));
+        assert_eq!(
+            Domain::new("a.b.example.co.uk").registrable(),
+            Domain::new("example.co.uk"),
+        );
+    }
+
+    #[test]
+    fn suffix_list_parse() {
+        let list = suffix::SuffixList::parse("
+            // comment
+            com
+            *.example.com
+            !www.example.com
+            公司.cn
+        ");
+        assert_eq!(list.len(), 4);
+        assert_eq!(list.suffix_len(&["a", "b", "com"]), 1);
+        assert_eq!(list.suffix_len(&["a", "b", "example", "com"]), 3);
+        assert_eq!(list.suffix_len(&["a", "www", "example", "com"]), 2);
+        assert_eq!(list.suffix_len(&["a", "org"]), 1);
+        assert_eq!(list.suffix_len(&["a", "b", "xn--55qx5d", "cn"]), 2);
     }
 
     #[test]
@@ -508,7 +1647,7 @@ mod tests {
         let host = make_host("http://127.0.0.1");
         match host {
             Host::Ip(ref full) => {
-                assert_eq!(full.as_ref(), "127.0.0.1");
+                assert_eq!(full.as_str(), "127.0.0.1");
             },
             other => panic!("wrong host: {:?}", other),
         }
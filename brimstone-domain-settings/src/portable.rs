@@ -0,0 +1,212 @@
+
+//! Portable JSON representation of the stored rules.
+//!
+//! A document looks like this:
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "rules": [
+//!     { "source": "news.example" },
+//!     { "source": "news.example", "target": "cdn.example" },
+//!     { "source": "news.example", "target": "ads.example", "deny": true },
+//!     { "source": "blog.example", "target": "fonts.example", "type": "font" }
+//!   ]
+//! }
+//! ```
+//!
+//! A rule without a `target` allows all requests from the source. `type`
+//! restricts a rule to one of the resource types named by
+//! `ResourceType::as_str` and `deny` turns it into a deny entry. Both
+//! require a `target`.
+
+use std::fmt;
+use std::io;
+
+use serde_json;
+
+use Entry;
+use Host;
+use ResourceType;
+
+pub const VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Document {
+    pub version: u32,
+    pub rules: Vec<Rule>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rule {
+    pub source: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    #[serde(default, rename = "type", skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub deny: bool,
+}
+
+fn is_false(value: &bool) -> bool { !*value }
+
+fn parse_host(value: &str) -> Result<Host, String> {
+    let value = value.trim().to_lowercase();
+    if Host::is_valid(&value) {
+        Ok(Host::parse(&value))
+    } else {
+        Err(format!("invalid host '{}'", value))
+    }
+}
+
+impl Rule {
+
+    pub fn from_entry(entry: &Entry) -> Rule {
+        Rule {
+            source: entry.source().as_str().into(),
+            target: entry.target().map(|target| target.as_str().into()),
+            kind: entry.kind().map(|kind| kind.as_str().into()),
+            deny: entry.is_denied(),
+        }
+    }
+
+    /// Validates the rule, returning the reason when it can't be stored.
+    pub fn to_entry(&self) -> Result<Entry, String> {
+        let source = parse_host(&self.source)?;
+        let target = match self.target {
+            Some(ref target) => parse_host(target)?,
+            None => {
+                if self.kind.is_some() || self.deny {
+                    return Err("'type' and 'deny' require a target".into());
+                }
+                return Ok(Entry::Always { source });
+            },
+        };
+        let kind = match self.kind {
+            Some(ref kind) => match ResourceType::from_str(kind) {
+                Some(kind) => Some(kind),
+                None => return Err(format!("unknown resource type '{}'", kind)),
+            },
+            None => None,
+        };
+        Ok(if self.deny {
+            Entry::Deny { source, target, kind }
+        } else {
+            Entry::Allow { source, target, kind }
+        })
+    }
+}
+
+impl Document {
+
+    pub fn from_entries(entries: &[Entry]) -> Document {
+        Document {
+            version: VERSION,
+            rules: entries.iter().map(Rule::from_entry).collect(),
+        }
+    }
+
+    pub fn parse(content: &str) -> Result<Document, Error> {
+        let document: Document = serde_json::from_str(content)?;
+        if document.version != VERSION {
+            return Err(Error::UnsupportedVersion(document.version));
+        }
+        Ok(document)
+    }
+
+    pub fn to_string(&self) -> String {
+        serde_json::to_string_pretty(self).expect("domain rules serialization")
+    }
+
+    /// Determines the entries to store on top of the existing ones.
+    ///
+    /// Rules contradicting an existing entry or an earlier rule for the
+    /// same source, target and resource type are reported as conflicts and
+    /// skipped, as are invalid rules.
+    pub fn resolve(&self, existing: &[Entry]) -> (Vec<Entry>, Report) {
+        let mut report = Report::default();
+        let mut entries: Vec<Entry> = Vec::new();
+        for (index, rule) in self.rules.iter().enumerate() {
+            let entry = match rule.to_entry() {
+                Ok(entry) => entry,
+                Err(reason) => {
+                    report.invalid.push((index, reason));
+                    continue;
+                },
+            };
+            let previous = existing.iter()
+                .chain(entries.iter())
+                .find(|other| is_same_key(other, &entry))
+                .cloned();
+            match previous {
+                Some(ref previous) if previous == &entry => report.unchanged += 1,
+                Some(previous) => report.conflicts.push(Conflict {
+                    existing: previous,
+                    imported: entry,
+                }),
+                None => {
+                    entries.push(entry);
+                    report.added += 1;
+                },
+            }
+        }
+        (entries, report)
+    }
+}
+
+fn is_same_key(a: &Entry, b: &Entry) -> bool {
+    a.source() == b.source() && a.target() == b.target() && a.kind() == b.kind()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Keeps existing entries and adds the imported ones.
+    Merge,
+    /// Removes all existing entries before importing.
+    Replace,
+}
+
+#[derive(Debug, Clone)]
+pub struct Conflict {
+    pub existing: Entry,
+    pub imported: Entry,
+}
+
+/// Outcome of an import.
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    pub added: usize,
+    pub unchanged: usize,
+    pub conflicts: Vec<Conflict>,
+    /// Position in the document and reason of every skipped rule.
+    pub invalid: Vec<(usize, String)>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Json(serde_json::Error),
+    UnsupportedVersion(u32),
+}
+
+impl From<io::Error> for Error {
+
+    fn from(error: io::Error) -> Error { Error::Io(error) }
+}
+
+impl From<serde_json::Error> for Error {
+
+    fn from(error: serde_json::Error) -> Error { Error::Json(error) }
+}
+
+impl fmt::Display for Error {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref error) => write!(f, "{}", error),
+            Error::Json(ref error) => write!(f, "{}", error),
+            Error::UnsupportedVersion(version) =>
+                write!(f, "unsupported document version {}", version),
+        }
+    }
+}
@@ -0,0 +1,62 @@
+
+/// Whether media may start playing without user interaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Autoplay {
+    Allow,
+    RequireGesture,
+}
+
+impl Autoplay {
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Autoplay::Allow => "allow",
+            Autoplay::RequireGesture => "require-gesture",
+        }
+    }
+
+    pub fn from_str(value: &str) -> Option<Autoplay> {
+        match value {
+            "allow" => Some(Autoplay::Allow),
+            "require-gesture" => Some(Autoplay::RequireGesture),
+            _ => None,
+        }
+    }
+}
+
+/// Browser settings for a host. Unset values are inherited from the
+/// parent domains, and fall back to the browser defaults.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SitePreferences {
+    pub javascript: Option<bool>,
+    pub images: Option<bool>,
+    pub zoom_level: Option<f64>,
+    pub user_agent: Option<String>,
+    pub autoplay: Option<Autoplay>,
+}
+
+impl SitePreferences {
+
+    pub fn is_empty(&self) -> bool {
+        self == &SitePreferences::default()
+    }
+
+    /// Fills all unset values from the parent's preferences.
+    pub fn inherit_from(&mut self, parent: &SitePreferences) {
+        if self.javascript.is_none() {
+            self.javascript = parent.javascript;
+        }
+        if self.images.is_none() {
+            self.images = parent.images;
+        }
+        if self.zoom_level.is_none() {
+            self.zoom_level = parent.zoom_level;
+        }
+        if self.user_agent.is_none() {
+            self.user_agent = parent.user_agent.clone();
+        }
+        if self.autoplay.is_none() {
+            self.autoplay = parent.autoplay;
+        }
+    }
+}
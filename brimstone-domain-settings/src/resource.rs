@@ -0,0 +1,120 @@
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ResourceType {
+    Frame,
+    Script,
+    Stylesheet,
+    Image,
+    Font,
+    Media,
+    Xhr,
+    Other,
+}
+
+const EXTENSIONS: &[(&str, ResourceType)] = &[
+    ("js", ResourceType::Script),
+    ("mjs", ResourceType::Script),
+    ("css", ResourceType::Stylesheet),
+    ("png", ResourceType::Image),
+    ("jpg", ResourceType::Image),
+    ("jpeg", ResourceType::Image),
+    ("gif", ResourceType::Image),
+    ("svg", ResourceType::Image),
+    ("webp", ResourceType::Image),
+    ("ico", ResourceType::Image),
+    ("woff", ResourceType::Font),
+    ("woff2", ResourceType::Font),
+    ("ttf", ResourceType::Font),
+    ("otf", ResourceType::Font),
+    ("eot", ResourceType::Font),
+    ("mp3", ResourceType::Media),
+    ("mp4", ResourceType::Media),
+    ("ogg", ResourceType::Media),
+    ("webm", ResourceType::Media),
+    ("json", ResourceType::Xhr),
+];
+
+impl ResourceType {
+
+    pub fn all() -> &'static [ResourceType] {
+        &[
+            ResourceType::Frame,
+            ResourceType::Script,
+            ResourceType::Stylesheet,
+            ResourceType::Image,
+            ResourceType::Font,
+            ResourceType::Media,
+            ResourceType::Xhr,
+            ResourceType::Other,
+        ]
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            ResourceType::Frame => "frame",
+            ResourceType::Script => "script",
+            ResourceType::Stylesheet => "stylesheet",
+            ResourceType::Image => "image",
+            ResourceType::Font => "font",
+            ResourceType::Media => "media",
+            ResourceType::Xhr => "xhr",
+            ResourceType::Other => "other",
+        }
+    }
+
+    pub fn from_str(value: &str) -> Option<ResourceType> {
+        ResourceType::all().iter().cloned().find(|kind| kind.as_str() == value)
+    }
+
+    /// Guesses the type of a subresource request.
+    ///
+    /// WebKit doesn't tell the web extension what a request is for, so this
+    /// looks at the `Accept` and `X-Requested-With` headers first and falls
+    /// back to the file extension of the URI path.
+    pub fn classify(
+        accept: Option<&str>,
+        requested_with: Option<&str>,
+        path: &str,
+    ) -> ResourceType {
+
+        if requested_with.map(|value| value.eq_ignore_ascii_case("XMLHttpRequest")).unwrap_or(false) {
+            return ResourceType::Xhr;
+        }
+
+        if let Some(accept) = accept {
+            let accept = accept.to_ascii_lowercase();
+            let first = accept.split(',').next().unwrap_or("").trim();
+            if first.starts_with("text/html") || first.starts_with("application/xhtml+xml") {
+                return ResourceType::Frame;
+            }
+            if first.starts_with("text/css") {
+                return ResourceType::Stylesheet;
+            }
+            if first.starts_with("image/") {
+                return ResourceType::Image;
+            }
+            if first.starts_with("font/") || first.starts_with("application/font") {
+                return ResourceType::Font;
+            }
+            if first.starts_with("video/") || first.starts_with("audio/") {
+                return ResourceType::Media;
+            }
+            if first.starts_with("application/json") {
+                return ResourceType::Xhr;
+            }
+            if first.contains("javascript") || first.contains("ecmascript") {
+                return ResourceType::Script;
+            }
+        }
+
+        let name = path.rsplit('/').next().unwrap_or("");
+        let extension = match name.rfind('.') {
+            Some(pos) => name[pos + 1..].to_ascii_lowercase(),
+            None => return ResourceType::Other,
+        };
+        EXTENSIONS.iter()
+            .find(|&&(candidate, _)| candidate == extension)
+            .map(|&(_, kind)| kind)
+            .unwrap_or(ResourceType::Other)
+    }
+}
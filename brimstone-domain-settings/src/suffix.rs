@@ -0,0 +1,101 @@
+
+use std::collections;
+use std::fs;
+use std::io;
+use std::path;
+use std::sync;
+
+use idna;
+
+const EMBEDDED: &str = include_str!("../data/public_suffix_list.dat");
+
+lazy_static! {
+    static ref ACTIVE: sync::RwLock<sync::Arc<SuffixList>> =
+        sync::RwLock::new(sync::Arc::new(SuffixList::parse(EMBEDDED)));
+}
+
+/// Rules from a Public Suffix List (https://publicsuffix.org/).
+#[derive(Debug)]
+pub struct SuffixList {
+    exact: collections::HashSet<String>,
+    wildcards: collections::HashSet<String>,
+    exceptions: collections::HashSet<String>,
+}
+
+impl SuffixList {
+
+    pub fn parse(content: &str) -> SuffixList {
+        let mut list = SuffixList {
+            exact: collections::HashSet::new(),
+            wildcards: collections::HashSet::new(),
+            exceptions: collections::HashSet::new(),
+        };
+        for line in content.lines() {
+            let rule = match line.split_whitespace().next() {
+                Some(rule) => rule,
+                None => continue,
+            };
+            if rule.starts_with("//") {
+                continue;
+            }
+            if rule.starts_with("!") {
+                list.exceptions.insert(to_ascii(&rule[1..]));
+            } else if rule.starts_with("*.") {
+                list.wildcards.insert(to_ascii(&rule[2..]));
+            } else {
+                list.exact.insert(to_ascii(rule));
+            }
+        }
+        list
+    }
+
+    pub fn load<P>(path: P) -> Result<SuffixList, io::Error>
+    where P: AsRef<path::Path> {
+        Ok(SuffixList::parse(&fs::read_to_string(path)?))
+    }
+
+    pub fn len(&self) -> usize {
+        self.exact.len() + self.wildcards.len() + self.exceptions.len()
+    }
+
+    /// Number of trailing labels forming the public suffix.
+    ///
+    /// Hosts not covered by any rule fall back to the implicit `*` rule,
+    /// making their last label the suffix.
+    pub fn suffix_len(&self, labels: &[&str]) -> usize {
+        for index in 0..labels.len() {
+            let candidate = labels[index..].join(".");
+            if self.exceptions.contains(&candidate) {
+                return labels.len() - index - 1;
+            }
+            if self.exact.contains(&candidate) {
+                return labels.len() - index;
+            }
+            if index + 1 < labels.len() && self.wildcards.contains(&labels[index + 1..].join(".")) {
+                return labels.len() - index;
+            }
+        }
+        if labels.is_empty() { 0 } else { 1 }
+    }
+}
+
+/// Internationalized rules are stored in Unicode, but hosts arrive in their
+/// ASCII form.
+fn to_ascii(rule: &str) -> String {
+    if rule.is_ascii() {
+        return rule.to_lowercase();
+    }
+    idna::domain_to_ascii(rule).unwrap_or_else(|_| rule.to_lowercase())
+}
+
+/// The list used for all `Domain` parsing in this process.
+pub fn active() -> sync::Arc<SuffixList> {
+    ACTIVE.read().expect("public suffix list read access").clone()
+}
+
+/// Replaces the embedded list with an updated one.
+///
+/// Only affects domains parsed after the call.
+pub fn install(list: SuffixList) {
+    *ACTIVE.write().expect("public suffix list write access") = sync::Arc::new(list);
+}
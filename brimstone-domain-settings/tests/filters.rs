@@ -0,0 +1,83 @@
+
+extern crate brimstone_domain_settings as domain_settings;
+extern crate url;
+
+use domain_settings::{Host, ResourceType};
+use domain_settings::filter::{Filter, Matcher, Request};
+
+const FILTERS: &str = include_str!("fixtures/filters.txt");
+const REQUESTS: &str = include_str!("fixtures/requests.txt");
+
+fn matcher() -> Matcher {
+    let mut matcher = Matcher::new();
+    matcher.add_list(FILTERS);
+    matcher
+}
+
+#[test]
+fn corpus() {
+    let matcher = matcher();
+    let mut failed = Vec::new();
+    for line in REQUESTS.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        assert_eq!(fields.len(), 4, "fixture line {:?}", line);
+        let expect_blocked = match fields[0] {
+            "block" => true,
+            "allow" => false,
+            other => panic!("unknown expectation {:?}", other),
+        };
+        let kind = ResourceType::from_str(fields[1]).expect("fixture resource type");
+        let source = Host::parse(fields[2]);
+        let url = url::Url::parse(fields[3]).expect("fixture url");
+        let request = Request::new(&url, Some(&source), kind);
+        if matcher.is_blocked(&request) != expect_blocked {
+            failed.push(line.to_string());
+        }
+    }
+    assert!(failed.is_empty(), "unexpected results:\n{}", failed.join("\n"));
+}
+
+#[test]
+fn supported_filters() {
+    assert_eq!(matcher().len(), 15);
+    assert!(Filter::parse("! comment").is_none());
+    assert!(Filter::parse("example.com##.ad").is_none());
+    assert!(Filter::parse("/ads[0-9]/").is_none());
+    assert!(Filter::parse("||example.com^$popup").is_none());
+    assert!(Filter::parse("@@||example.com^").unwrap().is_exception());
+}
+
+#[test]
+fn stored_lists() {
+    let domains = domain_settings::Settings::open_in_memory().unwrap();
+    let url = url::Url::parse("https://ads.example.com/lib.js").unwrap();
+    let source = Host::parse("www.site.test");
+    let request = Request::new(&url, Some(&source), ResourceType::Script);
+
+    assert!(!domains.is_filtered(&request));
+    assert_eq!(domains.import_filter_list("test", FILTERS), 15);
+    assert_eq!(domains.filter_lists(), vec![String::from("test")]);
+    assert!(domains.is_filtered(&request));
+
+    domains.remove_filter_list("test");
+    assert!(domains.filter_lists().is_empty());
+    assert!(!domains.is_filtered(&request));
+}
+
+#[test]
+fn wildcard_backtracking() {
+    let mut matcher = Matcher::new();
+    matcher.add_list("/a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*b^");
+    let url = format!("https://example.com/{}", "a".repeat(5000));
+    let url = url::Url::parse(&url).unwrap();
+    let source = Host::parse("www.site.test");
+    assert!(!matcher.is_blocked(&Request::new(&url, Some(&source), ResourceType::Other)));
+
+    let url = format!("https://example.com/{}b?", "a".repeat(5000));
+    let url = url::Url::parse(&url).unwrap();
+    assert!(matcher.is_blocked(&Request::new(&url, Some(&source), ResourceType::Other)));
+}
@@ -2,28 +2,38 @@
 extern crate nix;
 extern crate dbus;
 extern crate serde;
+extern crate serde_json;
 
 #[macro_use]
 extern crate serde_derive;
 
 extern crate brimstone_domain_settings as domain_settings;
 
+pub mod protocol;
+mod socket;
+
+pub use protocol::Error;
+
+use std::io;
+use std::fs;
+use std::env;
 use std::thread;
-use std::rc;
-use std::cell;
 use std::collections;
 use std::sync;
 use std::path;
+use std::time;
 
 const PATH_OBJECT: &str = "/at/dunkelheit/brimstone/page_state";
 const INTERFACE: &str = "at.dunkelheit.brimstone.page_state";
-const METHOD_SET_PAGE_HOST: &str = "SetPageHost";
-const METHOD_QUIT: &str = "Quit";
+
+const CALL_TIMEOUT_MS: i32 = 1000;
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct InitArguments {
     pub instance: String,
+    pub transport: Transport,
     pub domain_settings_path: path::PathBuf,
+    pub suffix_list_path: Option<path::PathBuf>,
 }
 
 fn unpack_host(host: &domain_settings::Host) -> (String, bool) {
@@ -34,198 +44,883 @@ fn pack_host(&(ref host, is_domain): &(String, bool)) -> domain_settings::Host {
     domain_settings::Host::new(host, is_domain)
 }
 
+fn now() -> u64 {
+    time::SystemTime::now()
+        .duration_since(time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum GrantScope {
+    Page(u64),
+    Until(u64),
+}
+
+/// A temporary allowance for requests from a source to a target and its
+/// subdomains, held in memory only.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Grant {
+    source: (String, bool),
+    target: (String, bool),
+    scope: GrantScope,
+}
+
+impl Grant {
+
+    pub fn for_page(
+        page_id: u64,
+        source: &domain_settings::Host,
+        target: &domain_settings::Host,
+    ) -> Grant {
+        Grant {
+            source: unpack_host(source),
+            target: unpack_host(target),
+            scope: GrantScope::Page(page_id),
+        }
+    }
+
+    pub fn for_duration(
+        duration: time::Duration,
+        source: &domain_settings::Host,
+        target: &domain_settings::Host,
+    ) -> Grant {
+        Grant {
+            source: unpack_host(source),
+            target: unpack_host(target),
+            scope: GrantScope::Until(now() + duration.as_secs()),
+        }
+    }
+
+    pub fn source(&self) -> domain_settings::Host { pack_host(&self.source) }
+
+    pub fn target(&self) -> domain_settings::Host { pack_host(&self.target) }
+
+    pub fn scope(&self) -> GrantScope { self.scope }
+
+    pub fn is_expired(&self) -> bool {
+        match self.scope {
+            GrantScope::Page(_) => false,
+            GrantScope::Until(until) => until <= now(),
+        }
+    }
+
+    pub fn applies(
+        &self,
+        page_id: u64,
+        source: &domain_settings::Host,
+        target: &domain_settings::Host,
+    ) -> bool {
+        let is_in_scope = match self.scope {
+            GrantScope::Page(grant_page_id) => grant_page_id == page_id,
+            GrantScope::Until(_) => !self.is_expired(),
+        };
+        is_in_scope
+            && &self.source() == source
+            && target.to_expanded().contains(&self.target())
+    }
+}
+
+/// How a request was handled by the web extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Outcome {
+    Allowed,
+    /// Blocked by the domain rules.
+    Denied,
+    /// Blocked by an imported filter list.
+    Filtered,
+}
+
+impl Outcome {
+
+    pub fn is_blocked(&self) -> bool { *self != Outcome::Allowed }
+}
+
+/// Maximum number of blocked requests remembered per page.
+const BLOCKED_LOG_LIMIT: usize = 500;
+
 pub struct Store {
     pages: collections::HashMap<u64, StoreEntry>,
+    grants: Vec<Grant>,
 }
 
 impl Store {
 
     pub fn get_data(&self, page_id: u64) -> Option<Data> {
-        self.pages.get(&page_id).map(|data| Data {
-            host: pack_host(&data.host),
-            allowed: data.allowed.iter().map(|host| pack_host(host)).collect(),
-            denied: data.denied.iter().map(|host| pack_host(host)).collect(),
+        self.pages.get(&page_id).map(|data| {
+            let targets = data.targets.iter()
+                .map(|(host, stats)| TargetStats {
+                    host: pack_host(host),
+                    allowed: stats.allowed,
+                    blocked: stats.blocked,
+                    first_request: stats.first_request,
+                    last_request: stats.last_request,
+                })
+                .collect::<Vec<_>>();
+            let host = pack_host(&data.host);
+            let mut allowed = Vec::new();
+            let mut denied = Vec::new();
+            for stats in targets.iter().filter(|stats| !stats.host.is_same_site(&host)) {
+                if stats.allowed > 0 {
+                    allowed.push(stats.host.clone());
+                }
+                if stats.blocked > 0 {
+                    denied.push(stats.host.clone());
+                }
+            }
+            Data {
+                host,
+                allowed,
+                denied,
+                targets,
+                blocked: data.blocked.iter()
+                    .map(|request| BlockedRequest {
+                        uri: request.uri.clone(),
+                        target: pack_host(&request.target),
+                        outcome: request.outcome,
+                        time: request.time,
+                    })
+                    .collect(),
+            }
         })
     }
 
+    /// Active grants relevant to the page, either scoped to it or timed.
+    pub fn get_grants(&self, page_id: u64) -> Vec<Grant> {
+        self.grants.iter()
+            .filter(|grant| !grant.is_expired() && match grant.scope {
+                GrantScope::Page(grant_page_id) => grant_page_id == page_id,
+                GrantScope::Until(_) => true,
+            })
+            .cloned()
+            .collect()
+    }
+
+    fn add_grant(&mut self, grant: Grant) {
+        if !self.grants.contains(&grant) {
+            self.grants.push(grant);
+        }
+    }
+
+    fn revoke_grant(&mut self, grant: &Grant) {
+        self.grants.retain(|other| other != grant);
+    }
+
+    /// Drops the state and grants of a page, returns `true` if any grants
+    /// were removed.
+    fn forget_page(&mut self, page_id: u64) -> bool {
+        self.pages.remove(&page_id);
+        let count = self.grants.len();
+        self.grants.retain(|grant| grant.scope != GrantScope::Page(page_id));
+        self.grants.len() != count
+    }
+
+    /// Drops the recorded requests of a page but keeps its grants, returns
+    /// `true` if there were any.
+    fn clear_page(&mut self, page_id: u64) -> bool {
+        self.pages.remove(&page_id).is_some()
+    }
+
+    /// Drops the state and grants of all pages not in the list, returns
+    /// `true` if any grants were removed.
+    fn retain_pages(&mut self, page_ids: &[u64]) -> bool {
+        self.pages.retain(|page_id, _| page_ids.contains(page_id));
+        let count = self.grants.len();
+        self.grants.retain(|grant| match grant.scope {
+            GrantScope::Page(page_id) => page_ids.contains(&page_id),
+            GrantScope::Until(_) => true,
+        });
+        self.grants.len() != count
+    }
+
+    /// Drops expired grants, returns `true` if any were removed.
+    fn remove_expired_grants(&mut self) -> bool {
+        let count = self.grants.len();
+        self.grants.retain(|grant| !grant.is_expired());
+        self.grants.len() != count
+    }
+
+    /// Records a request, returns `true` if the blocked requests of the
+    /// page changed.
     fn push(
         &mut self,
         page_id: u64,
         source: &domain_settings::Host,
         target: &domain_settings::Host,
-        is_allowed: bool,
-    ) {
+        uri: &str,
+        outcome: Outcome,
+    ) -> bool {
         let entry = self.pages.entry(page_id).or_insert_with(|| StoreEntry {
             host: unpack_host(source),
-            allowed: Vec::new(),
-            denied: Vec::new(),
+            targets: collections::BTreeMap::new(),
+            blocked: collections::VecDeque::new(),
         });
-        if &pack_host(&entry.host) != source {
-            entry.allowed.clear();
-            entry.denied.clear();
+        let is_reset = &pack_host(&entry.host) != source;
+        if is_reset {
+            entry.host = unpack_host(source);
+            entry.targets.clear();
+            entry.blocked.clear();
         }
-        if is_allowed {
-            entry.allowed.push(unpack_host(target));
+        let time = now();
+        let stats = entry.targets.entry(unpack_host(target)).or_insert_with(|| StoreStats {
+            allowed: 0,
+            blocked: 0,
+            first_request: time,
+            last_request: time,
+        });
+        stats.last_request = time;
+        if outcome.is_blocked() {
+            stats.blocked += 1;
+            if entry.blocked.len() >= BLOCKED_LOG_LIMIT {
+                entry.blocked.pop_front();
+            }
+            entry.blocked.push_back(StoreBlocked {
+                uri: uri.into(),
+                target: unpack_host(target),
+                outcome,
+                time,
+            });
         } else {
-            entry.denied.push(unpack_host(target));
+            stats.allowed += 1;
         }
+        is_reset || outcome.is_blocked()
     }
 }
 
 pub struct StoreEntry {
     host: (String, bool),
-    allowed: Vec<(String, bool)>,
-    denied: Vec<(String, bool)>,
+    targets: collections::BTreeMap<(String, bool), StoreStats>,
+    blocked: collections::VecDeque<StoreBlocked>,
+}
+
+struct StoreBlocked {
+    uri: String,
+    target: (String, bool),
+    outcome: Outcome,
+    time: u64,
+}
+
+struct StoreStats {
+    allowed: u64,
+    blocked: u64,
+    first_request: u64,
+    last_request: u64,
+}
+
+/// The bus the page-state service is reachable on.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Bus {
+    Session,
+    /// A bus at a D-Bus server address like `unix:path=/tmp/bus`.
+    Address(String),
+}
+
+impl Bus {
+
+    fn connect(&self) -> Result<dbus::Connection, dbus::Error> {
+        match *self {
+            Bus::Session => dbus::Connection::get_private(dbus::BusType::Session),
+            Bus::Address(ref address) => {
+                let conn = dbus::Connection::open_private(address)?;
+                conn.register()?;
+                Ok(conn)
+            },
+        }
+    }
+}
+
+/// How clients reach the page-state service.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Transport {
+    Bus(Bus),
+    /// A Unix domain socket, for environments without a session bus.
+    Socket(path::PathBuf),
+}
+
+/// A socket path private to this process.
+///
+/// Without a runtime directory the socket is placed in a directory in the
+/// temporary directory that only the current user can access.
+pub fn socket_path() -> Result<path::PathBuf, Error> {
+    let dir = match env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => path::PathBuf::from(dir),
+        None => private_temp_dir()?,
+    };
+    Ok(dir.join(format!("brimstone-page-state-{}.sock", nix::unistd::getpid())))
+}
+
+fn private_temp_dir() -> Result<path::PathBuf, Error> {
+    use std::os::unix::fs::{ DirBuilderExt, MetadataExt };
+
+    let uid = nix::unistd::getuid();
+    let dir = env::temp_dir().join(format!("brimstone-page-state-{}", uid));
+    match fs::DirBuilder::new().mode(0o700).create(&dir) {
+        Ok(()) => (),
+        Err(ref error) if error.kind() == io::ErrorKind::AlreadyExists => (),
+        Err(error) => return Err(error.into()),
+    }
+    let metadata = fs::symlink_metadata(&dir)?;
+    let is_private = metadata.is_dir()
+        && nix::unistd::Uid::from_raw(metadata.uid()) == uid
+        && metadata.mode() & 0o077 == 0;
+    if !is_private {
+        return Err(Error::Io(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{} is not a private directory", dir.display()),
+        )));
+    }
+    Ok(dir)
 }
 
 #[derive(Debug)]
 pub struct Server {
+    transport: Transport,
     name: String,
 }
 
 impl Server {
 
     pub fn name(&self) -> &str { &self.name }
+
+    pub fn transport(&self) -> &Transport { &self.transport }
 }
 
 impl Drop for Server {
 
     fn drop(&mut self) {
-        println!("DROP");
-        let client = Client::new(&self.name);
-        client.quit();
+        let result = Client::new(&self.transport, &self.name).and_then(|client| client.quit());
+        if let Err(error) = result {
+            eprintln!("Unable to stop page state server: {}", error);
+        }
     }
 }
 
-pub fn run_server() -> (Server, sync::Arc<sync::Mutex<Store>>) {
+enum Signal {
+    RulesChanged,
+    GrantsChanged,
+    PageChanged(u64),
+}
+
+impl Signal {
+
+    fn member(&self) -> &'static str {
+        match *self {
+            Signal::RulesChanged => protocol::SIGNAL_RULES_CHANGED,
+            Signal::GrantsChanged => protocol::SIGNAL_GRANTS_CHANGED,
+            Signal::PageChanged(_) => protocol::SIGNAL_PAGE_CHANGED,
+        }
+    }
+
+    fn page_id(&self) -> Option<u64> {
+        match *self {
+            Signal::PageChanged(page_id) => Some(page_id),
+            _ => None,
+        }
+    }
+
+    fn to_message(&self) -> dbus::Message {
+        let message = dbus::Message::signal(
+            &PATH_OBJECT.into(),
+            &INTERFACE.into(),
+            &self.member().into(),
+        );
+        match self.page_id() {
+            Some(page_id) => message.append1(page_id),
+            None => message,
+        }
+    }
+
+    fn to_frame(&self) -> protocol::Frame {
+        protocol::Frame::Signal {
+            member: self.member().into(),
+            page_id: self.page_id(),
+        }
+    }
+}
+
+/// The result of a handled method call.
+struct Handled {
+    reply: Option<String>,
+    signals: Vec<Signal>,
+}
+
+impl Handled {
+
+    fn signals(signals: Vec<Signal>) -> Handled {
+        Handled { reply: None, signals }
+    }
+
+    fn reply<T>(value: &T) -> Handled where T: serde::Serialize {
+        Handled {
+            reply: Some(serde_json::to_string(value).expect("page state reply serialization")),
+            signals: Vec::new(),
+        }
+    }
+}
+
+fn decode_request<T>(request: Option<&str>) -> Result<T, String>
+where T: serde::de::DeserializeOwned {
+    let request = request.ok_or_else(|| "missing request".to_string())?;
+    serde_json::from_str(request).map_err(|error| error.to_string())
+}
+
+/// Handles a method call the same way for all transports.
+fn handle(
+    store: &sync::Mutex<Store>,
+    quit_flag: &sync::atomic::AtomicBool,
+    method: &str,
+    request: Option<&str>,
+) -> Result<Handled, String> {
+    let mut store = store.lock().expect("page state storage access");
+    Ok(match method {
+        protocol::METHOD_HANDSHAKE => {
+            let request: protocol::Handshake = decode_request(request)?;
+            if request.version != protocol::VERSION {
+                eprintln!("Page state client with unsupported protocol version {}",
+                    request.version,
+                );
+            }
+            Handled::reply(&protocol::HandshakeReply { version: protocol::VERSION })
+        },
+        protocol::METHOD_QUIT => {
+            quit_flag.store(true, sync::atomic::Ordering::SeqCst);
+            Handled::signals(Vec::new())
+        },
+        protocol::METHOD_ADD_GRANT => {
+            let request: protocol::GrantChange = decode_request(request)?;
+            store.add_grant(request.grant);
+            Handled::signals(vec![Signal::GrantsChanged])
+        },
+        protocol::METHOD_REVOKE_GRANT => {
+            let request: protocol::GrantChange = decode_request(request)?;
+            store.revoke_grant(&request.grant);
+            Handled::signals(vec![Signal::GrantsChanged])
+        },
+        protocol::METHOD_GET_GRANTS => {
+            let grants = store.grants.iter()
+                .filter(|grant| !grant.is_expired())
+                .cloned()
+                .collect::<Vec<_>>();
+            Handled::reply(&protocol::GrantsReply { grants })
+        },
+        protocol::METHOD_FORGET_PAGE => {
+            let request: protocol::ForgetPage = decode_request(request)?;
+            Handled::signals(grants_changed_signals(store.forget_page(request.page_id)))
+        },
+        protocol::METHOD_CLEAR_PAGE => {
+            let request: protocol::ClearPage = decode_request(request)?;
+            Handled::signals(if store.clear_page(request.page_id) {
+                vec![Signal::PageChanged(request.page_id)]
+            } else {
+                Vec::new()
+            })
+        },
+        protocol::METHOD_RETAIN_PAGES => {
+            let request: protocol::RetainPages = decode_request(request)?;
+            Handled::signals(grants_changed_signals(store.retain_pages(&request.page_ids)))
+        },
+        protocol::METHOD_NOTIFY_RULES_CHANGED => {
+            Handled::signals(vec![Signal::RulesChanged])
+        },
+        protocol::METHOD_PUSH => {
+            let request: protocol::Push = decode_request(request)?;
+            let has_changed = store.push(
+                request.page_id,
+                &pack_host(&request.source),
+                &pack_host(&request.target),
+                &request.uri,
+                request.outcome,
+            );
+            Handled::signals(if has_changed {
+                vec![Signal::PageChanged(request.page_id)]
+            } else {
+                Vec::new()
+            })
+        },
+        _ => return Err(format!("unknown method {}", method)),
+    })
+}
+
+fn grants_changed_signals(has_changed: bool) -> Vec<Signal> {
+    if has_changed {
+        vec![Signal::GrantsChanged]
+    } else {
+        Vec::new()
+    }
+}
+
+/// Starts the page-state service on a new thread and waits until it is
+/// reachable.
+pub fn run_server(transport: &Transport) -> Result<(Server, sync::Arc<sync::Mutex<Store>>), Error> {
     let name = format!("{}.instance-{}", INTERFACE, nix::unistd::getpid());
     let store = sync::Arc::new(sync::Mutex::new(Store {
         pages: collections::HashMap::new(),
+        grants: Vec::new(),
     }));
+    let quit_flag = sync::Arc::new(sync::atomic::AtomicBool::new(false));
+    match *transport {
+        Transport::Bus(ref bus) => run_bus_server(bus, &name, store.clone(), quit_flag)?,
+        Transport::Socket(ref path) => socket::run_server(path, store.clone(), quit_flag)?,
+    }
+    Ok((Server { transport: transport.clone(), name }, store))
+}
+
+fn run_bus_server(
+    bus: &Bus,
+    name: &str,
+    store: sync::Arc<sync::Mutex<Store>>,
+    quit_flag: sync::Arc<sync::atomic::AtomicBool>,
+) -> Result<(), Error> {
+    let (ready_sender, ready_receiver) = sync::mpsc::channel();
     thread::spawn({
-        let name = name.clone();
-        let store = store.clone();
+        let bus = bus.clone();
+        let name = name.to_string();
         move || {
-            let quit_flag = rc::Rc::new(cell::Cell::new(false));
-            let conn = dbus::Connection::get_private(dbus::BusType::Session)
-                .expect("dbus session bus access");
-            conn.register_name(&name, dbus::NameFlag::ReplaceExisting as u32)
-                .expect(&format!("dbus name {:?}", name));
-            let fac = dbus::tree::Factory::new_fn::<()>();
-            let tree = fac
-                .tree(())
-                .add(fac.object_path(PATH_OBJECT, ()).add(
-                    fac.interface(INTERFACE, ())
-                        .add_m({
-                            let quit_flag = quit_flag.clone();
-                            fac.method(METHOD_QUIT, (), move |_m| {
-                                quit_flag.set(true);
-                                Ok(Vec::new())
-                            })
-                        })
-                        .add_m(
-                            fac.method(METHOD_SET_PAGE_HOST, (), move |m| {
-                                let mut args = m.msg.iter_init();
-                                let source = {
-                                    let host: &str = args.read()?;
-                                    let is_domain: bool = args.read()?;
-                                    domain_settings::Host::new(host, is_domain)
-                                };
-                                let target = {
-                                    let host: &str = args.read()?;
-                                    let is_domain: bool = args.read()?;
-                                    domain_settings::Host::new(host, is_domain)
-                                };
-                                let is_allowed: bool = args.read()?;
-                                let page_id: u64 = args.read()?;
-                                store.lock()
-                                    .expect("page state storage access")
-                                    .push(page_id, &source, &target, is_allowed);
-                                Ok(Vec::new())
-                            })
-                            .inarg::<&str, _>("source_host")
-                            .inarg::<bool, _>("source_host_is_domain")
-                            .inarg::<&str, _>("target_host")
-                            .inarg::<bool, _>("target_host_is_domain")
-                            .inarg::<bool, _>("is_allowed")
-                            .inarg::<u64, _>("page_id")
-                        )
-                ));
-            tree.set_registered(&conn, true)
-                .expect("dbus tree registered");
+            let conn = match bus.connect() {
+                Ok(conn) => conn,
+                Err(error) => {
+                    let _ = ready_sender.send(Err(Error::from(error)));
+                    return;
+                },
+            };
+            if let Err(error) = conn.register_name(&name, dbus::NameFlag::ReplaceExisting as u32) {
+                let _ = ready_sender.send(Err(Error::from(error)));
+                return;
+            }
+            let tree = create_tree(store.clone(), quit_flag.clone());
+            if let Err(error) = tree.set_registered(&conn, true) {
+                let _ = ready_sender.send(Err(Error::from(error)));
+                return;
+            }
             conn.add_handler(tree);
+            let _ = ready_sender.send(Ok(()));
             loop {
-                if quit_flag.get() {
+                if quit_flag.load(sync::atomic::Ordering::SeqCst) {
                     return;
                 }
                 conn.incoming(1000).next();
+                let has_expired = store.lock()
+                    .expect("page state storage access")
+                    .remove_expired_grants();
+                if has_expired {
+                    if let Err(()) = conn.send(Signal::GrantsChanged.to_message()) {
+                        eprintln!("Unable to send grants changed signal");
+                    }
+                }
             }
         }
     });
-    (Server { name }, store)
+    match ready_receiver.recv() {
+        Ok(result) => result,
+        Err(_) => Err(Error::Send("page state server thread exited".into())),
+    }
+}
+
+type Tree = dbus::tree::Tree<dbus::tree::MTFn<()>, ()>;
+
+fn create_tree(
+    store: sync::Arc<sync::Mutex<Store>>,
+    quit_flag: sync::Arc<sync::atomic::AtomicBool>,
+) -> Tree {
+    let fac = dbus::tree::Factory::new_fn::<()>();
+    let mut interface = fac.interface(INTERFACE, ())
+        .add_s(fac.signal(protocol::SIGNAL_RULES_CHANGED, ()))
+        .add_s(fac.signal(protocol::SIGNAL_GRANTS_CHANGED, ()))
+        .add_s(fac.signal(protocol::SIGNAL_PAGE_CHANGED, ()).sarg::<u64, _>("page_id"));
+    for &(name, has_request, has_reply) in protocol::METHODS {
+        let store = store.clone();
+        let quit_flag = quit_flag.clone();
+        let mut method = fac.method(name, (), move |m| {
+            let request: Option<&str> = if has_request { Some(m.msg.read1()?) } else { None };
+            let handled = handle(&store, &quit_flag, name, request)
+                .map_err(|error| dbus::tree::MethodErr::invalid_arg(&error))?;
+            let mut messages = Vec::new();
+            if let Some(reply) = handled.reply {
+                messages.push(m.msg.method_return().append1(reply));
+            }
+            messages.extend(handled.signals.iter().map(Signal::to_message));
+            Ok(messages)
+        });
+        if has_request {
+            method = method.inarg::<&str, _>("request");
+        }
+        if has_reply {
+            method = method.outarg::<&str, _>("reply");
+        }
+        interface = interface.add_m(method);
+    }
+    fac.tree(()).add(fac.object_path(PATH_OBJECT, ()).add(interface))
+}
+
+
+/// Change signals received since the last check.
+#[derive(Debug, Clone, Default)]
+pub struct Changes {
+    pub rules: bool,
+    pub grants: bool,
+    /// Pages with changed blocked requests, see `watch_page_changes`.
+    pub pages: Vec<u64>,
+}
+
+enum Connection {
+    Bus(dbus::Connection),
+    Socket(socket::Connection),
 }
 
 pub struct Client {
     name: String,
-    conn: dbus::Connection,
+    conn: Connection,
 }
 
 impl Client {
 
-    pub fn new(name: &str) -> Client {
-        Client {
+    /// Connects to the service without checking the protocol version.
+    ///
+    /// The name is only used on a bus transport.
+    pub fn new(transport: &Transport, name: &str) -> Result<Client, Error> {
+        Ok(Client {
             name: name.into(),
-            conn: dbus::Connection::get_private(dbus::BusType::Session)
-                .expect("dbus client connection"),
+            conn: match *transport {
+                Transport::Bus(ref bus) => Connection::Bus(bus.connect()?),
+                Transport::Socket(ref path) => Connection::Socket(socket::Connection::open(path)?),
+            },
+        })
+    }
+
+    /// Connects to the service and makes sure it speaks the same protocol
+    /// version.
+    pub fn connect(transport: &Transport, name: &str) -> Result<Client, Error> {
+        let client = Client::new(transport, name)?;
+        let reply: protocol::HandshakeReply = client.call(
+            protocol::METHOD_HANDSHAKE,
+            Some(&protocol::Handshake { version: protocol::VERSION }),
+        )?;
+        if reply.version != protocol::VERSION {
+            return Err(Error::VersionMismatch {
+                client: protocol::VERSION,
+                server: reply.version,
+            });
         }
+        Ok(client)
     }
 
-    pub fn quit(&self) {
-        let call = dbus::Message::new_method_call(
+    fn message(&self, method: &str, request: Option<String>) -> Result<dbus::Message, Error> {
+        let message = dbus::Message::new_method_call(
             &self.name,
             PATH_OBJECT,
             INTERFACE,
-            METHOD_QUIT,
-        ).expect("dbus quit method message construction");
-        self.conn.send(call).expect("dbus quit method call dispatch");
+            method,
+        ).map_err(Error::Send)?;
+        Ok(match request {
+            Some(request) => message.append1(request),
+            None => message,
+        })
+    }
+
+    /// Sends a request without waiting for the reply.
+    fn send<T>(&self, method: &str, request: Option<&T>) -> Result<(), Error>
+    where T: serde::Serialize {
+        let request = encode_request(request)?;
+        match self.conn {
+            Connection::Bus(ref conn) => {
+                let message = self.message(method, request)?;
+                conn.send(message)
+                    .map(|_serial| ())
+                    .map_err(|()| Error::Send(format!("{} call dispatch", method)))
+            },
+            Connection::Socket(ref conn) => conn.send(method, request),
+        }
     }
 
+    /// Sends a request and waits for the reply.
+    fn call<T, R>(&self, method: &str, request: Option<&T>) -> Result<R, Error>
+    where T: serde::Serialize, R: serde::de::DeserializeOwned {
+        let request = encode_request(request)?;
+        let reply = match self.conn {
+            Connection::Bus(ref conn) => {
+                let message = self.message(method, request)?;
+                let reply = conn.send_with_reply_and_block(message, CALL_TIMEOUT_MS)?;
+                let reply: String = reply.read1()
+                    .map_err(|error| Error::Decode(format!("{:?}", error)))?;
+                reply
+            },
+            Connection::Socket(ref conn) => conn.call(method, request, CALL_TIMEOUT_MS)?,
+        };
+        Ok(serde_json::from_str(&reply)?)
+    }
+
+    pub fn quit(&self) -> Result<(), Error> {
+        self.send::<()>(protocol::METHOD_QUIT, None)
+    }
+
+    /// Records a request made by the page.
     pub fn push(
         &self,
         page_id: u64,
         source: &domain_settings::Host,
         target: &domain_settings::Host,
-        is_allowed: bool,
-    ) {
-        let call =
-            dbus::Message::new_method_call(
-                &self.name,
-                PATH_OBJECT,
-                INTERFACE,
-                METHOD_SET_PAGE_HOST,
-            ).expect("dbus push method message construction")
-            .append1(source.as_str())
-            .append1(source.is_domain())
-            .append1(target.as_str())
-            .append1(target.is_domain())
-            .append1(is_allowed)
-            .append1(page_id);
-        self.conn.send(call).expect("dbus push method call dispatch");
+        uri: &str,
+        outcome: Outcome,
+    ) -> Result<(), Error> {
+        self.send(protocol::METHOD_PUSH, Some(&protocol::Push {
+            page_id,
+            source: unpack_host(source),
+            target: unpack_host(target),
+            uri: uri.into(),
+            outcome,
+        }))
+    }
+
+    /// Drops all state kept for a page that went away.
+    pub fn forget_page(&self, page_id: u64) -> Result<(), Error> {
+        self.send(protocol::METHOD_FORGET_PAGE, Some(&protocol::ForgetPage { page_id }))
+    }
+
+    /// Drops the requests recorded for a page, for example after its web
+    /// process went away. Grants for the page are kept.
+    pub fn clear_page(&self, page_id: u64) -> Result<(), Error> {
+        self.send(protocol::METHOD_CLEAR_PAGE, Some(&protocol::ClearPage { page_id }))
+    }
+
+    /// Drops all state kept for pages other than the given ones.
+    pub fn retain_pages(&self, page_ids: &[u64]) -> Result<(), Error> {
+        self.send(protocol::METHOD_RETAIN_PAGES, Some(&protocol::RetainPages {
+            page_ids: page_ids.to_vec(),
+        }))
+    }
+
+    /// Asks the server to tell all web processes that domain rules changed.
+    pub fn notify_rules_changed(&self) -> Result<(), Error> {
+        self.send::<()>(protocol::METHOD_NOTIFY_RULES_CHANGED, None)
+    }
+
+    pub fn add_grant(&self, grant: &Grant) -> Result<(), Error> {
+        self.send(protocol::METHOD_ADD_GRANT, Some(&protocol::GrantChange {
+            grant: grant.clone(),
+        }))
+    }
+
+    pub fn revoke_grant(&self, grant: &Grant) -> Result<(), Error> {
+        self.send(protocol::METHOD_REVOKE_GRANT, Some(&protocol::GrantChange {
+            grant: grant.clone(),
+        }))
+    }
+
+    /// Fetches all active grants from the server.
+    pub fn get_grants(&self) -> Result<Vec<Grant>, Error> {
+        let reply: protocol::GrantsReply = self.call::<(), _>(protocol::METHOD_GET_GRANTS, None)?;
+        Ok(reply.grants)
+    }
+
+    fn watch_signal(&self, member: &str) -> Result<(), Error> {
+        match self.conn {
+            Connection::Bus(ref conn) => {
+                let rule = format!(
+                    "type='signal',sender='{}',path='{}',interface='{}',member='{}'",
+                    self.name,
+                    PATH_OBJECT,
+                    INTERFACE,
+                    member,
+                );
+                conn.add_match(&rule)?;
+            },
+            Connection::Socket(ref conn) => conn.watch(member)?,
+        }
+        Ok(())
+    }
+
+    /// Subscribes to rule and grant change signals from the server.
+    pub fn watch_changes(&self) -> Result<(), Error> {
+        self.watch_signal(protocol::SIGNAL_RULES_CHANGED)?;
+        self.watch_signal(protocol::SIGNAL_GRANTS_CHANGED)
+    }
+
+    /// Subscribes to signals about pages with changed blocked requests.
+    pub fn watch_page_changes(&self) -> Result<(), Error> {
+        self.watch_signal(protocol::SIGNAL_PAGE_CHANGED)
+    }
+
+    /// Drains pending messages without blocking and reports which change
+    /// signals were among them. Requires `watch_changes` or
+    /// `watch_page_changes`.
+    pub fn take_changes(&self) -> Changes {
+        let signals = match self.conn {
+            Connection::Bus(ref conn) => conn.incoming(0)
+                .filter(|message| message.msg_type() == dbus::MessageType::Signal)
+                .filter_map(|message| {
+                    message.member()
+                        .map(|member| ((*member).to_string(), message.get1::<u64>()))
+                })
+                .collect::<Vec<_>>(),
+            Connection::Socket(ref conn) => conn.take_signals(),
+        };
+        let mut changes = Changes::default();
+        for (member, page_id) in signals {
+            match member.as_str() {
+                protocol::SIGNAL_RULES_CHANGED => changes.rules = true,
+                protocol::SIGNAL_GRANTS_CHANGED => changes.grants = true,
+                protocol::SIGNAL_PAGE_CHANGED => {
+                    if let Some(page_id) = page_id {
+                        if !changes.pages.contains(&page_id) {
+                            changes.pages.push(page_id);
+                        }
+                    }
+                },
+                _ => (),
+            }
+        }
+        changes
     }
 }
 
+fn encode_request<T>(request: Option<&T>) -> Result<Option<String>, Error>
+where T: serde::Serialize {
+    match request {
+        Some(request) => Ok(Some(serde_json::to_string(request)?)),
+        None => Ok(None),
+    }
+}
+
+
+/// Requests made to a target host since the page switched to its current
+/// host. Times are in seconds since the Unix epoch.
+#[derive(Debug, Clone)]
+pub struct TargetStats {
+    pub host: domain_settings::Host,
+    pub allowed: u64,
+    pub blocked: u64,
+    pub first_request: u64,
+    pub last_request: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct BlockedRequest {
+    pub uri: String,
+    pub target: domain_settings::Host,
+    pub outcome: Outcome,
+    pub time: u64,
+}
+
 pub struct Data {
     host: domain_settings::Host,
     allowed: Vec<domain_settings::Host>,
     denied: Vec<domain_settings::Host>,
+    targets: Vec<TargetStats>,
+    blocked: Vec<BlockedRequest>,
 }
 
 impl Data {
 
     pub fn host(&self) -> &domain_settings::Host { &self.host }
 
+    /// Third-party target hosts with at least one allowed request.
     pub fn allowed(&self) -> &[domain_settings::Host] { &self.allowed }
 
+    /// Third-party target hosts with at least one blocked request.
     pub fn denied(&self) -> &[domain_settings::Host] { &self.denied }
-}
 
+    pub fn targets(&self) -> &[TargetStats] { &self.targets }
+
+    /// The most recent blocked requests, oldest first.
+    pub fn blocked(&self) -> &[BlockedRequest] { &self.blocked }
+}
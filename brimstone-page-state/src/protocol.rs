@@ -0,0 +1,176 @@
+
+//! Messages exchanged with the page-state service.
+//!
+//! Every method takes at most one JSON encoded request and returns at most
+//! one JSON encoded reply. Clients have to complete the `Handshake` before
+//! anything else, which fails when client and server disagree on
+//! `VERSION`. The version has to be raised with every incompatible change
+//! to the messages below.
+//!
+//! On the socket transport the same methods and signals are carried in
+//! `Frame`s, one JSON document per line.
+
+use std::fmt;
+use std::io;
+
+use dbus;
+use serde_json;
+
+use Grant;
+use Outcome;
+
+pub const VERSION: u32 = 1;
+
+pub const METHOD_HANDSHAKE: &str = "Handshake";
+pub const METHOD_PUSH: &str = "Push";
+pub const METHOD_QUIT: &str = "Quit";
+pub const METHOD_NOTIFY_RULES_CHANGED: &str = "NotifyRulesChanged";
+pub const METHOD_ADD_GRANT: &str = "AddGrant";
+pub const METHOD_REVOKE_GRANT: &str = "RevokeGrant";
+pub const METHOD_GET_GRANTS: &str = "GetGrants";
+pub const METHOD_FORGET_PAGE: &str = "ForgetPage";
+pub const METHOD_CLEAR_PAGE: &str = "ClearPage";
+pub const METHOD_RETAIN_PAGES: &str = "RetainPages";
+
+/// All methods, with whether they take a request and return a reply.
+pub const METHODS: &[(&str, bool, bool)] = &[
+    (METHOD_HANDSHAKE, true, true),
+    (METHOD_PUSH, true, false),
+    (METHOD_QUIT, false, false),
+    (METHOD_NOTIFY_RULES_CHANGED, false, false),
+    (METHOD_ADD_GRANT, true, false),
+    (METHOD_REVOKE_GRANT, true, false),
+    (METHOD_GET_GRANTS, false, true),
+    (METHOD_FORGET_PAGE, true, false),
+    (METHOD_CLEAR_PAGE, true, false),
+    (METHOD_RETAIN_PAGES, true, false),
+];
+
+pub const SIGNAL_RULES_CHANGED: &str = "RulesChanged";
+pub const SIGNAL_GRANTS_CHANGED: &str = "GrantsChanged";
+pub const SIGNAL_PAGE_CHANGED: &str = "PageChanged";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Handshake {
+    pub version: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HandshakeReply {
+    pub version: u32,
+}
+
+/// A request made by a page, hosts are passed as name and domain flag.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Push {
+    pub page_id: u64,
+    pub source: (String, bool),
+    pub target: (String, bool),
+    pub uri: String,
+    pub outcome: Outcome,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GrantChange {
+    pub grant: Grant,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GrantsReply {
+    pub grants: Vec<Grant>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ForgetPage {
+    pub page_id: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClearPage {
+    pub page_id: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RetainPages {
+    pub page_ids: Vec<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Frame {
+    /// A method call, only answered when `needs_reply` is set.
+    Call {
+        serial: u64,
+        method: String,
+        request: Option<String>,
+        needs_reply: bool,
+    },
+    Return {
+        serial: u64,
+        reply: Option<String>,
+    },
+    Error {
+        serial: u64,
+        message: String,
+    },
+    /// Subscribes the connection to a signal.
+    Watch {
+        member: String,
+    },
+    /// Sent to every connection watching the signal.
+    Signal {
+        member: String,
+        page_id: Option<u64>,
+    },
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// The bus connection or a method call failed.
+    Dbus(dbus::Error),
+    /// The socket connection failed.
+    Io(io::Error),
+    /// The server rejected a call on the socket transport.
+    Remote(String),
+    /// A message couldn't be constructed or queued for sending.
+    Send(String),
+    /// A reply couldn't be decoded.
+    Decode(String),
+    Json(serde_json::Error),
+    VersionMismatch { client: u32, server: u32 },
+}
+
+impl From<dbus::Error> for Error {
+
+    fn from(error: dbus::Error) -> Error { Error::Dbus(error) }
+}
+
+impl From<io::Error> for Error {
+
+    fn from(error: io::Error) -> Error { Error::Io(error) }
+}
+
+impl From<serde_json::Error> for Error {
+
+    fn from(error: serde_json::Error) -> Error { Error::Json(error) }
+}
+
+impl fmt::Display for Error {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Dbus(ref error) => write!(f, "D-Bus error: {}",
+                error.message().unwrap_or("unknown error"),
+            ),
+            Error::Io(ref error) => write!(f, "socket error: {}", error),
+            Error::Remote(ref message) => write!(f, "server error: {}", message),
+            Error::Send(ref message) => write!(f, "unable to send message: {}", message),
+            Error::Decode(ref message) => write!(f, "unable to decode reply: {}", message),
+            Error::Json(ref error) => write!(f, "{}", error),
+            Error::VersionMismatch { client, server } =>
+                write!(f, "protocol version {} is not supported by server version {}",
+                    client,
+                    server,
+                ),
+        }
+    }
+}
@@ -0,0 +1,320 @@
+
+//! The page-state protocol over a Unix domain socket.
+
+use std::io;
+use std::fs;
+use std::path;
+use std::sync;
+use std::thread;
+use std::time;
+use std::cell;
+
+use std::io::{ BufRead, Write };
+use std::os::unix::net;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::AsRawFd;
+
+use nix;
+
+use serde_json;
+
+use protocol;
+use Error;
+use Signal;
+use Store;
+
+/// Interval for checking for new connections and the quit flag.
+const ACCEPT_POLL_MS: u64 = 100;
+
+/// Interval for dropping expired grants.
+const EXPIRE_INTERVAL_SECS: u64 = 1;
+
+/// Clients that don't take their signals in time are disconnected.
+const WRITE_TIMEOUT_MS: u64 = 1000;
+
+struct Peer {
+    stream: net::UnixStream,
+    watched: Vec<String>,
+}
+
+type SharedPeer = sync::Arc<sync::Mutex<Peer>>;
+
+fn write_frame(stream: &mut net::UnixStream, frame: &protocol::Frame) -> Result<(), Error> {
+    let mut line = serde_json::to_string(frame)?;
+    line.push('\n');
+    stream.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+/// Sends the signals to all clients watching them and forgets disconnected
+/// ones.
+fn broadcast(peers: &sync::Mutex<Vec<SharedPeer>>, signals: &[Signal]) {
+    if signals.is_empty() {
+        return;
+    }
+    let mut peers = peers.lock().expect("page state peers access");
+    peers.retain(|peer| {
+        let mut peer = peer.lock().expect("page state peer access");
+        let Peer { ref mut stream, ref watched } = *peer;
+        signals.iter()
+            .filter(|signal| watched.iter().any(|member| member == signal.member()))
+            .all(|signal| write_frame(stream, &signal.to_frame()).is_ok())
+    });
+}
+
+/// Binds the socket and serves clients on new threads until a client asks
+/// the server to quit. A stale socket file is replaced.
+pub fn run_server(
+    path: &path::Path,
+    store: sync::Arc<sync::Mutex<Store>>,
+    quit_flag: sync::Arc<sync::atomic::AtomicBool>,
+) -> Result<(), Error> {
+    if path.exists() {
+        fs::remove_file(path)?;
+    }
+    let listener = net::UnixListener::bind(path)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    listener.set_nonblocking(true)?;
+    let path = path.to_path_buf();
+    thread::spawn(move || {
+        let peers = sync::Arc::new(sync::Mutex::new(Vec::new()));
+        let mut last_expire = time::Instant::now();
+        while !quit_flag.load(sync::atomic::Ordering::SeqCst) {
+            match listener.accept() {
+                Ok((stream, _address)) => {
+                    let result = serve_client(stream, store.clone(), quit_flag.clone(), peers.clone());
+                    if let Err(error) = result {
+                        eprintln!("Unable to serve page state client: {}", error);
+                    }
+                },
+                Err(ref error) if error.kind() == io::ErrorKind::WouldBlock => {
+                    thread::sleep(time::Duration::from_millis(ACCEPT_POLL_MS));
+                },
+                Err(error) => {
+                    eprintln!("Unable to accept page state client: {}", error);
+                    thread::sleep(time::Duration::from_millis(ACCEPT_POLL_MS));
+                },
+            }
+            if last_expire.elapsed() >= time::Duration::from_secs(EXPIRE_INTERVAL_SECS) {
+                last_expire = time::Instant::now();
+                let has_expired = store.lock()
+                    .expect("page state storage access")
+                    .remove_expired_grants();
+                if has_expired {
+                    broadcast(&peers, &[Signal::GrantsChanged]);
+                }
+            }
+        }
+        if let Err(error) = fs::remove_file(&path) {
+            eprintln!("Unable to remove page state socket: {}", error);
+        }
+    });
+    Ok(())
+}
+
+/// Rejects clients running as a different user.
+fn check_peer(stream: &net::UnixStream) -> Result<(), Error> {
+    use nix::sys::socket::{ getsockopt, sockopt };
+
+    let credentials = getsockopt(stream.as_raw_fd(), sockopt::PeerCredentials)
+        .map_err(|error| io::Error::new(io::ErrorKind::Other, error.to_string()))?;
+    if nix::unistd::Uid::from_raw(credentials.uid()) != nix::unistd::getuid() {
+        return Err(Error::Io(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("page state client runs as user {}", credentials.uid()),
+        )));
+    }
+    Ok(())
+}
+
+fn serve_client(
+    stream: net::UnixStream,
+    store: sync::Arc<sync::Mutex<Store>>,
+    quit_flag: sync::Arc<sync::atomic::AtomicBool>,
+    peers: sync::Arc<sync::Mutex<Vec<SharedPeer>>>,
+) -> Result<(), Error> {
+    check_peer(&stream)?;
+    stream.set_nonblocking(false)?;
+    stream.set_write_timeout(Some(time::Duration::from_millis(WRITE_TIMEOUT_MS)))?;
+    let peer = sync::Arc::new(sync::Mutex::new(Peer {
+        stream: stream.try_clone()?,
+        watched: Vec::new(),
+    }));
+    peers.lock().expect("page state peers access").push(peer.clone());
+    thread::spawn(move || {
+        for line in io::BufReader::new(stream).lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => return,
+            };
+            let (serial, method, request, needs_reply) = match serde_json::from_str(&line) {
+                Ok(protocol::Frame::Call { serial, method, request, needs_reply }) =>
+                    (serial, method, request, needs_reply),
+                Ok(protocol::Frame::Watch { member }) => {
+                    let mut peer = peer.lock().expect("page state peer access");
+                    if !peer.watched.contains(&member) {
+                        peer.watched.push(member);
+                    }
+                    continue;
+                },
+                Ok(frame) => {
+                    eprintln!("Unexpected page state frame: {:?}", frame);
+                    continue;
+                },
+                Err(error) => {
+                    eprintln!("Invalid page state frame: {}", error);
+                    continue;
+                },
+            };
+            let result = ::handle(
+                &store,
+                &quit_flag,
+                &method,
+                request.as_ref().map(|request| request.as_str()),
+            );
+            let reply = match result {
+                Ok(handled) => {
+                    broadcast(&peers, &handled.signals);
+                    protocol::Frame::Return { serial, reply: handled.reply }
+                },
+                Err(message) => {
+                    if !needs_reply {
+                        eprintln!("Page state {} call failed: {}", method, message);
+                    }
+                    protocol::Frame::Error { serial, message }
+                },
+            };
+            if needs_reply {
+                let mut peer = peer.lock().expect("page state peer access");
+                if write_frame(&mut peer.stream, &reply).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+/// A client connection to a socket server.
+pub struct Connection {
+    reader: cell::RefCell<io::BufReader<net::UnixStream>>,
+    writer: cell::RefCell<net::UnixStream>,
+    /// A partially received frame.
+    buffer: cell::RefCell<Vec<u8>>,
+    serial: cell::Cell<u64>,
+    /// Signals received while waiting for a reply.
+    signals: cell::RefCell<Vec<(String, Option<u64>)>>,
+}
+
+impl Connection {
+
+    pub fn open(path: &path::Path) -> Result<Connection, Error> {
+        let stream = net::UnixStream::connect(path)?;
+        Ok(Connection {
+            writer: cell::RefCell::new(stream.try_clone()?),
+            reader: cell::RefCell::new(io::BufReader::new(stream)),
+            buffer: cell::RefCell::new(Vec::new()),
+            serial: cell::Cell::new(0),
+            signals: cell::RefCell::new(Vec::new()),
+        })
+    }
+
+    fn write_call(
+        &self,
+        method: &str,
+        request: Option<String>,
+        needs_reply: bool,
+    ) -> Result<u64, Error> {
+        let serial = self.serial.get() + 1;
+        self.serial.set(serial);
+        write_frame(&mut self.writer.borrow_mut(), &protocol::Frame::Call {
+            serial,
+            method: method.into(),
+            request,
+            needs_reply,
+        })?;
+        Ok(serial)
+    }
+
+    /// Reads the next complete frame. Partial frames are kept until the
+    /// rest arrives.
+    fn read_frame(&self) -> Result<Option<protocol::Frame>, Error> {
+        let mut buffer = self.buffer.borrow_mut();
+        if self.reader.borrow_mut().read_until(b'\n', &mut *buffer)? == 0 {
+            return Err(Error::Io(io::ErrorKind::UnexpectedEof.into()));
+        }
+        if buffer.last() != Some(&b'\n') {
+            return Ok(None);
+        }
+        let frame: Result<protocol::Frame, _> = serde_json::from_slice(&buffer);
+        buffer.clear();
+        Ok(Some(frame?))
+    }
+
+    fn receive_signal(&self, frame: protocol::Frame) {
+        if let protocol::Frame::Signal { member, page_id } = frame {
+            self.signals.borrow_mut().push((member, page_id));
+        }
+    }
+
+    pub fn send(&self, method: &str, request: Option<String>) -> Result<(), Error> {
+        self.write_call(method, request, false).map(|_serial| ())
+    }
+
+    pub fn call(
+        &self,
+        method: &str,
+        request: Option<String>,
+        timeout_ms: i32,
+    ) -> Result<String, Error> {
+        let serial = self.write_call(method, request, true)?;
+        let timeout = time::Duration::from_millis(timeout_ms as u64);
+        self.reader.borrow().get_ref().set_read_timeout(Some(timeout))?;
+        let result = loop {
+            let frame = match self.read_frame() {
+                Ok(Some(frame)) => frame,
+                Ok(None) => continue,
+                Err(error) => break Err(error),
+            };
+            match frame {
+                protocol::Frame::Return { serial: reply_serial, reply } if reply_serial == serial =>
+                    break reply.ok_or_else(|| Error::Decode("missing reply".into())),
+                protocol::Frame::Error { serial: reply_serial, message } if reply_serial == serial =>
+                    break Err(Error::Remote(message)),
+                frame => self.receive_signal(frame),
+            }
+        };
+        self.reader.borrow().get_ref().set_read_timeout(None)?;
+        result
+    }
+
+    /// Asks the server to send the signal to this connection.
+    pub fn watch(&self, member: &str) -> Result<(), Error> {
+        write_frame(&mut self.writer.borrow_mut(), &protocol::Frame::Watch {
+            member: member.into(),
+        })
+    }
+
+    /// Drains received signals without blocking.
+    pub fn take_signals(&self) -> Vec<(String, Option<u64>)> {
+        if let Err(error) = self.reader.borrow().get_ref().set_nonblocking(true) {
+            eprintln!("Unable to poll page state socket: {}", error);
+            return self.signals.replace(Vec::new());
+        }
+        loop {
+            match self.read_frame() {
+                Ok(Some(frame)) => self.receive_signal(frame),
+                Ok(None) => (),
+                Err(Error::Io(ref error)) if error.kind() == io::ErrorKind::WouldBlock => break,
+                Err(error) => {
+                    eprintln!("Unable to read page state signals: {}", error);
+                    break;
+                },
+            }
+        }
+        if let Err(error) = self.reader.borrow().get_ref().set_nonblocking(false) {
+            eprintln!("Unable to reset page state socket: {}", error);
+        }
+        self.signals.replace(Vec::new())
+    }
+}
@@ -0,0 +1,199 @@
+extern crate brimstone_domain_settings as domain_settings;
+extern crate brimstone_page_state as page_state;
+
+use std::env;
+use std::io;
+use std::path;
+use std::process;
+use std::thread;
+use std::time;
+
+use std::io::BufRead;
+
+use domain_settings::Host;
+use page_state::{Bus, Client, Grant, Outcome, Transport};
+
+/// A private bus daemon that is stopped when dropped.
+struct Daemon {
+    child: process::Child,
+    address: String,
+}
+
+impl Daemon {
+
+    fn start() -> Daemon {
+        let mut child = process::Command::new("dbus-daemon")
+            .args(&["--session", "--nofork", "--print-address=1"])
+            .stdout(process::Stdio::piped())
+            .spawn()
+            .expect("private dbus-daemon startup");
+        let mut address = String::new();
+        io::BufReader::new(child.stdout.take().expect("dbus-daemon output"))
+            .read_line(&mut address)
+            .expect("dbus-daemon address");
+        Daemon {
+            child,
+            address: address.trim().into(),
+        }
+    }
+
+    fn transport(&self) -> Transport { Transport::Bus(Bus::Address(self.address.clone())) }
+}
+
+impl Drop for Daemon {
+
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn socket_path(test: &str) -> path::PathBuf {
+    env::temp_dir().join(format!("brimstone-ipc-{}-{}.sock", process::id(), test))
+}
+
+/// Waits until the server handled everything sent before.
+fn sync(client: &Client) {
+    client.get_grants().expect("round trip");
+}
+
+#[test]
+fn handshake() {
+    let daemon = Daemon::start();
+    let (server, _store) = page_state::run_server(&daemon.transport()).expect("server");
+    assert_eq!(server.transport(), &daemon.transport());
+    Client::connect(server.transport(), server.name()).expect("handshake");
+    assert!(Client::connect(server.transport(), "at.dunkelheit.brimstone.missing").is_err());
+}
+
+#[test]
+fn handshake_socket() {
+    let path = socket_path("handshake");
+    let transport = Transport::Socket(path.clone());
+    {
+        let (server, _store) = page_state::run_server(&transport).expect("server");
+        Client::connect(server.transport(), server.name()).expect("handshake");
+    }
+    let start = time::Instant::now();
+    while path.exists() && start.elapsed() < time::Duration::from_secs(2) {
+        thread::sleep(time::Duration::from_millis(10));
+    }
+    assert!(!path.exists(), "socket removed after quit");
+    assert!(Client::connect(&transport, "").is_err());
+}
+
+#[test]
+fn unreachable() {
+    let bus = Transport::Bus(Bus::Address("unix:path=/nonexistent/brimstone-bus".into()));
+    assert!(page_state::run_server(&bus).is_err());
+    assert!(Client::connect(&bus, "at.dunkelheit.brimstone.missing").is_err());
+    let socket = Transport::Socket("/nonexistent/brimstone.sock".into());
+    assert!(page_state::run_server(&socket).is_err());
+    assert!(Client::connect(&socket, "").is_err());
+}
+
+#[test]
+fn push() {
+    let daemon = Daemon::start();
+    check_push(&daemon.transport());
+}
+
+#[test]
+fn push_socket() {
+    check_push(&Transport::Socket(socket_path("push")));
+}
+
+fn check_push(transport: &Transport) {
+    let (server, store) = page_state::run_server(transport).expect("server");
+    let client = Client::connect(server.transport(), server.name()).expect("client");
+
+    let source = Host::parse("example.com");
+    let cdn = Host::parse("cdn.example.com");
+    let tracker = Host::parse("tracker.test");
+    client.push(1, &source, &cdn, "http://cdn.example.com/a.js", Outcome::Allowed).unwrap();
+    client.push(1, &source, &tracker, "http://tracker.test/t.js", Outcome::Denied).unwrap();
+    client.push(1, &source, &tracker, "http://tracker.test/p.gif", Outcome::Filtered).unwrap();
+    sync(&client);
+
+    let data = store.lock().unwrap().get_data(1).expect("page data");
+    assert_eq!(data.host().as_str(), "example.com");
+    assert_eq!(data.denied().len(), 1);
+    assert_eq!(data.denied()[0].as_str(), "tracker.test");
+    assert_eq!(data.blocked().len(), 2);
+    assert_eq!(data.blocked()[0].uri, "http://tracker.test/t.js");
+    assert_eq!(data.blocked()[1].outcome, Outcome::Filtered);
+    let tracker_stats = data.targets().iter()
+        .find(|stats| stats.host.as_str() == "tracker.test")
+        .expect("tracker stats");
+    assert_eq!((tracker_stats.allowed, tracker_stats.blocked), (0, 2));
+    assert!(store.lock().unwrap().get_data(2).is_none());
+}
+
+#[test]
+fn grants() {
+    let daemon = Daemon::start();
+    check_grants(&daemon.transport());
+}
+
+#[test]
+fn grants_socket() {
+    check_grants(&Transport::Socket(socket_path("grants")));
+}
+
+fn check_grants(transport: &Transport) {
+    let (server, _store) = page_state::run_server(transport).expect("server");
+    let client = Client::connect(server.transport(), server.name()).expect("client");
+    let watcher = Client::connect(server.transport(), server.name()).expect("watcher");
+    let other = Client::connect(server.transport(), server.name()).expect("other");
+    watcher.watch_changes().expect("watch");
+    sync(&watcher);
+
+    let grant = Grant::for_page(1, &Host::parse("example.com"), &Host::parse("cdn.test"));
+    client.add_grant(&grant).unwrap();
+    assert_eq!(client.get_grants().unwrap(), vec![grant.clone()]);
+    sync(&watcher);
+    assert!(watcher.take_changes().grants);
+    assert!(!other.take_changes().grants);
+
+    client.revoke_grant(&grant).unwrap();
+    assert!(client.get_grants().unwrap().is_empty());
+}
+
+#[test]
+fn forget_pages() {
+    let daemon = Daemon::start();
+    check_forget_pages(&daemon.transport());
+}
+
+#[test]
+fn forget_pages_socket() {
+    check_forget_pages(&Transport::Socket(socket_path("forget")));
+}
+
+fn check_forget_pages(transport: &Transport) {
+    let (server, store) = page_state::run_server(transport).expect("server");
+    let client = Client::connect(server.transport(), server.name()).expect("client");
+
+    let source = Host::parse("example.com");
+    let target = Host::parse("cdn.test");
+    for page_id in 1..4 {
+        client.push(page_id, &source, &target, "http://cdn.test/", Outcome::Denied).unwrap();
+        client.add_grant(&Grant::for_page(page_id, &source, &target)).unwrap();
+    }
+
+    client.clear_page(2).unwrap();
+    sync(&client);
+    assert!(store.lock().unwrap().get_data(2).is_none());
+    assert_eq!(client.get_grants().unwrap().len(), 3);
+
+    client.forget_page(1).unwrap();
+    sync(&client);
+    assert!(store.lock().unwrap().get_data(1).is_none());
+    assert_eq!(client.get_grants().unwrap().len(), 2);
+
+    client.retain_pages(&[3]).unwrap();
+    sync(&client);
+    assert!(store.lock().unwrap().get_data(2).is_none());
+    assert!(store.lock().unwrap().get_data(3).is_some());
+    assert_eq!(client.get_grants().unwrap(), vec![Grant::for_page(3, &source, &target)]);
+}
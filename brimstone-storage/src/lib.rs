@@ -1,12 +1,14 @@
-
 pub extern crate rusqlite;
 
 use std::path;
 use std::io;
 use std::fs;
 use std::cell;
+use std::time;
+
+const BASE_VERSION: u32 = 1;
 
-const CURRENT_VERSION: u32 = 1;
+pub type Migration = fn(&mut rusqlite::Transaction) -> Result<(), rusqlite::Error>;
 
 pub fn do_nothing(_: &mut rusqlite::Connection) -> Result<(), rusqlite::Error> { Ok(()) }
 
@@ -15,6 +17,9 @@ pub enum Error {
     Sqlite(rusqlite::Error),
     InvalidPath(path::PathBuf),
     Io(io::Error),
+    UnsupportedVersion { found: u32, supported: u32 },
+    InvalidVersion,
+    Corrupt(path::PathBuf),
 }
 
 impl From<rusqlite::Error> for Error {
@@ -27,39 +32,59 @@ impl From<io::Error> for Error {
     fn from(error: io::Error) -> Error { Error::Io(error) }
 }
 
+#[derive(Debug, Clone)]
+pub struct Recovery {
+    pub moved_to: path::PathBuf,
+    pub tables: Vec<(String, usize)>,
+    pub is_complete: bool,
+}
+
 pub struct Storage {
     conn: cell::RefCell<rusqlite::Connection>,
+    recovery: Option<Recovery>,
 }
 
 impl Storage {
 
-    pub fn open_in_memory<FI>(init: FI) -> Result<Storage, Error>
+    pub fn open_in_memory<FI>(init: FI, migrations: &[Migration]) -> Result<Storage, Error>
     where
         FI: FnOnce(&mut rusqlite::Connection) -> Result<(), rusqlite::Error>,
     {
         let mut conn = rusqlite::Connection::open_in_memory()?;
         common_init(&mut conn)?;
         init(&mut conn)?;
+        migrate(&mut conn, migrations)?;
         let conn = cell::RefCell::new(conn);
-        Ok(Storage { conn })
+        Ok(Storage { conn, recovery: None })
     }
 
-    pub fn open<P, FP>(path: P, prepare: FP) -> Result<Storage, Error>
+    pub fn open<P, FP>(path: P, migrations: &[Migration], prepare: FP) -> Result<Storage, Error>
     where
         P: AsRef<path::Path>,
         FP: FnOnce(&mut rusqlite::Connection) -> Result<(), rusqlite::Error>,
     {
+        let path = path.as_ref();
+
+        if !is_intact(path)? {
+            return Err(Error::Corrupt(path.into()));
+        }
         let mut conn = rusqlite::Connection::open_with_flags(
             path,
             rusqlite::OpenFlags::SQLITE_OPEN_READ_WRITE,
         )?;
+        migrate(&mut conn, migrations)?;
         common_prepare(&mut conn)?;
         prepare(&mut conn)?;
         let conn = cell::RefCell::new(conn);
-        Ok(Storage { conn })
+        Ok(Storage { conn, recovery: None })
     }
 
-    pub fn open_or_create<P, FI, FP>(path: P, init: FI, prepare: FP) -> Result<Storage, Error>
+    pub fn open_or_create<P, FI, FP>(
+        path: P,
+        init: FI,
+        migrations: &[Migration],
+        prepare: FP,
+    ) -> Result<Storage, Error>
     where
         P: AsRef<path::Path>,
         FI: FnOnce(&mut rusqlite::Connection) -> Result<(), rusqlite::Error>,
@@ -67,26 +92,52 @@ impl Storage {
     {
         let path = path.as_ref();
 
+        let recovery =
+            if path.exists() && !is_intact(path)? {
+                Some(recover(path)?)
+            } else {
+                None
+            };
+
         if path.exists() {
             let mut conn = rusqlite::Connection::open_with_flags(
                 path,
                 rusqlite::OpenFlags::SQLITE_OPEN_READ_WRITE,
             )?;
+            migrate(&mut conn, migrations)?;
             common_prepare(&mut conn)?;
             prepare(&mut conn)?;
             let conn = cell::RefCell::new(conn);
-            Ok(Storage { conn })
+            Ok(Storage { conn, recovery })
         } else {
             let parent = path.parent().ok_or_else(|| Error::InvalidPath(path.into()))?;
             fs::create_dir_all(parent)?;
             let mut conn = rusqlite::Connection::open(path)?;
             common_init(&mut conn)?;
             init(&mut conn)?;
+            migrate(&mut conn, migrations)?;
             let conn = cell::RefCell::new(conn);
-            Ok(Storage { conn })
+            Ok(Storage { conn, recovery })
         }
     }
 
+    pub fn recovery(&self) -> Option<&Recovery> { self.recovery.as_ref() }
+
+    pub fn backup_to<P>(&self, path: P) -> Result<(), Error>
+    where P: AsRef<path::Path> {
+        let path = path.as_ref();
+        let parent = path.parent().ok_or_else(|| Error::InvalidPath(path.into()))?;
+        fs::create_dir_all(parent)?;
+        let conn = self.conn.borrow();
+        conn.backup(rusqlite::DatabaseName::Main, path, None)?;
+        Ok(())
+    }
+
+    pub fn version(&self) -> Result<u32, Error> {
+        let conn = self.conn.borrow();
+        read_version(&conn)
+    }
+
     pub fn with_connection<F, R>(&self, body: F) -> Result<R, Error>
     where F: FnOnce(&rusqlite::Connection) -> Result<R, rusqlite::Error> {
         let conn = self.conn.borrow();
@@ -104,9 +155,22 @@ impl Storage {
     }
 }
 
+pub fn restore<P, S>(path: P, snapshot: S) -> Result<(), Error>
+where
+    P: AsRef<path::Path>,
+    S: AsRef<path::Path>,
+{
+    let path = path.as_ref();
+    let parent = path.parent().ok_or_else(|| Error::InvalidPath(path.into()))?;
+    fs::create_dir_all(parent)?;
+    let mut conn = rusqlite::Connection::open(path)?;
+    conn.restore(rusqlite::DatabaseName::Main, snapshot, None)?;
+    Ok(())
+}
+
 fn common_init(conn: &mut rusqlite::Connection) -> Result<(), rusqlite::Error> {
     conn.execute("CREATE TABLE app_version (version INTEGER NOT NULL)", &[])?;
-    conn.execute("INSERT INTO app_version (version) VALUES (?)", &[&CURRENT_VERSION])?;
+    conn.execute("INSERT INTO app_version (version) VALUES (?)", &[&BASE_VERSION])?;
     Ok(())
 }
 
@@ -115,3 +179,443 @@ fn common_prepare(conn: &mut rusqlite::Connection) -> Result<(), rusqlite::Error
     Ok(())
 }
 
+fn is_corruption(error: &rusqlite::Error) -> bool {
+    match *error {
+        rusqlite::Error::SqliteFailure(ref failure, _) => match failure.code {
+            rusqlite::ffi::ErrorCode::DatabaseCorrupt
+            | rusqlite::ffi::ErrorCode::NotADatabase => true,
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+fn is_intact(path: &path::Path) -> Result<bool, Error> {
+
+    fn quick_check(path: &path::Path) -> Result<bool, rusqlite::Error> {
+        let conn = rusqlite::Connection::open_with_flags(
+            path,
+            rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY,
+        )?;
+        let result: String = conn.query_row("PRAGMA quick_check", &[], |row| row.get(0))?;
+        Ok(result == "ok")
+    }
+
+    match quick_check(path) {
+        Ok(is_intact) => Ok(is_intact),
+        Err(ref error) if is_corruption(error) => Ok(false),
+        Err(error) => Err(error.into()),
+    }
+}
+
+fn recover(path: &path::Path) -> Result<Recovery, Error> {
+    let salvage_path = path.with_extension("salvage");
+    if salvage_path.exists() {
+        fs::remove_file(&salvage_path)?;
+    }
+
+    // without a valid version the salvaged schema can't be migrated, so
+    // recovery starts over with a fresh database and no salvaged tables
+    let (tables, is_complete) = match salvage(path, &salvage_path) {
+        Ok(salvaged) => salvaged,
+        Err(_) => {
+            if salvage_path.exists() {
+                fs::remove_file(&salvage_path)?;
+            }
+            (Vec::new(), false)
+        },
+    };
+
+    let timestamp = time::SystemTime::now()
+        .duration_since(time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+    let moved_to = path.with_extension(format!("corrupt-{}", timestamp));
+    fs::rename(path, &moved_to)?;
+    if salvage_path.exists() {
+        fs::rename(&salvage_path, path)?;
+    }
+
+    Ok(Recovery { moved_to, tables, is_complete })
+}
+
+/// Copies the readable schema and rows into a new database. Fails when the
+/// copy doesn't end up with exactly one valid version row.
+fn salvage(source: &path::Path, target: &path::Path)
+-> Result<(Vec<(String, usize)>, bool), Error> {
+
+    let broken = rusqlite::Connection::open_with_flags(
+        source,
+        rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY,
+    )?;
+    let mut fresh = rusqlite::Connection::open(target)?;
+
+    let mut schema = Vec::new();
+    {
+        let mut stmt = broken.prepare("
+            SELECT type, name, sql
+            FROM sqlite_master
+            WHERE sql IS NOT NULL
+            ORDER BY type = 'index'
+        ")?;
+        let mut rows = stmt.query(&[])?;
+        while let Some(row) = rows.next() {
+            let row = row?;
+            let kind: String = row.get_checked(0)?;
+            let name: String = row.get_checked(1)?;
+            let sql: String = row.get_checked(2)?;
+            schema.push((kind, name, sql));
+        }
+    }
+
+    let virtual_tables = schema.iter()
+        .filter(|&&(_, _, ref sql)| sql.to_uppercase().starts_with("CREATE VIRTUAL TABLE"))
+        .map(|&(_, ref name, _)| format!("{}_", name))
+        .collect::<Vec<_>>();
+    let is_internal = |name: &str| {
+        name.starts_with("sqlite_")
+            || virtual_tables.iter().any(|prefix| name.starts_with(prefix.as_str()))
+    };
+
+    let tx = fresh.transaction()?;
+    let mut is_complete = true;
+    for &(_, ref name, ref sql) in &schema {
+        if !is_internal(name) && tx.execute(sql, &[]).is_err() {
+            is_complete = false;
+        }
+    }
+
+    let mut tables = Vec::new();
+    for &(ref kind, ref name, _) in &schema {
+        if kind != "table" || is_internal(name) {
+            continue;
+        }
+        match salvage_table(&broken, &tx, name) {
+            Ok((count, is_table_complete)) => {
+                tables.push((name.clone(), count));
+                is_complete = is_complete && is_table_complete;
+            },
+            Err(_) => is_complete = false,
+        }
+    }
+    read_version(&tx)?;
+    tx.commit()?;
+
+    Ok((tables, is_complete))
+}
+
+/// Copies the readable rows of a table. Rows that can't be read or stored
+/// are skipped, reading stops at the first damaged page.
+fn salvage_table(broken: &rusqlite::Connection, tx: &rusqlite::Transaction, name: &str)
+-> Result<(usize, bool), rusqlite::Error> {
+    use rusqlite::types::{ Value, ToSql };
+
+    let mut select = broken.prepare(&format!("SELECT * FROM \"{}\"", name))?;
+    let columns = select.column_count();
+    let mut insert = tx.prepare(&format!(
+        "INSERT OR IGNORE INTO \"{}\" VALUES ({})",
+        name,
+        vec!["?"; columns as usize].join(", "),
+    ))?;
+    let mut rows = select.query(&[])?;
+    let mut count = 0;
+    let mut is_complete = true;
+    loop {
+        let row = match rows.next() {
+            Some(Ok(row)) => row,
+            Some(Err(_)) => {
+                is_complete = false;
+                break;
+            },
+            None => break,
+        };
+        let values = (0..columns)
+            .map(|index| row.get_checked(index))
+            .collect::<Result<Vec<Value>, _>>();
+        let values = match values {
+            Ok(values) => values,
+            Err(_) => {
+                is_complete = false;
+                continue;
+            },
+        };
+        let params = values.iter().map(|value| value as &ToSql).collect::<Vec<_>>();
+        match insert.execute(&params) {
+            Ok(_) => count += 1,
+            Err(_) => is_complete = false,
+        }
+    }
+    Ok((count, is_complete))
+}
+
+fn read_version(conn: &rusqlite::Connection) -> Result<u32, Error> {
+    let mut stmt = conn.prepare("SELECT version FROM app_version")?;
+    let mut rows = stmt.query(&[])?;
+    let version: u32 = match rows.next() {
+        Some(row) => row?.get_checked(0)?,
+        None => return Err(Error::InvalidVersion),
+    };
+    if rows.next().is_some() || version < BASE_VERSION {
+        return Err(Error::InvalidVersion);
+    }
+    Ok(version)
+}
+
+fn migrate(conn: &mut rusqlite::Connection, migrations: &[Migration]) -> Result<(), Error> {
+    let supported = BASE_VERSION + migrations.len() as u32;
+    let found = read_version(conn)?;
+
+    if found > supported {
+        return Err(Error::UnsupportedVersion { found, supported });
+    }
+    if found == supported {
+        return Ok(());
+    }
+
+    let mut tx = conn.transaction()?;
+    for migration in &migrations[(found - BASE_VERSION) as usize..] {
+        migration(&mut tx)?;
+    }
+    tx.execute("UPDATE app_version SET version = ?", &[&supported])?;
+    tx.commit()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::io;
+    use std::path;
+    use std::process;
+
+    use super::*;
+
+    fn temp_path(name: &str) -> path::PathBuf {
+        let path = env::temp_dir()
+            .join(format!("brimstone-storage-test-{}", process::id()))
+            .join(name);
+        if path.exists() {
+            fs::remove_file(&path).unwrap();
+        }
+        path
+    }
+
+    fn init(conn: &mut rusqlite::Connection) -> Result<(), rusqlite::Error> {
+        conn.execute("CREATE TABLE items (name TEXT NOT NULL)", &[])?;
+        Ok(())
+    }
+
+    fn add_value(tx: &mut rusqlite::Transaction) -> Result<(), rusqlite::Error> {
+        tx.execute("ALTER TABLE items ADD COLUMN value INTEGER", &[])?;
+        Ok(())
+    }
+
+    fn add_index(tx: &mut rusqlite::Transaction) -> Result<(), rusqlite::Error> {
+        tx.execute("CREATE UNIQUE INDEX idx_items ON items (name)", &[])?;
+        Ok(())
+    }
+
+    fn failing(tx: &mut rusqlite::Transaction) -> Result<(), rusqlite::Error> {
+        tx.execute("ALTER TABLE missing ADD COLUMN value INTEGER", &[])?;
+        Ok(())
+    }
+
+    #[test]
+    fn in_memory_runs_all_migrations() {
+        let storage = Storage::open_in_memory(init, &[add_value, add_index]).unwrap();
+        assert_eq!(storage.version().unwrap(), 3);
+        storage.with_connection(|conn| {
+            conn.execute("INSERT INTO items (name, value) VALUES (?, ?)", &[&"a", &23])
+        }).unwrap();
+    }
+
+    #[test]
+    fn pending_migrations() {
+        let path = temp_path("pending.db");
+
+        let storage = Storage::open_or_create(&path, init, &[], do_nothing).unwrap();
+        assert_eq!(storage.version().unwrap(), 1);
+        drop(storage);
+
+        let storage = Storage::open_or_create(&path, init, &[add_value], do_nothing).unwrap();
+        assert_eq!(storage.version().unwrap(), 2);
+        drop(storage);
+
+        let storage = Storage::open(&path, &[add_value, add_index], do_nothing).unwrap();
+        assert_eq!(storage.version().unwrap(), 3);
+        storage.with_connection(|conn| {
+            conn.execute("INSERT INTO items (name, value) VALUES (?, ?)", &[&"a", &23])
+        }).unwrap();
+    }
+
+    #[test]
+    fn downgrade() {
+        let path = temp_path("downgrade.db");
+
+        let storage = Storage::open_or_create(&path, init, &[add_value], do_nothing).unwrap();
+        drop(storage);
+
+        match Storage::open(&path, &[], do_nothing) {
+            Err(Error::UnsupportedVersion { found: 2, supported: 1 }) => (),
+            Err(other) => panic!("unexpected error: {:?}", other),
+            Ok(_) => panic!("downgrade was accepted"),
+        }
+    }
+
+    #[test]
+    fn invalid_version() {
+        let path = temp_path("invalid.db");
+
+        let storage = Storage::open_or_create(&path, init, &[], do_nothing).unwrap();
+        storage.with_connection(|conn| conn.execute("DELETE FROM app_version", &[])).unwrap();
+        drop(storage);
+
+        match Storage::open(&path, &[], do_nothing) {
+            Err(Error::InvalidVersion) => (),
+            Err(other) => panic!("unexpected error: {:?}", other),
+            Ok(_) => panic!("missing version was accepted"),
+        }
+    }
+
+    #[test]
+    fn backup_and_restore() {
+        let path = temp_path("original.db");
+        let snapshot = temp_path("snapshot.db");
+
+        let storage = Storage::open_or_create(&path, init, &[add_value], do_nothing).unwrap();
+        storage.with_connection(|conn| {
+            conn.execute("INSERT INTO items (name, value) VALUES (?, ?)", &[&"a", &23])
+        }).unwrap();
+        storage.backup_to(&snapshot).unwrap();
+        storage.with_connection(|conn| conn.execute("DELETE FROM items", &[])).unwrap();
+        drop(storage);
+
+        restore(&path, &snapshot).unwrap();
+
+        let storage = Storage::open(&path, &[add_value], do_nothing).unwrap();
+        let value: i64 = storage.with_connection(|conn| {
+            conn.query_row("SELECT value FROM items WHERE name = ?", &[&"a"], |row| row.get(0))
+        }).unwrap();
+        assert_eq!(value, 23);
+    }
+
+    #[test]
+    fn recover_unreadable() {
+        let path = temp_path("unreadable.db");
+
+        let storage = Storage::open_or_create(&path, init, &[], do_nothing).unwrap();
+        assert!(storage.recovery().is_none());
+        drop(storage);
+
+        fs::write(&path, &[0xAB; 4096][..]).unwrap();
+        match Storage::open(&path, &[], do_nothing) {
+            Err(Error::Corrupt(_)) => (),
+            Err(other) => panic!("unexpected error: {:?}", other),
+            Ok(_) => panic!("corrupt file was accepted"),
+        }
+
+        let storage = Storage::open_or_create(&path, init, &[add_value], do_nothing).unwrap();
+        let recovery = storage.recovery().cloned().expect("recovery report");
+        assert!(recovery.moved_to.exists());
+        assert!(!recovery.is_complete);
+        assert_eq!(storage.version().unwrap(), 2);
+        storage.with_connection(|conn| {
+            conn.execute("INSERT INTO items (name, value) VALUES (?, ?)", &[&"a", &23])
+        }).unwrap();
+        fs::remove_file(&recovery.moved_to).unwrap();
+    }
+
+    #[test]
+    fn recover_damaged_table() {
+        use std::io::{ Seek, Write };
+
+        let path = temp_path("damaged.db");
+
+        let storage = Storage::open_or_create(&path, init, &[], do_nothing).unwrap();
+        let (page_size, root_page) = storage.with_connection(|conn| {
+            conn.execute("CREATE TABLE notes (text TEXT NOT NULL)", &[])?;
+            for index in 0..100 {
+                let name = format!("item-{}", index);
+                let text = format!("note-{}", index);
+                conn.execute("INSERT INTO items (name) VALUES (?)", &[&name])?;
+                conn.execute("INSERT INTO notes (text) VALUES (?)", &[&text])?;
+            }
+            let page_size: i64 = conn.query_row("PRAGMA page_size", &[], |row| row.get(0))?;
+            let root_page: i64 = conn.query_row(
+                "SELECT rootpage FROM sqlite_master WHERE name = 'items'",
+                &[],
+                |row| row.get(0),
+            )?;
+            Ok((page_size, root_page))
+        }).unwrap();
+        drop(storage);
+
+        let mut file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.seek(io::SeekFrom::Start(((root_page - 1) * page_size) as u64)).unwrap();
+        file.write_all(&vec![0xAB; page_size as usize]).unwrap();
+        drop(file);
+
+        let storage = Storage::open_or_create(&path, init, &[], do_nothing).unwrap();
+        let recovery = storage.recovery().cloned().expect("recovery report");
+        assert!(!recovery.is_complete);
+        assert!(recovery.tables.contains(&("notes".into(), 100)));
+        let count: i64 = storage.with_connection(|conn| {
+            conn.query_row("SELECT COUNT(*) FROM notes", &[], |row| row.get(0))
+        }).unwrap();
+        assert_eq!(count, 100);
+        fs::remove_file(&recovery.moved_to).unwrap();
+    }
+
+    #[test]
+    fn recover_without_version() {
+        use std::io::{ Seek, Write };
+
+        let path = temp_path("unversioned.db");
+
+        let storage = Storage::open_or_create(&path, init, &[], do_nothing).unwrap();
+        let (page_size, root_page) = storage.with_connection(|conn| {
+            for index in 0..100 {
+                let name = format!("item-{}", index);
+                conn.execute("INSERT INTO items (name) VALUES (?)", &[&name])?;
+            }
+            conn.execute("DELETE FROM app_version", &[])?;
+            let page_size: i64 = conn.query_row("PRAGMA page_size", &[], |row| row.get(0))?;
+            let root_page: i64 = conn.query_row(
+                "SELECT rootpage FROM sqlite_master WHERE name = 'items'",
+                &[],
+                |row| row.get(0),
+            )?;
+            Ok((page_size, root_page))
+        }).unwrap();
+        drop(storage);
+
+        let mut file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.seek(io::SeekFrom::Start(((root_page - 1) * page_size) as u64)).unwrap();
+        file.write_all(&vec![0xAB; page_size as usize]).unwrap();
+        drop(file);
+
+        let storage = Storage::open_or_create(&path, init, &[add_value], do_nothing).unwrap();
+        let recovery = storage.recovery().cloned().expect("recovery report");
+        assert!(!recovery.is_complete);
+        assert!(recovery.tables.is_empty());
+        assert_eq!(storage.version().unwrap(), 2);
+        storage.with_connection(|conn| {
+            conn.execute("INSERT INTO items (name, value) VALUES (?, ?)", &[&"a", &23])
+        }).unwrap();
+        fs::remove_file(&recovery.moved_to).unwrap();
+    }
+
+    #[test]
+    fn failed_migration_rolls_back() {
+        let path = temp_path("rollback.db");
+
+        let storage = Storage::open_or_create(&path, init, &[], do_nothing).unwrap();
+        drop(storage);
+
+        assert!(Storage::open(&path, &[add_value, failing], do_nothing).is_err());
+
+        let storage = Storage::open(&path, &[], do_nothing).unwrap();
+        assert_eq!(storage.version().unwrap(), 1);
+    }
+}
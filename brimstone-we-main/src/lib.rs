@@ -1,5 +1,6 @@
 
 #[macro_use] extern crate webkit2gtk_webextension;
+extern crate webkit2gtk_webextension_sys;
 
 extern crate serde;
 extern crate serde_json;
@@ -9,6 +10,17 @@ extern crate brimstone_domain_settings as domain_settings;
 extern crate brimstone_page_state as page_state;
 
 use std::rc;
+use std::cell;
+use std::ffi;
+use std::os::raw;
+
+#[link(name = "soup-2.4")]
+extern "C" {
+    fn soup_message_headers_get_one(
+        headers: *mut raw::c_void,
+        name: *const raw::c_char,
+    ) -> *const raw::c_char;
+}
 
 web_extension_init_with_data!();
 
@@ -22,20 +34,47 @@ pub fn web_extension_initialize(
     let init_args: page_state::InitArguments = serde_json::from_str(user_data)
         .expect("web process initialization arguments deserialization");
 
-    let page_state_client = rc::Rc::new(page_state::Client::new(&init_args.instance));
+    if let Some(ref path) = init_args.suffix_list_path {
+        match domain_settings::suffix::SuffixList::load(path) {
+            Ok(list) => domain_settings::suffix::install(list),
+            Err(error) => eprintln!("Public suffix list error: {}", error),
+        }
+    }
 
     let domains = domain_settings::Settings::open(&init_args.domain_settings_path)
         .map(rc::Rc::new)
         .unwrap();
 
+    let page_state_client = page_state::Client::connect(
+        &init_args.transport,
+        &init_args.instance,
+    );
+    let page_state_client = match page_state_client {
+        Ok(client) => Some(rc::Rc::new(client)),
+        Err(error) => {
+            eprintln!("Page state connection error: {}", error);
+            None
+        },
+    };
+    if let Some(ref client) = page_state_client {
+        if let Err(error) = client.watch_changes() {
+            eprintln!("Page state watch error: {}", error);
+        }
+    }
+    let grants = rc::Rc::new(cell::RefCell::new(
+        page_state_client.as_ref().map(|client| get_grants(client)).unwrap_or_else(Vec::new),
+    ));
+
     extension.connect_page_created({
         let domains = domains.clone();
         let page_state_client = page_state_client.clone();
+        let grants = grants.clone();
         move |_extension, page| {
 
             page.connect_send_request({
                 let domains = domains.clone();
                 let page_state_client = page_state_client.clone();
+                let grants = grants.clone();
                 move |page, request, _redir_response| {
                     
                     let source_uri = page.get_uri();
@@ -46,23 +85,72 @@ pub fn web_extension_initialize(
                         .and_then(|uri| parse_uri(uri))
                         .and_then(|uri| domain_settings::Host::from_uri(&uri));
 
-                    let target_host = target_uri
-                        .as_ref()
-                        .and_then(|uri| parse_uri(uri))
-                        .and_then(|uri| domain_settings::Host::from_uri(&uri));
+                    let target_uri = match target_uri.as_ref().and_then(|uri| parse_uri(uri)) {
+                        Some(target_uri) => target_uri,
+                        None => return false,
+                    };
+                    let target_host = domain_settings::Host::from_uri(&target_uri);
+
+                    if let Some(ref client) = page_state_client {
+                        let changes = client.take_changes();
+                        if changes.rules {
+                            if let Err(error) = domains.reload() {
+                                eprintln!("Domain settings reload error: {:?}", error);
+                            }
+                        }
+                        if changes.grants {
+                            *grants.borrow_mut() = get_grants(client);
+                        }
+                    }
+
+                    let kind = domain_settings::ResourceType::classify(
+                        request_header(request, "Accept").as_ref().map(|value| value.as_str()),
+                        request_header(request, "X-Requested-With").as_ref().map(|value| value.as_str()),
+                        target_uri.path(),
+                    );
+
+                    let is_filtered = domains.is_filtered(&domain_settings::filter::Request::new(
+                        &target_uri,
+                        source_host.as_ref(),
+                        kind,
+                    ));
 
+                    let page_id = page.get_id();
                     let (source_host, target_host) = match (source_host, target_host) {
                         (Some(source_host), Some(target_host)) => (source_host, target_host),
-                        _ => return false,
+                        _ => return is_filtered,
                     };
 
-                    if source_host.is_related_to(&target_host) {
-                        return false;
+                    if is_filtered {
+                        push(
+                            page_state_client.as_ref(),
+                            page_id,
+                            &source_host,
+                            &target_host,
+                            target_uri.as_str(),
+                            page_state::Outcome::Filtered,
+                        );
+                        return true;
                     }
 
-                    let allowed = domains.can_request(&source_host, &target_host);
-
-                    page_state_client.push(page.get_id(), &source_host, &target_host, allowed);
+                    let is_granted = grants.borrow().iter()
+                        .any(|grant| grant.applies(page_id, &source_host, &target_host));
+                    let allowed = source_host.is_same_site(&target_host)
+                        || is_granted
+                        || domains.can_request(&source_host, &target_host, kind);
+
+                    push(
+                        page_state_client.as_ref(),
+                        page_id,
+                        &source_host,
+                        &target_host,
+                        target_uri.as_str(),
+                        if allowed {
+                            page_state::Outcome::Allowed
+                        } else {
+                            page_state::Outcome::Denied
+                        },
+                    );
                     !allowed
                 }
             });
@@ -70,6 +158,55 @@ pub fn web_extension_initialize(
     });
 }
 
+fn get_grants(client: &page_state::Client) -> Vec<page_state::Grant> {
+    client.get_grants().unwrap_or_else(|error| {
+        eprintln!("Page state grants error: {}", error);
+        Vec::new()
+    })
+}
+
+/// Without a page state connection requests are still filtered, but
+/// nothing is recorded.
+fn push(
+    client: Option<&rc::Rc<page_state::Client>>,
+    page_id: u64,
+    source: &domain_settings::Host,
+    target: &domain_settings::Host,
+    uri: &str,
+    outcome: page_state::Outcome,
+) {
+    let client = match client {
+        Some(client) => client,
+        None => return,
+    };
+    if let Err(error) = client.push(page_id, source, target, uri, outcome) {
+        eprintln!("Page state push error: {}", error);
+    }
+}
+
+fn request_header(
+    request: &webkit2gtk_webextension::URIRequest,
+    name: &str,
+) -> Option<String> {
+    use glib::translate::ToGlibPtr;
+
+    let name = ffi::CString::new(name).expect("header name without nul bytes");
+    unsafe {
+        let headers = webkit2gtk_webextension_sys::webkit_uri_request_get_http_headers(
+            request.to_glib_none().0,
+        );
+        if headers.is_null() {
+            return None;
+        }
+        let value = soup_message_headers_get_one(headers as *mut raw::c_void, name.as_ptr());
+        if value.is_null() {
+            None
+        } else {
+            Some(ffi::CStr::from_ptr(value).to_string_lossy().into_owned())
+        }
+    }
+}
+
 fn parse_uri(uri: &str) -> Option<url::Url> {
     match url::Url::parse(uri) {
         Ok(uri) => Some(uri),
@@ -7,7 +7,10 @@ use gtk;
 use webkit2gtk;
 
 use app_action;
+use backup;
 use bookmarks;
+use cookies;
+use domain_rules;
 use domain_settings;
 use history;
 use main_paned;
@@ -18,9 +21,12 @@ use page_state;
 use page_store;
 use page_tree_view;
 use profile;
+use requests;
 use session;
 use shortcuts;
+use snapshots;
 use status_bar;
+use storage;
 use stored;
 use webview;
 use window;
@@ -55,20 +61,95 @@ fn arg_extract_value(args: &mut Vec<String>, name: &str)
 #[derive(Debug, Clone)]
 pub enum ArgumentError {
     MissingValue(String),
+    InvalidValue(String),
     UnclearProfileParameters,
     MissingProfileParameter,
+    UnclearCommandParameters,
+}
+
+#[derive(Debug, Clone)]
+pub enum Command {
+    Run,
+    ListSnapshots,
+    RestoreSnapshot {
+        name: String,
+        database: Option<String>,
+    },
+    ExportDomainRules {
+        path: String,
+    },
+    ImportDomainRules {
+        path: String,
+        mode: domain_settings::portable::Mode,
+    },
+    ExportSession {
+        path: String,
+    },
+    ImportSession {
+        path: String,
+        parent: Option<page_store::Id>,
+    },
 }
 
 #[derive(Debug, Clone)]
 pub struct Arguments {
     is_private: bool,
     profile_mode: profile::Mode,
+    session: Option<String>,
+    command: Command,
 }
 
 impl Arguments {
 
     pub fn extract(args: &mut Vec<String>) -> Result<Arguments, ArgumentError> {
         let is_private = arg_extract_flag(args, "--private");
+        let session = arg_extract_value(args, "--session")?;
+        let command = {
+            let list_snapshots = arg_extract_flag(args, "--list-snapshots");
+            let restore = arg_extract_value(args, "--restore-profile")?;
+            let restore_database = arg_extract_value(args, "--restore-database")?;
+            let export_session = arg_extract_value(args, "--export-session")?;
+            let import_session = arg_extract_value(args, "--import-session")?;
+            let import_parent = match arg_extract_value(args, "--import-parent")? {
+                None => None,
+                Some(id) => match id.parse() {
+                    Ok(id) => Some(id),
+                    Err(_) => return Err(ArgumentError::InvalidValue("--import-parent".into())),
+                },
+            };
+            let rules_command = {
+                use domain_settings::portable::Mode;
+
+                let export = arg_extract_value(args, "--export-domain-rules")?;
+                let import = arg_extract_value(args, "--import-domain-rules")?;
+                let replace = arg_extract_value(args, "--replace-domain-rules")?;
+                match (export, import, replace) {
+                    (None, None, None) => None,
+                    (Some(path), None, None) =>
+                        Some(Command::ExportDomainRules { path }),
+                    (None, Some(path), None) =>
+                        Some(Command::ImportDomainRules { path, mode: Mode::Merge }),
+                    (None, None, Some(path)) =>
+                        Some(Command::ImportDomainRules { path, mode: Mode::Replace }),
+                    _ => return Err(ArgumentError::UnclearCommandParameters),
+                }
+            };
+            let session_command = match (export_session, import_session, import_parent) {
+                (None, None, None) => None,
+                (Some(path), None, None) => Some(Command::ExportSession { path }),
+                (None, Some(path), parent) => Some(Command::ImportSession { path, parent }),
+                _ => return Err(ArgumentError::UnclearCommandParameters),
+            };
+            match (list_snapshots, restore, restore_database, rules_command, session_command) {
+                (false, None, None, None, None) => Command::Run,
+                (true, None, None, None, None) => Command::ListSnapshots,
+                (false, Some(name), database, None, None) =>
+                    Command::RestoreSnapshot { name, database },
+                (false, None, None, Some(command), None) => command,
+                (false, None, None, None, Some(command)) => command,
+                _ => return Err(ArgumentError::UnclearCommandParameters),
+            }
+        };
         let profile_mode = {
             let profile_custom = arg_extract_value(args, "--profile-custom")?;
             let profile_xdg = arg_extract_flag(args, "--profile-xdg");
@@ -84,8 +165,17 @@ impl Arguments {
         Ok(Arguments {
             is_private,
             profile_mode,
+            session,
+            command,
         })
     }
+
+    pub fn profile_mode(&self) -> &profile::Mode { &self.profile_mode }
+
+    /// Name of the session to open, it is created if it doesn't exist.
+    pub fn session(&self) -> Option<&str> { self.session.as_ref().map(|name| name.as_str()) }
+
+    pub fn command(&self) -> &Command { &self.command }
 }
 
 struct Data {
@@ -114,9 +204,13 @@ struct Data {
     shortcuts: rc::Rc<shortcuts::Shortcuts>,
     bookmarks: rc::Rc<bookmarks::Bookmarks>,
     domain_settings: rc::Rc<domain_settings::Settings>,
+    profile: rc::Rc<profile::Profile>,
+    recovered_storage: Vec<(&'static str, storage::Recovery)>,
+    suffix_list_error: Option<String>,
     is_private: bool,
     #[allow(unused)] page_state_server: page_state::Server,
     page_state_store: sync::Arc<sync::Mutex<page_state::Store>>,
+    page_state_client: rc::Rc<page_state::Client>,
 }
 
 pub struct Application {
@@ -130,6 +224,25 @@ impl Application {
         let profile = profile::Profile::new(&app_args.profile_mode);
         log_trace!("profile {:#?}", profile);
 
+        let suffix_list_error = profile.suffix_list().and_then(|path| {
+            match domain_settings::suffix::SuffixList::load(path) {
+                Ok(list) => {
+                    log_debug!("using public suffix list {:?} ({} rules)", path, list.len());
+                    domain_settings::suffix::install(list);
+                    None
+                },
+                Err(error) => {
+                    eprintln!("Unable to load public suffix list {:?}: {}", path, error);
+                    Some(format!(
+                        "The public suffix list at {} could not be loaded, \
+                            the built-in list is used instead: {}",
+                        path.display(),
+                        error,
+                    ))
+                },
+            }
+        });
+
         let history = history::History::open_or_create(
             profile.history(),
             if app_args.is_private {
@@ -152,10 +265,53 @@ impl Application {
             } else {
                 let session = session::Session::open_or_create(profile.session())
                     .expect("session storage access in application setup");
+                if let Some(name) = app_args.session() {
+                    let id = match session.find(name)
+                        .expect("session lookup in application setup")
+                    {
+                        Some(id) => id,
+                        None => session.create(name)
+                            .expect("session creation in application setup"),
+                    };
+                    session.switch_to(id)
+                        .expect("session switch in application setup");
+                }
                 page_store::Store::new_stateful(session)
             };
 
-        let (page_state_server, page_state_store) = page_state::run_server();
+        let recovered_storage = vec![
+            ("history", history.recovery().cloned()),
+            ("domain settings", domains.recovery().cloned()),
+            ("shortcuts", shortcuts.recovery().cloned()),
+            ("bookmarks", bookmarks.recovery().cloned()),
+            ("session", page_store.session().and_then(|session| session.recovery()).cloned()),
+        ]
+            .into_iter()
+            .filter_map(|(name, recovery)| recovery.map(|recovery| (name, recovery)))
+            .collect::<Vec<_>>();
+
+        let (page_state_server, page_state_store) =
+            page_state::run_server(&page_state::Transport::Bus(page_state::Bus::Session))
+                .or_else(|error| {
+                    eprintln!("Session bus unavailable, using a page state socket: {}", error);
+                    page_state::socket_path().and_then(|path| {
+                        page_state::run_server(&page_state::Transport::Socket(path))
+                    })
+                })
+                .expect("page state server startup in application setup");
+        let page_state_client = page_state::Client::connect(
+            page_state_server.transport(),
+            page_state_server.name(),
+        ).map(rc::Rc::new).expect("page state client connection in application setup");
+
+        domains.connect_changed({
+            let page_state_client = page_state_client.clone();
+            move || {
+                if let Err(error) = page_state_client.notify_rules_changed() {
+                    eprintln!("Unable to announce domain rule changes: {}", error);
+                }
+            }
+        });
 
         let count = page_store.get_count();
         log_trace!("page store count is {}", count);
@@ -170,9 +326,16 @@ impl Application {
                 view_space: gtk::Box::new(gtk::Orientation::Horizontal, 0),
                 web_context: webview::create_web_context(
                     app_args.is_private,
+                    profile.cookies(),
+                    domains.cookie_policy(),
                     page_state::InitArguments {
                         instance: page_state_server.name().into(),
+                        transport: page_state_server.transport().clone(),
                         domain_settings_path: profile.domain_settings().into(),
+                        suffix_list_path: match suffix_list_error {
+                            Some(_) => None,
+                            None => profile.suffix_list().map(Into::into),
+                        },
                     },
                 ),
                 user_content_manager: webview::create_user_content_manager(),
@@ -193,9 +356,13 @@ impl Application {
                 shortcuts: rc::Rc::new(shortcuts),
                 bookmarks: rc::Rc::new(bookmarks),
                 domain_settings: rc::Rc::new(domains),
+                profile: rc::Rc::new(profile),
+                recovered_storage,
+                suffix_list_error,
                 is_private: app_args.is_private,
                 page_state_server,
                 page_state_store,
+                page_state_client,
             }),
         };
 
@@ -215,7 +382,12 @@ impl Application {
         shortcuts::setup(&app_handle);
         history::setup(&app_handle);
         bookmarks::setup(&app_handle);
+        domain_rules::setup(&app_handle);
+        cookies::setup(&app_handle);
+        requests::setup(&app_handle);
+        snapshots::setup(&app_handle);
         stored::setup(&app_handle);
+        backup::setup(&app_handle);
 
         if count == 0 {
             app_handle.page_store()
@@ -353,9 +525,21 @@ impl Handle {
     fn_get_rc_expected!(shortcuts: shortcuts::Shortcuts);
     fn_get_rc_expected!(bookmarks: bookmarks::Bookmarks);
     fn_get_rc_expected!(domain_settings: domain_settings::Settings);
+    fn_get_rc_expected!(profile: profile::Profile);
+    fn_get_rc_expected!(page_state_client: page_state::Client);
 
     fn_get_arc_mutex_expected!(page_state_store: page_state::Store);
 
+    pub fn recovered_storage(&self) -> Vec<(&'static str, storage::Recovery)> {
+        self.data.upgrade()
+            .map(|data| data.recovered_storage.clone())
+            .unwrap_or_else(|| Vec::new())
+    }
+
+    pub fn suffix_list_error(&self) -> Option<String> {
+        self.data.upgrade().and_then(|data| data.suffix_list_error.clone())
+    }
+
     pub fn is_private(&self) -> bool {
         self.data.upgrade().map(|data| data.is_private).unwrap_or(true)
     }
@@ -371,6 +555,12 @@ impl Handle {
         *data.active_webview.borrow_mut() = Some(view);
     }
 
+    pub fn clear_active(&self) {
+        let data = unwrap_or_return!(self.data.upgrade());
+        data.active_page_store_id.set(None);
+        *data.active_webview.borrow_mut() = None;
+    }
+
     pub fn active_webview(&self) -> Option<webkit2gtk::WebView> {
         self.data.upgrade().and_then(|data| match *data.active_webview.borrow() {
             Some(ref view) => Some(view.clone()),
@@ -1,10 +1,18 @@
 
+use std::path;
+
 use gio;
+use gtk;
 
 use app;
+use domain_rules;
 use page_store;
+use portable_tree;
+use text;
 use window;
 use recently_closed;
+use session;
+use snapshots;
 use stored;
 
 const ACCEL_RELOAD: &str = "<ctrl>r";
@@ -33,6 +41,18 @@ pub const ACTION_REOPEN: &str = "app.reopen";
 pub const ACTION_HISTORY: &str = "app.show-history";
 pub const ACTION_BOOKMARKS: &str = "app.show-bookmarks";
 pub const ACTION_SHORTCUTS: &str = "app.show-shortcuts";
+pub const ACTION_DOMAIN_RULES: &str = "app.show-domain-rules";
+pub const ACTION_COOKIES: &str = "app.show-cookies";
+pub const ACTION_REQUESTS: &str = "app.show-requests";
+pub const ACTION_SNAPSHOTS: &str = "app.show-snapshots";
+pub const ACTION_IMPORT_FILTER_LIST: &str = "app.import-filter-list";
+pub const ACTION_IMPORT_DOMAIN_RULES: &str = "app.import-domain-rules";
+pub const ACTION_EXPORT_DOMAIN_RULES: &str = "app.export-domain-rules";
+pub const ACTION_SWITCH_SESSION: &str = "app.switch-session";
+pub const ACTION_NEW_SESSION: &str = "app.new-session";
+pub const ACTION_RENAME_SESSION: &str = "app.rename-session";
+pub const ACTION_DUPLICATE_SESSION: &str = "app.duplicate-session";
+pub const ACTION_DELETE_SESSION: &str = "app.delete-session";
 
 pub struct Map {
     pub menu_bar: gio::Menu,
@@ -52,16 +72,31 @@ pub struct Map {
     pub history_action: gio::SimpleAction,
     pub bookmarks_action: gio::SimpleAction,
     pub shortcuts_action: gio::SimpleAction,
+    pub domain_rules_action: gio::SimpleAction,
+    pub cookies_action: gio::SimpleAction,
+    pub requests_action: gio::SimpleAction,
+    pub snapshots_action: gio::SimpleAction,
+    pub import_filter_list_action: gio::SimpleAction,
+    pub import_domain_rules_action: gio::SimpleAction,
+    pub export_domain_rules_action: gio::SimpleAction,
+    pub session_menu: gio::Menu,
+    pub switch_session_action: gio::SimpleAction,
+    pub new_session_action: gio::SimpleAction,
+    pub rename_session_action: gio::SimpleAction,
+    pub duplicate_session_action: gio::SimpleAction,
+    pub delete_session_action: gio::SimpleAction,
 }
 
 pub fn create() -> Map {
     use gtk::prelude::*;
 
     let recent_menu = gio::Menu::new();
-    let menu_bar = create_menu_bar(&recent_menu);
+    let session_menu = gio::Menu::new();
+    let menu_bar = create_menu_bar(&recent_menu, &session_menu);
     Map {
         menu_bar,
         recent_menu,
+        session_menu,
         quit_action: gio::SimpleAction::new("quit", None),
         go_back_action: gio::SimpleAction::new("go-back", None),
         go_forward_action: gio::SimpleAction::new("go-forward", None),
@@ -92,16 +127,55 @@ pub fn create() -> Map {
             None,
             &false.to_variant(),
         ),
+        domain_rules_action: gio::SimpleAction::new_stateful(
+            "show-domain-rules",
+            None,
+            &false.to_variant(),
+        ),
+        cookies_action: gio::SimpleAction::new_stateful(
+            "show-cookies",
+            None,
+            &false.to_variant(),
+        ),
+        requests_action: gio::SimpleAction::new_stateful(
+            "show-requests",
+            None,
+            &false.to_variant(),
+        ),
+        snapshots_action: gio::SimpleAction::new_stateful(
+            "show-snapshots",
+            None,
+            &false.to_variant(),
+        ),
+        import_filter_list_action: gio::SimpleAction::new("import-filter-list", None),
+        import_domain_rules_action: gio::SimpleAction::new("import-domain-rules", None),
+        export_domain_rules_action: gio::SimpleAction::new("export-domain-rules", None),
+        switch_session_action: gio::SimpleAction::new_stateful(
+            "switch-session",
+            Some(&*session::Id::static_variant_type()),
+            &(0 as session::Id).to_variant(),
+        ),
+        new_session_action: gio::SimpleAction::new("new-session", None),
+        rename_session_action: gio::SimpleAction::new("rename-session", None),
+        duplicate_session_action: gio::SimpleAction::new("duplicate-session", None),
+        delete_session_action: gio::SimpleAction::new("delete-session", None),
     }
 }
 
-fn create_menu_bar(recent_menu: &gio::Menu) -> gio::Menu {
+fn create_menu_bar(recent_menu: &gio::Menu, session_menu: &gio::Menu) -> gio::Menu {
     use gio::prelude::*;
     use menu;
 
     menu::build(|menu| {
         menu::add(menu, "_File", |menu| {
-            menu::add_item(menu, "_Quit", ACTION_QUIT, Some(ACCEL_QUIT));
+            menu::add_section(menu, |menu| {
+                menu::add_item(menu, "Import _Filter List...", ACTION_IMPORT_FILTER_LIST, None);
+                menu::add_item(menu, "_Import Domain Rules...", ACTION_IMPORT_DOMAIN_RULES, None);
+                menu::add_item(menu, "_Export Domain Rules...", ACTION_EXPORT_DOMAIN_RULES, None);
+            });
+            menu::add_section(menu, |menu| {
+                menu::add_item(menu, "_Quit", ACTION_QUIT, Some(ACCEL_QUIT));
+            });
         });
         menu::add(menu, "_Page", |menu| {
             menu::add_section(menu, |menu| {
@@ -122,6 +196,19 @@ fn create_menu_bar(recent_menu: &gio::Menu) -> gio::Menu {
                 menu::add_item(menu, "_History", ACTION_HISTORY, None);
                 menu::add_item(menu, "_Bookmarks", ACTION_BOOKMARKS, None);
                 menu::add_item(menu, "_Shortcuts", ACTION_SHORTCUTS, None);
+                menu::add_item(menu, "_Domain Rules", ACTION_DOMAIN_RULES, None);
+                menu::add_item(menu, "_Cookies", ACTION_COOKIES, None);
+                menu::add_item(menu, "_Requests", ACTION_REQUESTS, None);
+                menu::add_item(menu, "S_napshots", ACTION_SNAPSHOTS, None);
+            });
+        });
+        menu::add(menu, "_Sessions", |menu| {
+            menu.append_section(None, session_menu);
+            menu::add_section(menu, |menu| {
+                menu::add_item(menu, "_New Session...", ACTION_NEW_SESSION, None);
+                menu::add_item(menu, "_Rename Session...", ACTION_RENAME_SESSION, None);
+                menu::add_item(menu, "D_uplicate Session...", ACTION_DUPLICATE_SESSION, None);
+                menu::add_item(menu, "_Delete Session...", ACTION_DELETE_SESSION, None);
             });
         });
         menu::add(menu, "_History", |menu| {
@@ -165,6 +252,10 @@ fn change_stored_view(
         app_actions.history_action.set_state(&false.to_variant());
         app_actions.bookmarks_action.set_state(&false.to_variant());
         app_actions.shortcuts_action.set_state(&false.to_variant());
+        app_actions.domain_rules_action.set_state(&false.to_variant());
+        app_actions.cookies_action.set_state(&false.to_variant());
+        app_actions.requests_action.set_state(&false.to_variant());
+        app_actions.snapshots_action.set_state(&false.to_variant());
         action.set_state(&true.to_variant());
     }
 }
@@ -193,6 +284,63 @@ pub fn setup(app: &app::Handle) {
         log_action!(ACTION_SHORTCUTS);
         change_stored_view(&app, action, stored::Section::Shortcuts);
     });
+    menu::setup_action(&app, &app_actions.domain_rules_action, true, |app, action| {
+        log_action!(ACTION_DOMAIN_RULES);
+        change_stored_view(&app, action, stored::Section::DomainRules);
+    });
+    menu::setup_action(&app, &app_actions.cookies_action, true, |app, action| {
+        log_action!(ACTION_COOKIES);
+        change_stored_view(&app, action, stored::Section::Cookies);
+    });
+    menu::setup_action(&app, &app_actions.requests_action, true, |app, action| {
+        log_action!(ACTION_REQUESTS);
+        change_stored_view(&app, action, stored::Section::Requests);
+    });
+
+    menu::setup_action(&app, &app_actions.import_filter_list_action, true, |app, _| {
+        log_action!(ACTION_IMPORT_FILTER_LIST);
+        import_filter_list(&app);
+    });
+    menu::setup_action(&app, &app_actions.import_domain_rules_action, true, |app, _| {
+        log_action!(ACTION_IMPORT_DOMAIN_RULES);
+        import_domain_rules(&app);
+    });
+    menu::setup_action(&app, &app_actions.export_domain_rules_action, true, |app, _| {
+        log_action!(ACTION_EXPORT_DOMAIN_RULES);
+        export_domain_rules(&app);
+    });
+
+    let has_session = page_store.session().is_some();
+    menu::setup_action(&app, &app_actions.snapshots_action, has_session, |app, action| {
+        log_action!(ACTION_SNAPSHOTS);
+        change_stored_view(&app, action, stored::Section::Snapshots);
+    });
+    menu::setup_param_action(
+        &app,
+        &app_actions.switch_session_action,
+        has_session,
+        |app, id: session::Id| {
+            log_action!(ACTION_SWITCH_SESSION);
+            switch_session(&app, id);
+        },
+    );
+    menu::setup_action(&app, &app_actions.new_session_action, has_session, |app, _| {
+        log_action!(ACTION_NEW_SESSION);
+        new_session(&app);
+    });
+    menu::setup_action(&app, &app_actions.rename_session_action, has_session, |app, _| {
+        log_action!(ACTION_RENAME_SESSION);
+        rename_session(&app);
+    });
+    menu::setup_action(&app, &app_actions.duplicate_session_action, has_session, |app, _| {
+        log_action!(ACTION_DUPLICATE_SESSION);
+        duplicate_session(&app);
+    });
+    menu::setup_action(&app, &app_actions.delete_session_action, false, |app, _| {
+        log_action!(ACTION_DELETE_SESSION);
+        delete_session(&app);
+    });
+    update_session_menu(&app);
 
     menu::setup_action(&app, &app_actions.quit_action, true, |app, _| {
         log_action!(ACTION_QUIT);
@@ -286,6 +434,331 @@ pub fn setup(app: &app::Handle) {
     }));
 }
 
+fn import_filter_list(app: &app::Handle) {
+
+    let window = app.window();
+    let path = unwrap_or_return!(window::choose_file(
+        &window,
+        "Import Filter List",
+        gtk::FileChooserAction::Open,
+        "_Import",
+    ));
+    let name = path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.display().to_string());
+
+    match app.domain_settings().import_filter_list_file(&name, &path) {
+        Ok(count) => window::show_info(
+            &window,
+            &format!("Imported {} filters from {}.", count, name),
+        ),
+        Err(error) => window::show_warning(
+            &window,
+            &format!("Unable to import filter list {}: {}", path.display(), error),
+        ),
+    }
+}
+
+fn import_domain_rules(app: &app::Handle) {
+    use domain_settings::portable::Mode;
+
+    const RES_MERGE: i32 = 1;
+    const RES_REPLACE: i32 = 2;
+    const RES_CANCEL: i32 = 3;
+
+    let window = app.window();
+    let path = unwrap_or_return!(window::choose_file(
+        &window,
+        "Import Domain Rules",
+        gtk::FileChooserAction::Open,
+        "_Import",
+    ));
+
+    let mode = match window::confirm_action(
+        &window,
+        "Merge the imported rules with the existing ones, or replace all existing rules?",
+        &[("Merge", RES_MERGE), ("Replace", RES_REPLACE), ("Cancel", RES_CANCEL)],
+        RES_MERGE,
+    ) {
+        RES_MERGE => Mode::Merge,
+        RES_REPLACE => Mode::Replace,
+        _ => return,
+    };
+
+    match app.domain_settings().import_rules_file(&path, mode) {
+        Ok(report) => window::show_info(&window, &domain_rules::format_import_report(&report)),
+        Err(error) => window::show_warning(
+            &window,
+            &format!("Unable to import domain rules from {}: {}", path.display(), error),
+        ),
+    }
+}
+
+fn export_domain_rules(app: &app::Handle) {
+
+    let window = app.window();
+    let path = unwrap_or_return!(window::choose_file(
+        &window,
+        "Export Domain Rules",
+        gtk::FileChooserAction::Save,
+        "_Export",
+    ));
+
+    match app.domain_settings().export_rules_file(&path) {
+        Ok(count) => window::show_info(
+            &window,
+            &format!("Exported {} {} to {}.",
+                count,
+                text::pluralize(count as u64, "rule", "rules"),
+                path.display(),
+            ),
+        ),
+        Err(error) => window::show_warning(
+            &window,
+            &format!("Unable to export domain rules to {}: {}", path.display(), error),
+        ),
+    }
+}
+
+/// Grafts the pages of a page tree document as the last children of the
+/// parent page, or at the end of the top level.
+pub fn import_page_tree(app: &app::Handle, path: &path::Path, parent: Option<page_store::Id>) {
+
+    let window = app.window();
+    let document = match portable_tree::Document::read_file(path) {
+        Ok(document) => document,
+        Err(error) => {
+            window::show_warning(
+                &window,
+                &format!("Unable to import pages from {}: {}", path.display(), error),
+            );
+            return;
+        },
+    };
+
+    document.graft(&app.page_store(), parent);
+    if let Some(parent) = parent {
+        app.page_tree_view().expand(parent, false);
+    }
+    let count = document.page_count();
+    window::show_info(
+        &window,
+        &format!("Imported {} {} from {}.",
+            count,
+            text::pluralize(count as u64, "page", "pages"),
+            path.display(),
+        ),
+    );
+}
+
+/// Exports a page and everything below it. File names ending in `.html`
+/// get a read-only outline instead of JSON.
+pub fn export_page_tree(app: &app::Handle, id: page_store::Id) {
+
+    let window = app.window();
+    let document = unwrap_or_return!(
+        portable_tree::Document::from_store(&app.page_store(), id)
+    );
+    let path = unwrap_or_return!(window::choose_file(
+        &window,
+        "Export Pages",
+        gtk::FileChooserAction::Save,
+        "_Export",
+    ));
+
+    match document.write_file(&path) {
+        Ok(()) => window::show_info(
+            &window,
+            &format!("Exported {} {} to {}.",
+                document.page_count(),
+                text::pluralize(document.page_count() as u64, "page", "pages"),
+                path.display(),
+            ),
+        ),
+        Err(error) => window::show_warning(
+            &window,
+            &format!("Unable to export pages to {}: {}", path.display(), error),
+        ),
+    }
+}
+
+/// Lists the sessions in the menu and marks the current one.
+fn update_session_menu(app: &app::Handle) {
+    use gio::prelude::*;
+
+    let app_actions = app.app_actions();
+    let page_store = app.page_store();
+    let session = unwrap_or_return!(page_store.session());
+
+    let sessions = match session.list() {
+        Ok(sessions) => sessions,
+        Err(error) => {
+            eprintln!("Unable to list sessions: {:?}", error);
+            return;
+        },
+    };
+
+    let menu = &app_actions.session_menu;
+    menu.remove_all();
+    for entry in &sessions {
+        let item = gio::MenuItem::new(entry.name.as_str(), None);
+        item.set_action_and_target_value(ACTION_SWITCH_SESSION, Some(&entry.id.to_variant()));
+        menu.append_item(&item);
+    }
+
+    app_actions.switch_session_action.set_state(&session.current().to_variant());
+    app_actions.delete_session_action.set_enabled(sessions.len() > 1);
+}
+
+/// Asks for a session name that isn't taken yet.
+fn ask_session_name(app: &app::Handle, title: &str, initial: &str) -> Option<String> {
+
+    let window = app.window();
+    let page_store = app.page_store();
+    let session = page_store.session()?;
+    let name = window::ask_text(&window, title, "Session Name", initial)?;
+    match session.find(&name) {
+        Ok(None) => Some(name),
+        Ok(Some(_)) => {
+            window::show_warning(&window, &format!("A session named '{}' already exists.", name));
+            None
+        },
+        Err(error) => {
+            window::show_warning(&window, &format!("Unable to look up sessions: {:?}", error));
+            None
+        },
+    }
+}
+
+fn current_session_name(app: &app::Handle) -> Option<String> {
+    let page_store = app.page_store();
+    let session = page_store.session()?;
+    session.get_name(session.current()).ok().and_then(|name| name)
+}
+
+/// Replaces all pages with the pages of another session.
+pub fn switch_session(app: &app::Handle, id: session::Id) {
+
+    let page_store = app.page_store();
+    if page_store.session().map(|session| session.current()) == Some(id) {
+        return;
+    }
+
+    log_debug!("switching to session {}", id);
+    let result = app.without_select(|| {
+        app.clear_active();
+        page_store.switch_session(id)
+    });
+    let last_selected = match result {
+        Ok(last_selected) => last_selected,
+        Err(error) => {
+            window::show_warning(&app.window(), &format!("Unable to switch session: {:?}", error));
+            return;
+        },
+    };
+
+    show_loaded_pages(app, last_selected);
+    update_session_menu(app);
+}
+
+/// Selects a page again after the whole page tree was loaded anew.
+pub fn show_loaded_pages(app: &app::Handle, last_selected: Option<page_store::Id>) {
+    use gtk::prelude::*;
+
+    let page_store = app.page_store();
+    let page_tree_view = app.page_tree_view();
+    if page_store.get_count() == 0 {
+        page_store.insert(
+            page_store::InsertData::new("about:blank".into())
+                .with_title(Some("about:blank".into()))
+        ).expect("created initial page for empty session");
+    }
+
+    page_tree_view.apply_expanded(&page_store);
+    page_tree_view.widget().get_selection().unselect_all();
+    match last_selected {
+        Some(id) if page_store.exists(id) => page_tree_view.select(id),
+        _ => page_tree_view.select_first(),
+    }
+}
+
+fn new_session(app: &app::Handle) {
+    let page_store = app.page_store();
+    let session = unwrap_or_return!(page_store.session());
+    let name = unwrap_or_return!(ask_session_name(app, "New Session", ""));
+    match session.create(&name) {
+        Ok(id) => switch_session(app, id),
+        Err(error) => window::show_warning(
+            &app.window(),
+            &format!("Unable to create session: {:?}", error),
+        ),
+    }
+}
+
+fn rename_session(app: &app::Handle) {
+    let page_store = app.page_store();
+    let session = unwrap_or_return!(page_store.session());
+    let current_name = current_session_name(app).unwrap_or_else(|| String::new());
+    let name = unwrap_or_return!(ask_session_name(app, "Rename Session", &current_name));
+    if let Err(error) = session.rename(session.current(), &name) {
+        window::show_warning(&app.window(), &format!("Unable to rename session: {:?}", error));
+    }
+    update_session_menu(app);
+}
+
+fn duplicate_session(app: &app::Handle) {
+    let page_store = app.page_store();
+    let session = unwrap_or_return!(page_store.session());
+    let initial = current_session_name(app)
+        .map(|name| format!("{} Copy", name))
+        .unwrap_or_else(|| String::new());
+    let name = unwrap_or_return!(ask_session_name(app, "Duplicate Session", &initial));
+    page_store.update_session();
+    match session.duplicate(session.current(), &name) {
+        Ok(id) => switch_session(app, id),
+        Err(error) => window::show_warning(
+            &app.window(),
+            &format!("Unable to duplicate session: {:?}", error),
+        ),
+    }
+}
+
+/// Deletes the current session after switching to another one.
+fn delete_session(app: &app::Handle) {
+
+    const RES_DELETE: i32 = 1;
+    const RES_CANCEL: i32 = 2;
+
+    let window = app.window();
+    let page_store = app.page_store();
+    let session = unwrap_or_return!(page_store.session());
+    let current = session.current();
+    let name = current_session_name(app).unwrap_or_else(|| String::new());
+    let next = session.list()
+        .ok()
+        .and_then(|sessions| sessions.into_iter().find(|entry| entry.id != current));
+    let next = unwrap_or_return!(next);
+
+    let answer = window::confirm_action(
+        &window,
+        &format!("Really delete session '{}' and all of its pages?", name),
+        &[("Delete", RES_DELETE), ("Cancel", RES_CANCEL)],
+        RES_CANCEL,
+    );
+    if answer != RES_DELETE {
+        return;
+    }
+
+    switch_session(app, next.id);
+    if session.current() != next.id {
+        return;
+    }
+    if let Err(error) = session.delete(current) {
+        window::show_warning(&window, &format!("Unable to delete session: {:?}", error));
+    }
+    update_session_menu(app);
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum CreateMode {
     Sibling,
@@ -393,7 +866,10 @@ fn confirm_close_children(app: &app::Handle, count: i32) -> Option<bool> {
         CLOSE_ONE,
     );
     match answer {
-        CLOSE_ALL => Some(true),
+        CLOSE_ALL => {
+            snapshots::create_logged(app);
+            Some(true)
+        },
         CLOSE_ONE => Some(false),
         _ => None,
     }
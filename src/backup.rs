@@ -0,0 +1,241 @@
+
+use std::path;
+use std::fs;
+use std::io;
+use std::time;
+
+use glib;
+
+use app;
+use profile;
+use storage;
+
+const SNAPSHOT_PREFIX: &str = "snapshot-";
+const PARTIAL_PREFIX: &str = "partial-";
+const SNAPSHOT_KEEP: usize = 8;
+const SNAPSHOT_INTERVAL_SECONDS: u32 = 60 * 30;
+
+#[derive(Debug)]
+pub enum Error {
+    Storage(storage::Error),
+    Io(io::Error),
+    UnknownSnapshot(String),
+    UnknownDatabase(String),
+}
+
+impl From<storage::Error> for Error {
+
+    fn from(error: storage::Error) -> Error { Error::Storage(error) }
+}
+
+impl From<io::Error> for Error {
+
+    fn from(error: io::Error) -> Error { Error::Io(error) }
+}
+
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    name: String,
+    path: path::PathBuf,
+    created: u64,
+}
+
+impl Snapshot {
+
+    pub fn name(&self) -> &str { &self.name }
+
+    pub fn path(&self) -> &path::Path { &self.path }
+
+    pub fn created(&self) -> u64 { self.created }
+
+    pub fn databases(&self) -> Vec<String> {
+        let mut databases = Vec::new();
+        let entries = match fs::read_dir(&self.path) {
+            Ok(entries) => entries,
+            Err(_) => return databases,
+        };
+        for entry in entries {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(_) => continue,
+            };
+            if let Some(name) = entry.file_name().to_str() {
+                databases.push(name.into());
+            }
+        }
+        databases.sort();
+        databases
+    }
+}
+
+fn now() -> u64 {
+    time::SystemTime::now()
+        .duration_since(time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+fn database_file(path: &path::Path) -> &str {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .expect("database file name")
+}
+
+pub fn list(profile: &profile::Profile) -> Result<Vec<Snapshot>, Error> {
+    let dir = profile.backups();
+    let mut snapshots = Vec::new();
+    if !dir.exists() {
+        return Ok(snapshots);
+    }
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = match entry.file_name().into_string() {
+            Ok(name) => name,
+            Err(_) => continue,
+        };
+        let created = match name.trim_left_matches(SNAPSHOT_PREFIX).parse() {
+            Ok(created) if name.starts_with(SNAPSHOT_PREFIX) => created,
+            _ => continue,
+        };
+        snapshots.push(Snapshot {
+            path: entry.path(),
+            name,
+            created,
+        });
+    }
+    snapshots.sort_by_key(|snapshot| snapshot.created);
+    Ok(snapshots)
+}
+
+fn rotate(profile: &profile::Profile) -> Result<(), Error> {
+    let mut snapshots = list(profile)?;
+    while snapshots.len() > SNAPSHOT_KEEP {
+        let snapshot = snapshots.remove(0);
+        log_debug!("removing old snapshot {:?}", snapshot.name);
+        fs::remove_dir_all(&snapshot.path)?;
+    }
+    Ok(())
+}
+
+fn write_databases(app: &app::Handle, path: &path::Path) -> Result<(), Error> {
+    let profile = app.profile();
+
+    fs::create_dir_all(path)?;
+    app.history().backup_to(path.join(database_file(profile.history())))?;
+    app.domain_settings().backup_to(path.join(database_file(profile.domain_settings())))?;
+    app.shortcuts().backup_to(path.join(database_file(profile.shortcuts())))?;
+    app.bookmarks().backup_to(path.join(database_file(profile.bookmarks())))?;
+    if let Some(session) = app.page_store().session() {
+        session.backup_to(path.join(database_file(profile.session())))?;
+    }
+    Ok(())
+}
+
+/// The databases are copied into a temporary directory that is only
+/// renamed to the snapshot name once all of them were written.
+pub fn create(app: &app::Handle) -> Result<Snapshot, Error> {
+    let profile = app.profile();
+
+    let created = now();
+    let name = format!("{}{}", SNAPSHOT_PREFIX, created);
+    let path = profile.backups().join(&name);
+    let partial_path = profile.backups().join(format!("{}{}", PARTIAL_PREFIX, name));
+    log_debug!("creating snapshot {:?}", path);
+
+    if partial_path.exists() {
+        fs::remove_dir_all(&partial_path)?;
+    }
+    if let Err(error) = write_databases(app, &partial_path) {
+        if let Err(remove_error) = fs::remove_dir_all(&partial_path) {
+            log_debug!("unable to remove partial snapshot: {}", remove_error);
+        }
+        return Err(error);
+    }
+    fs::rename(&partial_path, &path)?;
+
+    rotate(&profile)?;
+
+    Ok(Snapshot { name, path, created })
+}
+
+pub fn restore(
+    profile: &profile::Profile,
+    name: &str,
+    database: Option<&str>,
+) -> Result<Vec<&'static str>, Error> {
+
+    let snapshot = list(profile)?
+        .into_iter()
+        .find(|snapshot| snapshot.name == name)
+        .ok_or_else(|| Error::UnknownSnapshot(name.into()))?;
+
+    let databases = profile.databases();
+    if let Some(database) = database {
+        if !databases.iter().any(|&(db_name, _)| db_name == database) {
+            return Err(Error::UnknownDatabase(database.into()));
+        }
+    }
+
+    let mut restored = Vec::new();
+    for (db_name, db_path) in databases {
+        if database.map(|database| database != db_name).unwrap_or(false) {
+            continue;
+        }
+        let source = snapshot.path.join(database_file(db_path));
+        if !source.exists() {
+            continue;
+        }
+        log_debug!("restoring {} from {:?}", db_name, source);
+        storage::restore(db_path, &source)?;
+        restored.push(db_name);
+    }
+    Ok(restored)
+}
+
+fn create_logged(app: &app::Handle) {
+    match create(app) {
+        Ok(snapshot) => log_debug!("created snapshot {:?}", snapshot.name),
+        Err(error) => eprintln!("Unable to create profile snapshot: {:?}", error),
+    }
+}
+
+/// Whether to take a snapshot right at startup.
+///
+/// Not after a database was recovered or shortly after the last snapshot,
+/// so repeated crashes at startup can't rotate out the snapshots taken
+/// before the damage.
+fn is_startup_snapshot_due(app: &app::Handle) -> bool {
+    if !app.recovered_storage().is_empty() {
+        log_debug!("skipping startup snapshot after storage recovery");
+        return false;
+    }
+    let last_created = list(&app.profile())
+        .ok()
+        .and_then(|snapshots| snapshots.last().map(|snapshot| snapshot.created));
+    match last_created {
+        Some(created) if now().saturating_sub(created) < SNAPSHOT_INTERVAL_SECONDS as u64 => {
+            log_debug!("skipping startup snapshot, last one is recent");
+            false
+        },
+        _ => true,
+    }
+}
+
+/// Schedules snapshots. Private runs don't keep a session, so their
+/// snapshots would push the real session out of the rotation.
+pub fn setup(app: &app::Handle) {
+
+    if app.is_private() {
+        log_debug!("no snapshots in private mode");
+        return;
+    }
+
+    if is_startup_snapshot_due(app) {
+        create_logged(app);
+    }
+
+    glib::timeout_add_seconds_local(SNAPSHOT_INTERVAL_SECONDS, with_cloned!(app, move || {
+        create_logged(&app);
+        glib::Continue(true)
+    }));
+}
@@ -761,12 +761,20 @@ impl Bookmarks {
         let storage = storage::Storage::open_or_create(
             path,
             init_storage,
+            MIGRATIONS,
             storage::do_nothing,
         )?;
 
         Ok(Bookmarks { storage })
     }
 
+    pub fn backup_to<P>(&self, path: P) -> Result<(), storage::Error>
+    where P: AsRef<path::Path> {
+        self.storage.backup_to(path)
+    }
+
+    pub fn recovery(&self) -> Option<&storage::Recovery> { self.storage.recovery() }
+
     fn find_by_id(&self, id: BookmarkId) -> Option<Bookmark> {
         self.storage.with_connection(|conn| {
             let mut stmt = conn.prepare("
@@ -1096,6 +1104,8 @@ impl Bookmarks {
     }
 }
 
+const MIGRATIONS: &[storage::Migration] = &[];
+
 fn init_storage(conn: &mut rusqlite::Connection) -> Result<(), rusqlite::Error> {
     conn.execute("
         CREATE TABLE bookmarks (
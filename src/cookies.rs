@@ -0,0 +1,363 @@
+
+use std::cell;
+use std::sync;
+
+use gtk;
+use glib;
+use webkit2gtk;
+
+use app;
+use layout;
+use scrolled;
+use text;
+use window;
+use webview;
+use domain_settings;
+
+const RES_OK: i32 = 2;
+const RES_CANCEL: i32 = 3;
+
+const COL_HOST: u32 = 0;
+const COL_POLICY: u32 = 1;
+const COL_HOST_RAW: u32 = 2;
+
+const POLICIES: &[(domain_settings::CookiePolicy, &str)] = &[
+    (domain_settings::CookiePolicy::Always, "Always"),
+    (domain_settings::CookiePolicy::NoThirdParty, "No Third-Party"),
+    (domain_settings::CookiePolicy::Never, "Never"),
+];
+
+/// Interval for picking up the cookie domains from the cookie manager.
+const FETCH_POLL_MS: u32 = 50;
+
+pub struct Map {
+    container: gtk::Box,
+    search_entry: gtk::SearchEntry,
+    summary: gtk::Label,
+    note: gtk::Label,
+    policy_combo: gtk::ComboBoxText,
+    list: gtk::TreeView,
+    model: gtk::ListStore,
+    refresh_button: gtk::Button,
+    remove_button: gtk::Button,
+    remove_all_button: gtk::Button,
+    hosts: cell::RefCell<Vec<String>>,
+}
+
+impl Map {
+
+    pub fn new() -> Map {
+        let icon_size = gtk::IconSize::Button.into();
+        Map {
+            container: layout::vbox(),
+            search_entry: gtk::SearchEntry::new(),
+            summary: gtk::Label::new(""),
+            note: gtk::Label::new(
+                "New cookies follow the policy of the site in the active page. \
+                    Cookies of sites set to Never are removed after every page load."
+            ),
+            policy_combo: gtk::ComboBoxText::new(),
+            list: gtk::TreeView::new(),
+            refresh_button: gtk::Button::new_from_icon_name("gtk-refresh", icon_size),
+            remove_button: gtk::Button::new_from_icon_name("gtk-remove", icon_size),
+            remove_all_button: gtk::Button::new_with_label("Remove All"),
+            hosts: cell::RefCell::new(Vec::new()),
+            model: gtk::ListStore::new(&[
+                <String as gtk::StaticType>::static_type(),
+                <String as gtk::StaticType>::static_type(),
+                <String as gtk::StaticType>::static_type(),
+            ]),
+        }
+    }
+
+    pub fn container(&self) -> &gtk::Box { &self.container }
+
+    pub fn focus(&self) {
+        use gtk::prelude::*;
+
+        self.search_entry.grab_focus();
+    }
+}
+
+/// Label for the policy column, only shown for hosts with an override.
+fn policy_label(policy: domain_settings::CookiePolicy) -> &'static str {
+    POLICIES.iter()
+        .find(|&&(candidate, _)| candidate == policy)
+        .map(|&(_, label)| label)
+        .expect("cookie policy label")
+}
+
+fn cookie_manager(app: &app::Handle) -> Option<webkit2gtk::CookieManager> {
+    use webkit2gtk::{ WebContextExt };
+
+    app.web_context().get_cookie_manager()
+}
+
+fn populate(app: &app::Handle) {
+    use gtk::prelude::*;
+
+    let domains = app.domain_settings();
+    let map = app.stored();
+    let map = map.cookies();
+
+    let search = map.search_entry.get_text()
+        .map(|text| text.trim().to_lowercase())
+        .unwrap_or_else(|| String::new());
+
+    map.model.clear();
+    let mut count = 0;
+    for host in map.hosts.borrow().iter() {
+        if !host.contains(search.as_str()) {
+            continue;
+        }
+        let host_escaped = text::escape(host);
+        let host_escaped: &str = &host_escaped;
+        let policy = domains
+            .host_cookie_policy(&domain_settings::Host::parse(host))
+            .map(policy_label)
+            .unwrap_or("");
+        map.model.insert_with_values(
+            None,
+            &[COL_HOST, COL_POLICY, COL_HOST_RAW],
+            &[&host_escaped, &policy, host],
+        );
+        count += 1;
+    }
+
+    map.summary.set_text(&format!("{} {}",
+        count,
+        text::pluralize(count as u64, "host", "hosts"),
+    ));
+}
+
+/// Removes the cookies of hosts whose policy is `Never` and drops them from
+/// the list.
+///
+/// The accept policy of the cookie manager follows the active view, so
+/// pages in other views can still store cookies for these hosts.
+fn remove_forbidden(app: &app::Handle, hosts: &mut Vec<String>) {
+    use webkit2gtk::{ CookieManagerExt };
+
+    let cookie_manager = unwrap_or_return!(cookie_manager(app));
+    let domains = app.domain_settings();
+    hosts.retain(|host| {
+        let policy = domains.effective_cookie_policy(&domain_settings::Host::parse(host));
+        if policy != domain_settings::CookiePolicy::Never {
+            return true;
+        }
+        log_debug!("removing cookies for {}", host);
+        cookie_manager.delete_cookies_for_domain(host);
+        false
+    });
+}
+
+/// Fetches the hosts with stored cookies and hands them to the handler.
+///
+/// The cookie manager requires a callback that can be sent across threads,
+/// so the result is handed over through a mutex and picked up from the
+/// main loop.
+fn fetch_hosts<F>(app: &app::Handle, handle_hosts: F)
+where F: Fn(&app::Handle, Vec<String>) + 'static {
+    use webkit2gtk::{ CookieManagerExt };
+
+    let cookie_manager = unwrap_or_return!(cookie_manager(app));
+    let result = sync::Arc::new(sync::Mutex::new(None));
+
+    cookie_manager.get_domains_with_cookies(None, with_cloned!(result, move |hosts| {
+        *result.lock().expect("cookie domains lock") = Some(hosts);
+    }));
+
+    glib::timeout_add_local(FETCH_POLL_MS, with_cloned!(app, move || {
+        let hosts = match result.lock().expect("cookie domains lock").take() {
+            Some(hosts) => hosts,
+            None => return glib::Continue(true),
+        };
+        let mut hosts = match hosts {
+            Ok(hosts) => hosts,
+            Err(error) => {
+                log_debug!("unable to fetch cookie domains: {}", error);
+                Vec::new()
+            },
+        };
+        for host in hosts.iter_mut() {
+            *host = host.trim_left_matches('.').to_lowercase();
+        }
+        hosts.sort();
+        hosts.dedup();
+        handle_hosts(&app, hosts);
+        glib::Continue(false)
+    }));
+}
+
+/// Fetches the hosts with stored cookies, removes the cookies of hosts
+/// that don't accept any and updates the list.
+pub fn refresh(app: &app::Handle) {
+    fetch_hosts(app, |app, mut hosts| {
+        remove_forbidden(app, &mut hosts);
+        *app.stored().cookies().hosts.borrow_mut() = hosts;
+        populate(app);
+    });
+}
+
+/// Removes the cookies of hosts whose policy is `Never`.
+///
+/// Runs after every finished load, so cookies stored for these hosts by
+/// pages outside the active view don't outlive the load.
+pub fn remove_forbidden_cookies(app: &app::Handle) {
+    let never = domain_settings::CookiePolicy::Never;
+    let domains = app.domain_settings();
+    let has_forbidden = domains.cookie_policy() == never
+        || domains.cookie_policy_hosts().iter().any(|&(_, policy)| policy == never);
+    if !has_forbidden {
+        return;
+    }
+    fetch_hosts(app, |app, mut hosts| remove_forbidden(app, &mut hosts));
+}
+
+pub fn setup(app: &app::Handle) {
+    use gtk::prelude::*;
+    use layout::{ BuildBox };
+    use pango;
+
+    let domains = app.domain_settings();
+
+    let map = app.stored();
+    let map = map.cookies();
+
+    for &(_, label) in POLICIES {
+        map.policy_combo.append_text(label);
+    }
+    let policy = domains.cookie_policy();
+    map.policy_combo.set_active(POLICIES.iter()
+        .position(|&(candidate, _)| candidate == policy)
+        .map(|index| index as i32)
+        .unwrap_or(-1));
+
+    map.container().add_start(&layout::hbox()
+        .add_start(&map.search_entry)
+        .add_end(&map.summary)
+    );
+    map.container().add_start_fill(&scrolled::create(map.list.clone()));
+    map.container().add_start(&map.note);
+    map.container().add_start(&layout::hbox()
+        .add_start(&map.refresh_button)
+        .add_start(&map.remove_button)
+        .add_start(&map.remove_all_button)
+        .add_end(&map.policy_combo)
+        .add_end(&gtk::Label::new("Accept Cookies"))
+    );
+    map.list.set_model(&map.model);
+    map.note.set_line_wrap(true);
+    map.note.set_xalign(0.0);
+
+    let host_column = {
+        let column = gtk::TreeViewColumn::new();
+        let cell = gtk::CellRendererText::new();
+        cell.set_property_ellipsize(pango::EllipsizeMode::End);
+        column.pack_start(&cell, true);
+        column.add_attribute(&cell, "text", COL_HOST as i32);
+        column.set_expand(true);
+        column
+    };
+
+    let policy_column = {
+        let column = gtk::TreeViewColumn::new();
+        let cell = gtk::CellRendererText::new();
+        column.pack_start(&cell, true);
+        column.add_attribute(&cell, "text", COL_POLICY as i32);
+        column
+    };
+
+    map.list.append_column(&host_column);
+    map.list.append_column(&policy_column);
+    map.list.set_headers_visible(false);
+    map.list.get_selection().set_mode(gtk::SelectionMode::Single);
+
+    map.remove_button.set_sensitive(false);
+
+    domains.connect_changed(with_cloned!(app, move || {
+        populate(&app);
+    }));
+
+    map.container.connect_map(with_cloned!(app, move |_container| {
+        refresh(&app);
+    }));
+
+    map.search_entry.connect_search_changed(with_cloned!(app, move |_entry| {
+        populate(&app);
+    }));
+
+    map.list.get_selection().connect_changed(with_cloned!(app, move |selection| {
+        let map = app.stored();
+        map.cookies().remove_button.set_sensitive(selection.get_selected().is_some());
+    }));
+
+    map.policy_combo.connect_changed(with_cloned!(app, move |combo| {
+        let index = combo.get_active();
+        if index < 0 {
+            return;
+        }
+        let policy = POLICIES[index as usize].0;
+        let domains = app.domain_settings();
+        if domains.cookie_policy() != policy {
+            domains.set_cookie_policy(policy);
+            if let Some(view) = app.active_webview() {
+                webview::apply_cookie_policy(&app, &view);
+            }
+            refresh(&app);
+        }
+    }));
+
+    map.refresh_button.connect_clicked(with_cloned!(app, move |_button| {
+        refresh(&app);
+    }));
+
+    map.remove_button.connect_clicked(with_cloned!(app, move |_button| {
+        remove_selected(&app);
+    }));
+
+    map.remove_all_button.connect_clicked(with_cloned!(app, move |_button| {
+        remove_all(&app);
+    }));
+}
+
+fn remove_selected(app: &app::Handle) {
+    use gtk::prelude::*;
+    use webkit2gtk::{ CookieManagerExt };
+
+    let window = app.window();
+    let map = app.stored();
+    let map = map.cookies();
+    let (model, iter) = unwrap_or_return!(map.list.get_selection().get_selected());
+    let host: String = model.get_value(&iter, COL_HOST_RAW as i32).get()
+        .expect("host in model");
+
+    let result = window::confirm_action(
+        &window,
+        &format!("Really remove all cookies for '{}'?", host),
+        &[("Ok", RES_OK), ("Cancel", RES_CANCEL)],
+        RES_OK,
+    );
+    if result == RES_OK {
+        let cookie_manager = unwrap_or_return!(cookie_manager(app));
+        cookie_manager.delete_cookies_for_domain(&host);
+        refresh(app);
+    }
+}
+
+fn remove_all(app: &app::Handle) {
+    use webkit2gtk::{ CookieManagerExt };
+
+    let window = app.window();
+    let result = window::confirm_action(
+        &window,
+        "Really remove all cookies?",
+        &[("Ok", RES_OK), ("Cancel", RES_CANCEL)],
+        RES_OK,
+    );
+    if result == RES_OK {
+        let cookie_manager = unwrap_or_return!(cookie_manager(app));
+        cookie_manager.delete_all_cookies();
+        refresh(app);
+    }
+}
@@ -0,0 +1,528 @@
+
+use gtk;
+
+use app;
+use layout;
+use scrolled;
+use text;
+use window;
+use domain_settings;
+
+const RES_OK: i32 = 2;
+const RES_CANCEL: i32 = 3;
+
+const COL_HOST: u32 = 0;
+const COL_RULE: u32 = 1;
+const COL_KIND: u32 = 2;
+const COL_SOURCE: u32 = 3;
+const COL_TARGET: u32 = 4;
+const COL_KIND_RAW: u32 = 5;
+const COL_IS_DENIED: u32 = 6;
+const COL_IS_ENTRY: u32 = 7;
+
+pub struct Map {
+    container: gtk::Box,
+    search_entry: gtk::SearchEntry,
+    summary: gtk::Label,
+    list: gtk::TreeView,
+    model: gtk::TreeStore,
+    add_button: gtk::Button,
+    remove_button: gtk::Button,
+    edit_button: gtk::Button,
+    add_dialog: Dialog,
+    edit_dialog: Dialog,
+}
+
+impl Map {
+
+    pub fn new() -> Map {
+        let icon_size = gtk::IconSize::Button.into();
+        Map {
+            container: layout::vbox(),
+            search_entry: gtk::SearchEntry::new(),
+            summary: gtk::Label::new(""),
+            list: gtk::TreeView::new(),
+            add_button: gtk::Button::new_from_icon_name("gtk-add", icon_size),
+            remove_button: gtk::Button::new_from_icon_name("gtk-remove", icon_size),
+            edit_button: gtk::Button::new_from_icon_name("gtk-edit", icon_size),
+            add_dialog: Dialog::new("Add Domain Rule"),
+            edit_dialog: Dialog::new("Edit Domain Rule"),
+            model: gtk::TreeStore::new(&[
+                <String as gtk::StaticType>::static_type(),
+                <String as gtk::StaticType>::static_type(),
+                <String as gtk::StaticType>::static_type(),
+                <String as gtk::StaticType>::static_type(),
+                <String as gtk::StaticType>::static_type(),
+                <String as gtk::StaticType>::static_type(),
+                <bool as gtk::StaticType>::static_type(),
+                <bool as gtk::StaticType>::static_type(),
+            ]),
+        }
+    }
+
+    pub fn container(&self) -> &gtk::Box { &self.container }
+
+    pub fn focus(&self) {
+        use gtk::prelude::*;
+
+        self.search_entry.grab_focus();
+    }
+}
+
+struct Dialog {
+    dialog: gtk::Dialog,
+    ok_button: gtk::Widget,
+    source_entry: gtk::Entry,
+    target_entry: gtk::Entry,
+    kind_combo: gtk::ComboBoxText,
+    deny_check: gtk::CheckButton,
+}
+
+impl Dialog {
+
+    fn new(title: &str) -> Dialog {
+        use gtk::prelude::*;
+
+        let dialog = gtk::Dialog::new();
+        dialog.set_title(title);
+        let ok_button = dialog.add_button("Ok", RES_OK);
+        dialog.add_button("Cancel", RES_CANCEL);
+        dialog.set_default_response(RES_OK);
+        dialog.set_modal(true);
+        dialog.set_destroy_with_parent(true);
+
+        let source_entry = gtk::Entry::new();
+        let target_entry = gtk::Entry::new();
+        target_entry.set_placeholder_text("All Domains");
+        let deny_check = gtk::CheckButton::new_with_label("Deny");
+
+        let kind_combo = gtk::ComboBoxText::new();
+        kind_combo.append_text("All Types");
+        for kind in domain_settings::ResourceType::all() {
+            kind_combo.append_text(kind.as_str());
+        }
+        kind_combo.set_active(0);
+
+        let grid = gtk::Grid::new();
+        grid.attach(&gtk::Label::new("Source"), 0, 0, 1, 1);
+        grid.attach(&source_entry, 1, 0, 1, 1);
+        grid.attach(&gtk::Label::new("Target"), 0, 1, 1, 1);
+        grid.attach(&target_entry, 1, 1, 1, 1);
+        grid.attach(&gtk::Label::new("Type"), 0, 2, 1, 1);
+        grid.attach(&kind_combo, 1, 2, 1, 1);
+        grid.attach(&deny_check, 1, 3, 1, 1);
+        grid.show_all();
+
+        dialog.get_content_area().add(&grid);
+
+        let dialog = Dialog {
+            dialog,
+            ok_button,
+            source_entry,
+            target_entry,
+            kind_combo,
+            deny_check,
+        };
+
+        dialog.source_entry.connect_property_text_notify({
+            let ok_button = dialog.ok_button.clone();
+            let target_entry = dialog.target_entry.clone();
+            move |entry| {
+                ok_button.set_sensitive(hosts_are_valid(entry, &target_entry));
+            }
+        });
+
+        dialog.target_entry.connect_property_text_notify({
+            let ok_button = dialog.ok_button.clone();
+            let source_entry = dialog.source_entry.clone();
+            let kind_combo = dialog.kind_combo.clone();
+            let deny_check = dialog.deny_check.clone();
+            move |entry| {
+                let has_target = !entry_text(entry).is_empty();
+                kind_combo.set_sensitive(has_target);
+                deny_check.set_sensitive(has_target);
+                ok_button.set_sensitive(hosts_are_valid(&source_entry, entry));
+            }
+        });
+
+        dialog
+    }
+
+    fn set(&self, entry: Option<&domain_settings::Entry>) {
+        use gtk::prelude::*;
+
+        let source = entry.map(|entry| entry.source().as_str()).unwrap_or("");
+        let target = entry.and_then(|entry| entry.target()).map(|target| target.as_str());
+        let kind = entry.and_then(|entry| entry.kind());
+        let kind_index = domain_settings::ResourceType::all().iter()
+            .position(|candidate| Some(*candidate) == kind)
+            .map(|index| index as i32 + 1)
+            .unwrap_or(0);
+
+        self.source_entry.set_text(source);
+        self.target_entry.set_text(target.unwrap_or(""));
+        self.kind_combo.set_active(kind_index);
+        self.deny_check.set_active(entry.map(|entry| entry.is_denied()).unwrap_or(false));
+        self.ok_button.set_sensitive(hosts_are_valid(&self.source_entry, &self.target_entry));
+    }
+
+    fn get(&self) -> domain_settings::Entry {
+        use gtk::prelude::*;
+
+        let source = domain_settings::Host::parse(&entry_text(&self.source_entry));
+        let target = entry_text(&self.target_entry);
+        if target.is_empty() {
+            return domain_settings::Entry::Always { source };
+        }
+        let target = domain_settings::Host::parse(&target);
+        // the first item stands for all types
+        let kind = match self.kind_combo.get_active() {
+            index if index > 0 =>
+                domain_settings::ResourceType::all().get(index as usize - 1).cloned(),
+            _ => None,
+        };
+        if self.deny_check.get_active() {
+            domain_settings::Entry::Deny { source, target, kind }
+        } else {
+            domain_settings::Entry::Allow { source, target, kind }
+        }
+    }
+}
+
+fn entry_text(entry: &gtk::Entry) -> String {
+    use gtk::prelude::*;
+
+    entry.get_text()
+        .map(|text| text.trim().to_lowercase())
+        .unwrap_or_else(|| String::new())
+}
+
+fn hosts_are_valid(source_entry: &gtk::Entry, target_entry: &gtk::Entry) -> bool {
+    let target = entry_text(target_entry);
+    domain_settings::Host::is_valid(&entry_text(source_entry))
+        && (target.is_empty() || domain_settings::Host::is_valid(&target))
+}
+
+fn rule_label(entry: &domain_settings::Entry) -> &'static str {
+    match *entry {
+        domain_settings::Entry::Always { .. } => "Always",
+        domain_settings::Entry::Allow { .. } => "Allow",
+        domain_settings::Entry::Deny { .. } => "Deny",
+    }
+}
+
+fn describe(entry: &domain_settings::Entry) -> String {
+    format!("{} {} from {} to {}",
+        rule_label(entry),
+        entry.kind().map(|kind| kind.as_str()).unwrap_or("all requests"),
+        entry.source().as_str(),
+        entry.target().map(|target| target.as_str()).unwrap_or("all domains"),
+    )
+}
+
+/// Summarizes the outcome of a rule import for the user.
+pub fn format_import_report(report: &domain_settings::portable::Report) -> String {
+
+    let mut message = format!("Imported {} {}, {} already present.",
+        report.added,
+        text::pluralize(report.added as u64, "rule", "rules"),
+        report.unchanged,
+    );
+    if !report.conflicts.is_empty() {
+        message.push_str("\n\nSkipped because of conflicting rules:");
+        for conflict in &report.conflicts {
+            message.push_str(&format!("\n  {} (keeping {})",
+                describe(&conflict.imported),
+                rule_label(&conflict.existing),
+            ));
+        }
+    }
+    if !report.invalid.is_empty() {
+        message.push_str("\n\nSkipped because they are invalid:");
+        for &(index, ref reason) in &report.invalid {
+            message.push_str(&format!("\n  rule {}: {}", index + 1, reason));
+        }
+    }
+    message
+}
+
+fn entry_at(model: &gtk::TreeModel, iter: &gtk::TreeIter) -> Option<domain_settings::Entry> {
+    use gtk::prelude::*;
+
+    let is_entry: bool = model.get_value(iter, COL_IS_ENTRY as i32).get()
+        .expect("entry flag in model");
+    if !is_entry {
+        return None;
+    }
+    let source: String = model.get_value(iter, COL_SOURCE as i32).get()
+        .expect("source in model");
+    let target: String = model.get_value(iter, COL_TARGET as i32).get()
+        .expect("target in model");
+    let kind: String = model.get_value(iter, COL_KIND_RAW as i32).get()
+        .expect("resource type in model");
+    let is_denied: bool = model.get_value(iter, COL_IS_DENIED as i32).get()
+        .expect("deny flag in model");
+
+    let source = domain_settings::Host::parse(&source);
+    if target.is_empty() {
+        return Some(domain_settings::Entry::Always { source });
+    }
+    let target = domain_settings::Host::parse(&target);
+    let kind = domain_settings::ResourceType::from_str(&kind);
+    Some(if is_denied {
+        domain_settings::Entry::Deny { source, target, kind }
+    } else {
+        domain_settings::Entry::Allow { source, target, kind }
+    })
+}
+
+fn populate(app: &app::Handle) {
+    use gtk::prelude::*;
+
+    let domains = app.domain_settings();
+    let map = app.stored();
+    let map = map.domain_rules();
+
+    let search = map.search_entry.get_text()
+        .map(|text| text.trim().to_lowercase())
+        .unwrap_or_else(|| String::new());
+    let matches = |host: &domain_settings::Host| host.as_str().contains(search.as_str());
+
+    map.model.clear();
+    let mut count = 0;
+    let mut current: Option<(String, gtk::TreeIter)> = None;
+    for entry in domains.entries() {
+        let is_match = matches(entry.source())
+            || entry.target().map(|target| matches(target)).unwrap_or(false);
+        if !is_match {
+            continue;
+        }
+        let source = entry.source().as_str().to_string();
+        let is_new_source = current.as_ref().map(|current| current.0 != source).unwrap_or(true);
+        if is_new_source {
+            let source_escaped = text::escape(&source);
+            let source_escaped: &str = &source_escaped;
+            let iter = map.model.insert_with_values(
+                None,
+                None,
+                &[COL_HOST, COL_RULE, COL_KIND, COL_SOURCE, COL_TARGET, COL_KIND_RAW,
+                    COL_IS_DENIED, COL_IS_ENTRY],
+                &[&source_escaped, &"", &"", &source, &"", &"", &false, &false],
+            );
+            current = Some((source.clone(), iter));
+        }
+        let parent = current.as_ref().map(|current| current.1.clone());
+        let target = entry.target().map(|target| target.as_str()).unwrap_or("");
+        let target_escaped = text::escape(entry.target()
+            .map(|target| target.as_str())
+            .unwrap_or("All Domains"));
+        let target_escaped: &str = &target_escaped;
+        let kind = entry.kind().map(|kind| kind.as_str()).unwrap_or("");
+        let kind_label = entry.kind().map(|kind| kind.as_str()).unwrap_or("all types");
+        map.model.insert_with_values(
+            parent.as_ref(),
+            None,
+            &[COL_HOST, COL_RULE, COL_KIND, COL_SOURCE, COL_TARGET, COL_KIND_RAW,
+                COL_IS_DENIED, COL_IS_ENTRY],
+            &[&target_escaped, &rule_label(&entry), &kind_label, &source, &target, &kind,
+                &entry.is_denied(), &true],
+        );
+        count += 1;
+    }
+
+    map.list.expand_all();
+    map.summary.set_text(&format!("{} {}",
+        count,
+        text::pluralize(count as u64, "rule", "rules"),
+    ));
+}
+
+pub fn setup(app: &app::Handle) {
+    use gtk::prelude::*;
+    use layout::{ BuildBox };
+    use pango;
+
+    let window = app.window();
+    let domains = app.domain_settings();
+
+    let map = app.stored();
+    let map = map.domain_rules();
+    map.container().add_start(&layout::hbox()
+        .add_start(&map.search_entry)
+        .add_end(&map.summary)
+    );
+    map.container().add_start_fill(&scrolled::create(map.list.clone()));
+    map.container().add_start(&layout::hbox()
+        .add_start(&map.add_button)
+        .add_start(&map.edit_button)
+        .add_start(&map.remove_button)
+    );
+    map.list.set_model(&map.model);
+
+    let host_column = {
+        let column = gtk::TreeViewColumn::new();
+        let cell = gtk::CellRendererText::new();
+        cell.set_property_ellipsize(pango::EllipsizeMode::End);
+        column.pack_start(&cell, true);
+        column.add_attribute(&cell, "text", COL_HOST as i32);
+        column.set_expand(true);
+        column
+    };
+
+    let rule_column = {
+        let column = gtk::TreeViewColumn::new();
+        let cell = gtk::CellRendererText::new();
+        column.pack_start(&cell, true);
+        column.add_attribute(&cell, "text", COL_RULE as i32);
+        column
+    };
+
+    let kind_column = {
+        let column = gtk::TreeViewColumn::new();
+        let cell = gtk::CellRendererText::new();
+        column.pack_start(&cell, true);
+        column.add_attribute(&cell, "text", COL_KIND as i32);
+        column
+    };
+
+    map.list.append_column(&host_column);
+    map.list.append_column(&rule_column);
+    map.list.append_column(&kind_column);
+    map.list.set_headers_visible(false);
+    map.list.get_selection().set_mode(gtk::SelectionMode::Single);
+
+    map.edit_button.set_sensitive(false);
+    map.remove_button.set_sensitive(false);
+
+    map.add_dialog.dialog.set_transient_for(&window);
+    map.edit_dialog.dialog.set_transient_for(&window);
+
+    populate(app);
+
+    domains.connect_changed(with_cloned!(app, move || {
+        populate(&app);
+    }));
+
+    map.search_entry.connect_search_changed(with_cloned!(app, move |_entry| {
+        populate(&app);
+    }));
+
+    map.list.get_selection().connect_changed(with_cloned!(app, move |selection| {
+        on_selection_change(&app, selection);
+    }));
+
+    map.list.connect_row_activated(with_cloned!(app, move |_view, _path, _column| {
+        edit_selected_rule(&app);
+    }));
+
+    map.edit_button.connect_clicked(with_cloned!(app, move |_button| {
+        edit_selected_rule(&app);
+    }));
+
+    map.add_button.connect_clicked(with_cloned!(app, move |_button| {
+        add_new_rule(&app);
+    }));
+
+    map.remove_button.connect_clicked(with_cloned!(app, move |_button| {
+        remove_selected(&app);
+    }));
+}
+
+fn on_selection_change(app: &app::Handle, selection: &gtk::TreeSelection) {
+    use gtk::prelude::*;
+
+    let map = app.stored();
+    let map = map.domain_rules();
+    if let Some((model, iter)) = selection.get_selected() {
+        map.edit_button.set_sensitive(entry_at(&model, &iter).is_some());
+        map.remove_button.set_sensitive(true);
+    } else {
+        map.edit_button.set_sensitive(false);
+        map.remove_button.set_sensitive(false);
+    }
+}
+
+fn edit_selected_rule(app: &app::Handle) {
+    use gtk::prelude::*;
+
+    let domains = app.domain_settings();
+    let map = app.stored();
+    let map = map.domain_rules();
+    let (model, iter) = unwrap_or_return!(map.list.get_selection().get_selected());
+    let entry = unwrap_or_return!(entry_at(&model, &iter));
+
+    map.edit_dialog.set(Some(&entry));
+    let result = map.edit_dialog.dialog.run();
+    map.edit_dialog.dialog.hide();
+
+    if result == RES_OK {
+        let new_entry = map.edit_dialog.get();
+        if new_entry != entry {
+            domains.remove(&entry);
+            domains.insert(&new_entry);
+        }
+    }
+}
+
+fn add_new_rule(app: &app::Handle) {
+    use gtk::prelude::*;
+
+    let domains = app.domain_settings();
+    let map = app.stored();
+    let map = map.domain_rules();
+
+    // prefill the source when a source group or one of its rules is selected
+    let selected_source = map.list.get_selection().get_selected()
+        .map(|(model, iter)| {
+            let source: String = model.get_value(&iter, COL_SOURCE as i32).get()
+                .expect("source in model");
+            domain_settings::Entry::Always { source: domain_settings::Host::parse(&source) }
+        });
+
+    map.add_dialog.set(selected_source.as_ref());
+    let result = map.add_dialog.dialog.run();
+    map.add_dialog.dialog.hide();
+    if result == RES_OK {
+        domains.insert(&map.add_dialog.get());
+    }
+}
+
+fn remove_selected(app: &app::Handle) {
+    use gtk::prelude::*;
+
+    let domains = app.domain_settings();
+    let window = app.window();
+    let map = app.stored();
+    let map = map.domain_rules();
+    let (model, iter) = unwrap_or_return!(map.list.get_selection().get_selected());
+
+    if let Some(entry) = entry_at(&model, &iter) {
+        let result = window::confirm_action(
+            &window,
+            &format!("Really remove rule '{}'?", describe(&entry)),
+            &[("Ok", RES_OK), ("Cancel", RES_CANCEL)],
+            RES_OK,
+        );
+        if result == RES_OK {
+            domains.remove(&entry);
+        }
+    } else {
+        let source: String = model.get_value(&iter, COL_SOURCE as i32).get()
+            .expect("source in model");
+        let source = domain_settings::Host::parse(&source);
+        let count = domains.source_entries(&source).len();
+        let result = window::confirm_action(
+            &window,
+            &format!("Really remove all {} {} for '{}'?",
+                count,
+                text::pluralize(count as u64, "rule", "rules"),
+                source.as_str(),
+            ),
+            &[("Ok", RES_OK), ("Cancel", RES_CANCEL)],
+            RES_OK,
+        );
+        if result == RES_OK {
+            domains.remove_source(&source);
+        }
+    }
+}
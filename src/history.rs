@@ -136,6 +136,8 @@ fn load_selected(app: &app::Handle, view: &gtk::TreeView, path: &gtk::TreePath)
     webview.load_uri(&uri);
 }
 
+const MIGRATIONS: &[storage::Migration] = &[];
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Mode {
     Read,
@@ -169,6 +171,7 @@ impl History {
                         ", &[])?;
                         Ok(())
                     },
+                    MIGRATIONS,
                     |_conn| Ok(()),
                 )?)
             } else {
@@ -177,6 +180,18 @@ impl History {
         })
     }
 
+    pub fn backup_to<P>(&self, path: P) -> Result<(), storage::Error>
+    where P: AsRef<path::Path> {
+        match self.storage {
+            Some(ref storage) => storage.backup_to(path),
+            None => Ok(()),
+        }
+    }
+
+    pub fn recovery(&self) -> Option<&storage::Recovery> {
+        self.storage.as_ref().and_then(|storage| storage.recovery())
+    }
+
     pub fn search(&self, text: &str, model: &gtk::ListStore) -> usize {
         use gtk::prelude::*;
 
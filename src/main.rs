@@ -8,6 +8,8 @@ extern crate pango;
 extern crate rusqlite;
 extern crate serde;
 extern crate serde_json;
+#[macro_use]
+extern crate serde_derive;
 extern crate webkit2gtk;
 extern crate xdg;
 
@@ -19,8 +21,11 @@ extern crate brimstone_page_state as page_state;
 
 pub mod app;
 pub mod app_action;
+pub mod backup;
 pub mod bar;
 pub mod bookmarks;
+pub mod cookies;
+pub mod domain_rules;
 pub mod dynamic;
 pub mod history;
 pub mod layout;
@@ -33,13 +38,17 @@ pub mod page_context_menu;
 pub mod page_store;
 pub mod page_tree_store;
 pub mod page_tree_view;
+pub mod portable_tree;
 pub mod profile;
 pub mod recently_closed;
+pub mod requests;
 pub mod script_dialog;
 pub mod scrolled;
 pub mod session;
 pub mod shortcuts;
 pub mod signal;
+pub mod site_preferences;
+pub mod snapshots;
 pub mod status_bar;
 pub mod stored;
 pub mod text;
@@ -80,15 +89,47 @@ fn main() {
             match err {
                 app::ArgumentError::MissingValue(param) =>
                     eprintln!("Missing value for {} parameter.", param),
+                app::ArgumentError::InvalidValue(param) =>
+                    eprintln!("Invalid value for {} parameter.", param),
                 app::ArgumentError::UnclearProfileParameters =>
                     eprintln!("Unclear profile variant selection parameters."),
                 app::ArgumentError::MissingProfileParameter =>
                     eprintln!("No profile parameters were specified."),
+                app::ArgumentError::UnclearCommandParameters =>
+                    eprintln!("Unclear command selection parameters."),
             }
             return;
         },
     };
 
+    match *app_args.command() {
+        app::Command::Run => (),
+        app::Command::ListSnapshots => {
+            list_snapshots(&app_args);
+            return;
+        },
+        app::Command::RestoreSnapshot { ref name, ref database } => {
+            restore_snapshot(&app_args, name, database.as_ref().map(|db| db.as_str()));
+            return;
+        },
+        app::Command::ExportDomainRules { ref path } => {
+            export_domain_rules(&app_args, path);
+            return;
+        },
+        app::Command::ImportDomainRules { ref path, mode } => {
+            import_domain_rules(&app_args, path, mode);
+            return;
+        },
+        app::Command::ExportSession { ref path } => {
+            export_session(&app_args, path);
+            return;
+        },
+        app::Command::ImportSession { ref path, parent } => {
+            import_session(&app_args, path, parent);
+            return;
+        },
+    }
+
     log_debug!("construct application");
     let app = gtk::Application::new("web.brimstone", gio::ApplicationFlags::empty())
         .expect("successfully initialized application");
@@ -107,6 +148,166 @@ fn main() {
     log_debug!("run complete");
 }
 
+fn list_snapshots(app_args: &app::Arguments) {
+
+    let profile = profile::Profile::new(app_args.profile_mode());
+    let snapshots = match backup::list(&profile) {
+        Ok(snapshots) => snapshots,
+        Err(err) => {
+            eprintln!("Unable to list profile snapshots: {:?}", err);
+            return;
+        },
+    };
+
+    if snapshots.is_empty() {
+        println!("No snapshots in {}", profile.backups().display());
+    }
+    for snapshot in snapshots {
+        println!("{}\t{}", snapshot.name(), snapshot.databases().join(", "));
+    }
+}
+
+fn restore_snapshot(app_args: &app::Arguments, name: &str, database: Option<&str>) {
+
+    let profile = profile::Profile::new(app_args.profile_mode());
+    match backup::restore(&profile, name, database) {
+        Ok(restored) => for db_name in restored {
+            println!("Restored {} from {}", db_name, name);
+        },
+        Err(backup::Error::UnknownSnapshot(name)) =>
+            eprintln!("Unknown snapshot {:?}, use --list-snapshots to list them.", name),
+        Err(backup::Error::UnknownDatabase(database)) =>
+            eprintln!("Unknown database {:?}.", database),
+        Err(err) =>
+            eprintln!("Unable to restore profile snapshot: {:?}", err),
+    }
+}
+
+fn open_domain_settings(app_args: &app::Arguments) -> Option<domain_settings::Settings> {
+
+    let profile = profile::Profile::new(app_args.profile_mode());
+    match domain_settings::Settings::open_or_create(profile.domain_settings()) {
+        Ok(domains) => Some(domains),
+        Err(err) => {
+            eprintln!("Unable to open domain settings: {:?}", err);
+            None
+        },
+    }
+}
+
+fn export_domain_rules(app_args: &app::Arguments, path: &str) {
+
+    let domains = unwrap_or_return!(open_domain_settings(app_args));
+    match domains.export_rules_file(path) {
+        Ok(count) => println!("Exported {} {} to {}",
+            count,
+            text::pluralize(count as u64, "rule", "rules"),
+            path,
+        ),
+        Err(err) => eprintln!("Unable to export domain rules to {}: {}", path, err),
+    }
+}
+
+fn import_domain_rules(
+    app_args: &app::Arguments,
+    path: &str,
+    mode: domain_settings::portable::Mode,
+) {
+    let domains = unwrap_or_return!(open_domain_settings(app_args));
+    match domains.import_rules_file(path, mode) {
+        Ok(report) => println!("{}", domain_rules::format_import_report(&report)),
+        Err(err) => eprintln!("Unable to import domain rules from {}: {}", path, err),
+    }
+}
+
+/// Opens the session storage of the profile and picks the session given
+/// by name, or the current one.
+fn open_session(app_args: &app::Arguments) -> Option<(session::Session, session::Id)> {
+
+    let profile = profile::Profile::new(app_args.profile_mode());
+    let path = profile.session();
+    if !path.exists() {
+        eprintln!("No session storage in profile at {}.", path.display());
+        return None;
+    }
+    let session = match session::Session::open(path) {
+        Ok(session) => session,
+        Err(err) => {
+            eprintln!("Unable to open session storage: {:?}", err);
+            return None;
+        },
+    };
+    let id = match app_args.session() {
+        None => session.current(),
+        Some(name) => match session.find(name) {
+            Ok(Some(id)) => id,
+            Ok(None) => {
+                eprintln!("Unknown session {:?}.", name);
+                return None;
+            },
+            Err(err) => {
+                eprintln!("Unable to find session {:?}: {:?}", name, err);
+                return None;
+            },
+        },
+    };
+    Some((session, id))
+}
+
+fn export_session(app_args: &app::Arguments, path: &str) {
+
+    let (session, id) = unwrap_or_return!(open_session(app_args));
+    let tree = match session.load_tree_of(id) {
+        Ok(tree) => tree,
+        Err(err) => {
+            eprintln!("Unable to load session: {:?}", err);
+            return;
+        },
+    };
+    let document = portable_tree::Document::from_tree(&tree);
+    match document.write_file(path) {
+        Ok(()) => println!("Exported {} {} to {}",
+            document.page_count(),
+            text::pluralize(document.page_count() as u64, "page", "pages"),
+            path,
+        ),
+        Err(err) => eprintln!("Unable to export session to {}: {}", path, err),
+    }
+}
+
+fn import_session(app_args: &app::Arguments, path: &str, parent: Option<page_store::Id>) {
+
+    let document = match portable_tree::Document::read_file(path) {
+        Ok(document) => document,
+        Err(err) => {
+            eprintln!("Unable to import session from {}: {}", path, err);
+            return;
+        },
+    };
+    let (session, id) = unwrap_or_return!(open_session(app_args));
+    if let Some(parent) = parent {
+        match session.load_tree_of(id) {
+            Ok(ref tree) if tree.find_node(parent).is_some() => (),
+            Ok(_) => {
+                eprintln!("Unknown parent page {}.", parent);
+                return;
+            },
+            Err(err) => {
+                eprintln!("Unable to load session: {:?}", err);
+                return;
+            },
+        }
+    }
+    match session.graft(id, parent, &document.pages) {
+        Ok(_) => println!("Imported {} {} from {}",
+            document.page_count(),
+            text::pluralize(document.page_count() as u64, "page", "pages"),
+            path,
+        ),
+        Err(err) => eprintln!("Unable to import session from {}: {:?}", path, err),
+    }
+}
+
 fn setup(app: &gtk::Application, app_args: &app::Arguments) -> app::Application {
 
     let app = app::Application::new(app, app_args);
@@ -1,14 +1,27 @@
 
+use std::time;
+
 use gtk;
 use gdk;
+use glib;
 
 use app;
 use app_action;
 use bar;
 use mouse;
+use page_state;
 use page_store;
+use site_preferences;
 use text;
 
+const GRANT_DURATION_SECONDS: u64 = 60 * 60;
+
+/// Interval for dispatching page change signals from the page-state server.
+const PAGE_CHANGES_POLL_MS: u32 = 250;
+
+/// Maximum number of blocked hosts listed in the domain button tooltip.
+const TOOLTIP_HOST_LIMIT: usize = 10;
+
 pub struct Map {
     pub container: gtk::Box,
     pub address_entry: gtk::Entry,
@@ -126,7 +139,6 @@ fn show_domain_popup(app: &app::Handle, event: &gdk::EventButton) {
     }
 
     if !complete {
-
         for host in &source_hosts {
             let item = gtk::MenuItem::new_with_label(
                 &format!("Allow All Requests from {}", host.as_str()),
@@ -138,31 +150,41 @@ fn show_domain_popup(app: &app::Handle, event: &gdk::EventButton) {
                 domains.insert_always_entry(&host);
             }));
         }
+    }
 
-        let mut target_hosts = Vec::new();
-        for host in data.allowed() {
-            target_hosts.extend(host.to_expanded());
-        }
-        for host in data.denied() {
-            target_hosts.extend(host.to_expanded());
-        }
-        target_hosts.sort();
-        target_hosts.dedup();
-
-        let target_hosts = target_hosts.into_iter().map(|host| {
-            let has_entry = domains.has_entry(data.host(), &host);
-            (host, has_entry)
-        }).collect::<Vec<_>>();
-
-        if !target_hosts.is_empty() {
-            let sep = gtk::SeparatorMenuItem::new();
-            menu.append(&sep);
-        }
+    let mut target_hosts = Vec::new();
+    for host in data.allowed() {
+        target_hosts.extend(host.to_expanded());
+    }
+    for host in data.denied() {
+        target_hosts.extend(host.to_expanded());
+    }
+    target_hosts.sort();
+    target_hosts.dedup();
+
+    let grants = page_state_store.lock()
+        .expect("exclusive access to page state store")
+        .get_grants(page_id)
+        .into_iter()
+        .filter(|grant| &grant.source() == data.host())
+        .collect::<Vec<_>>();
+
+    let target_hosts = target_hosts.into_iter().map(|host| {
+        let has_entry = domains.has_entry(data.host(), &host, None);
+        let has_deny_entry = domains.has_deny_entry(data.host(), &host, None);
+        (host, has_entry, has_deny_entry)
+    }).collect::<Vec<_>>();
+
+    if !target_hosts.is_empty() {
+        let sep = gtk::SeparatorMenuItem::new();
+        menu.append(&sep);
+    }
 
-        let mut allowed_count = 0;
-        let mut denied_count = 0;
-        for &(ref host, has_entry) in &target_hosts {
-            if !has_entry {
+    let mut open_count = 0;
+    let mut stored_count = 0;
+    for &(ref host, has_entry, has_deny_entry) in &target_hosts {
+        if !has_entry && !has_deny_entry {
+            if !complete {
                 let item = gtk::MenuItem::new_with_label(
                     &format!("Allow Requests to {}", host.as_str()),
                 );
@@ -171,33 +193,109 @@ fn show_domain_popup(app: &app::Handle, event: &gdk::EventButton) {
                 let source = data.host().clone();
                 item.connect_activate(with_cloned!(app, move |_item| {
                     let domains = app.domain_settings();
-                    domains.insert_entry(&source, &host);
+                    domains.insert_entry(&source, &host, None);
                 }));
-                denied_count += 1;
-            } else {
-                allowed_count += 1;
             }
+            if !complete && !grants.iter().any(|grant| &grant.target() == host) {
+                append_grant_item(
+                    app,
+                    &menu,
+                    &format!("Allow Requests to {} for This Page", host.as_str()),
+                    page_state::Grant::for_page(page_id, data.host(), host),
+                );
+                append_grant_item(
+                    app,
+                    &menu,
+                    &format!("Allow Requests to {} for 1 Hour", host.as_str()),
+                    page_state::Grant::for_duration(
+                        time::Duration::from_secs(GRANT_DURATION_SECONDS),
+                        data.host(),
+                        host,
+                    ),
+                );
+            }
+            let item = gtk::MenuItem::new_with_label(
+                &format!("Deny Requests to {}", host.as_str()),
+            );
+            menu.append(&item);
+            let host = host.clone();
+            let source = data.host().clone();
+            item.connect_activate(with_cloned!(app, move |_item| {
+                let domains = app.domain_settings();
+                domains.insert_deny_entry(&source, &host, None);
+            }));
+            open_count += 1;
+        } else {
+            stored_count += 1;
         }
+    }
+
+    if open_count > 0 && stored_count > 0 {
+        let sep = gtk::SeparatorMenuItem::new();
+        menu.append(&sep);
+    }
 
-        if allowed_count > 0 && denied_count > 0 {
-            let sep = gtk::SeparatorMenuItem::new();
-            menu.append(&sep);
+    for &(ref host, has_entry, has_deny_entry) in &target_hosts {
+        if has_entry {
+            let item = gtk::MenuItem::new_with_label(
+                &format!("Unallow Requests to {}", host.as_str()),
+            );
+            menu.append(&item);
+            let host = host.clone();
+            let source = data.host().clone();
+            item.connect_activate(with_cloned!(app, move |_item| {
+                let domains = app.domain_settings();
+                domains.remove_entry(&source, &host, None);
+            }));
         }
+        if has_deny_entry {
+            let item = gtk::MenuItem::new_with_label(
+                &format!("Undeny Requests to {}", host.as_str()),
+            );
+            menu.append(&item);
+            let host = host.clone();
+            let source = data.host().clone();
+            item.connect_activate(with_cloned!(app, move |_item| {
+                let domains = app.domain_settings();
+                domains.remove_deny_entry(&source, &host, None);
+            }));
+        }
+    }
 
-        for &(ref host, has_entry) in &target_hosts {
-            if has_entry {
-                let item = gtk::MenuItem::new_with_label(
-                    &format!("Unallow Requests to {}", host.as_str()),
-                );
-                menu.append(&item);
-                let host = host.clone();
-                let source = data.host().clone();
-                item.connect_activate(with_cloned!(app, move |_item| {
-                    let domains = app.domain_settings();
-                    domains.remove_entry(&source, &host);
-                }));
+    if !grants.is_empty() {
+        let sep = gtk::SeparatorMenuItem::new();
+        menu.append(&sep);
+    }
+
+    for grant in grants {
+        let item = gtk::MenuItem::new_with_label(&format!(
+            "Revoke Temporary Allowance for {}{}",
+            grant.target().as_str(),
+            match grant.scope() {
+                page_state::GrantScope::Page(_) => "",
+                page_state::GrantScope::Until(_) => " (Timed)",
+            },
+        ));
+        menu.append(&item);
+        item.connect_activate(with_cloned!(app, move |_item| {
+            if let Err(error) = app.page_state_client().revoke_grant(&grant) {
+                eprintln!("Unable to revoke temporary allowance: {}", error);
             }
-        }
+        }));
+    }
+
+    let sep = gtk::SeparatorMenuItem::new();
+    menu.append(&sep);
+
+    for host in &source_hosts {
+        let item = gtk::MenuItem::new_with_label(
+            &format!("Site Preferences for {}...", host.as_str()),
+        );
+        menu.append(&item);
+        let host = host.clone();
+        item.connect_activate(with_cloned!(app, move |_item| {
+            site_preferences::edit(&app, &host);
+        }));
     }
 
     app.set_cached_domain_menu(Some(menu.clone()));
@@ -205,6 +303,23 @@ fn show_domain_popup(app: &app::Handle, event: &gdk::EventButton) {
     menu.popup_easy(event.get_button(), event.get_time());
 }
 
+fn append_grant_item(
+    app: &app::Handle,
+    menu: &gtk::Menu,
+    label: &str,
+    grant: page_state::Grant,
+) {
+    use gtk::prelude::*;
+
+    let item = gtk::MenuItem::new_with_label(label);
+    menu.append(&item);
+    item.connect_activate(with_cloned!(app, move |_item| {
+        if let Err(error) = app.page_state_client().add_grant(&grant) {
+            eprintln!("Unable to add temporary allowance: {}", error);
+        }
+    }));
+}
+
 fn is_plain_host(value: &str) -> bool {
     value.chars().all(|c| match c {
         'a'...'z' | 'A'...'Z' | '0'...'9' | '.' | '-' => true,
@@ -292,6 +407,81 @@ pub fn setup(app: &app::Handle) {
     page_tree_view.on_selection_change(with_cloned!(app, move |_map, &id| {
         on_selection_change(&app, id);
     }));
+
+    bar.domain_button.set_always_show_image(true);
+    update_blocked_badge(app);
+
+    if let Err(error) = app.page_state_client().watch_page_changes() {
+        eprintln!("Unable to watch page state changes: {}", error);
+    }
+    glib::timeout_add_local(PAGE_CHANGES_POLL_MS, with_cloned!(app, move || {
+        use webkit2gtk::{ WebViewExt };
+
+        let changes = app.page_state_client().take_changes();
+        let active_page_id = app.active_webview().map(|view| view.get_page_id());
+        let is_active_changed = active_page_id
+            .map(|page_id| changes.pages.contains(&page_id))
+            .unwrap_or(false);
+        if is_active_changed {
+            update_blocked_badge(&app);
+        }
+        glib::Continue(true)
+    }));
+}
+
+/// Shows the number of blocked third-party hosts of the active page on the
+/// domain button.
+pub fn update_blocked_badge(app: &app::Handle) {
+    use gtk::prelude::*;
+    use webkit2gtk::{ WebViewExt };
+
+    let bar = app.navigation_bar();
+    let page_state_store = app.page_state_store();
+    let data = app.active_webview().and_then(|view| {
+        page_state_store.lock()
+            .expect("exclusive access to page state store")
+            .get_data(view.get_page_id())
+    });
+    let data = match data {
+        Some(data) => data,
+        None => {
+            bar.domain_button.set_label("");
+            bar.domain_button.set_tooltip_text("No requests blocked");
+            return;
+        },
+    };
+
+    let denied = data.denied();
+    let blocked_count = data.blocked().len();
+    if denied.is_empty() {
+        bar.domain_button.set_label("");
+    } else {
+        bar.domain_button.set_label(&denied.len().to_string());
+    }
+
+    let mut tooltip =
+        if blocked_count == 0 {
+            format!("No requests blocked on {}", data.host().as_str())
+        } else {
+            format!("{} {} blocked on {}",
+                blocked_count,
+                text::pluralize(blocked_count as u64, "request", "requests"),
+                data.host().as_str(),
+            )
+        };
+    if !denied.is_empty() {
+        tooltip.push_str(&format!("\n{} third-party {}:",
+            denied.len(),
+            text::pluralize(denied.len() as u64, "host", "hosts"),
+        ));
+        for host in denied.iter().take(TOOLTIP_HOST_LIMIT) {
+            tooltip.push_str(&format!("\n  {}", host.as_str()));
+        }
+        if denied.len() > TOOLTIP_HOST_LIMIT {
+            tooltip.push_str(&format!("\n  and {} more", denied.len() - TOOLTIP_HOST_LIMIT));
+        }
+    }
+    bar.domain_button.set_tooltip_text(tooltip.as_str());
 }
 
 fn address_activate(app: &app::Handle, entry: &gtk::Entry) {
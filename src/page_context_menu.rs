@@ -7,6 +7,7 @@ use menu;
 use page_store;
 use text;
 use app_action;
+use window;
 
 const ACTION_CLOSE: &str = "win.page-ctx-close";
 const ACTION_RELOAD: &str = "win.page-ctx-reload";
@@ -16,6 +17,8 @@ const ACTION_EXPAND_ALL: &str = "win.page-ctx-expand-all";
 const ACTION_COLLAPSE: &str = "win.page-ctx-collapse";
 const ACTION_DUPLICATE: &str = "win.page-ctx-duplicate";
 const ACTION_PIN: &str = "win.page-ctx-pin";
+const ACTION_EXPORT: &str = "win.page-ctx-export";
+const ACTION_IMPORT: &str = "win.page-ctx-import";
 
 pub struct Map {
     menu: gtk::Menu,
@@ -27,6 +30,8 @@ pub struct Map {
     collapse_action: gio::SimpleAction,
     duplicate_action: gio::SimpleAction,
     pin_action: gio::SimpleAction,
+    export_action: gio::SimpleAction,
+    import_action: gio::SimpleAction,
 }
 
 impl Map {
@@ -83,6 +88,10 @@ pub fn create() -> Map {
             menu::add_section(menu, |menu| {
                 menu::add_item(menu, "Pin to Top", ACTION_PIN, None);
             });
+            menu::add_section(menu, |menu| {
+                menu::add_item(menu, "Export Pages...", ACTION_EXPORT, None);
+                menu::add_item(menu, "Import Pages Here...", ACTION_IMPORT, None);
+            });
         })),
         close_action: gio::SimpleAction::new("page-ctx-close", None),
         reload_action: gio::SimpleAction::new("page-ctx-reload", None),
@@ -92,6 +101,8 @@ pub fn create() -> Map {
         collapse_action: gio::SimpleAction::new("page-ctx-collapse", None),
         duplicate_action: gio::SimpleAction::new("page-ctx-duplicate", None),
         pin_action: gio::SimpleAction::new_stateful("page-ctx-pin", None, &false.to_variant()),
+        export_action: gio::SimpleAction::new("page-ctx-export", None),
+        import_action: gio::SimpleAction::new("page-ctx-import", None),
     }
 }
 
@@ -136,6 +147,23 @@ pub fn setup(app: &app::Handle) {
         ).expect("duplicate page creation");
     });
 
+    menu::setup_win_action(&app, &map.export_action, true, |app, _| {
+        log_action!(ACTION_EXPORT);
+        app_action::export_page_tree(&app, unwrap_or_return!(app.get_page_tree_target()));
+    });
+
+    menu::setup_win_action(&app, &map.import_action, true, |app, _| {
+        log_action!(ACTION_IMPORT);
+        let id = unwrap_or_return!(app.get_page_tree_target());
+        let path = unwrap_or_return!(window::choose_file(
+            &app.window(),
+            "Import Pages",
+            gtk::FileChooserAction::Open,
+            "_Import",
+        ));
+        app_action::import_page_tree(&app, &path, Some(id));
+    });
+
     menu::setup_win_action(&app, &map.reload_action, true, |app, _| {
         use webkit2gtk::{ WebViewExt };
 
@@ -3,8 +3,10 @@ use std::rc;
 use std::cell;
 use std::cmp;
 use std::collections;
+use std::mem;
 
 use gtk;
+use glib;
 use webkit2gtk;
 use cairo;
 use pango;
@@ -17,6 +19,7 @@ use recently_closed;
 use text;
 use signal;
 use script_dialog;
+use storage;
 
 pub type Id = u32;
 
@@ -45,6 +48,7 @@ pub struct Store {
     pinned: cell::RefCell<Vec<Id>>,
     tree_store: gtk::TreeStore,
     session: Option<session::Session>,
+    is_session_update_deferred: cell::Cell<bool>,
     recently_closed: recently_closed::State,
     count_change_notifier: signal::Notifier<Store, usize>,
     load_state_change_notifier: signal::Notifier<Store, (Id, LoadState)>,
@@ -68,6 +72,25 @@ impl Store {
     pub fn new_stateful(session: session::Session) -> (Store, Option<Id>) {
         log_debug!("new stateful page store");
 
+        let store = Store {
+            last_id: cell::Cell::new(0),
+            entries: rc::Rc::new(cell::RefCell::new(collections::HashMap::new())),
+            tree_store: page_tree_store::create(),
+            pinned: cell::RefCell::new(Vec::new()),
+            session: Some(session),
+            is_session_update_deferred: cell::Cell::new(false),
+            recently_closed: recently_closed::State::new(),
+            count_change_notifier: signal::Notifier::new(),
+            load_state_change_notifier: signal::Notifier::new(),
+        };
+        let last_selected = store.load_session();
+
+        (store, last_selected)
+    }
+
+    /// Fills the empty store with the page tree of the current session.
+    fn load_session(&self) -> Option<Id> {
+
         fn populate(
             parent: Option<&gtk::TreeIter>,
             children: &[session::Node],
@@ -84,6 +107,8 @@ impl Store {
                     favicon: None,
                     is_noclose: false,
                     is_pinned: child.is_pinned(),
+                    is_expanded: child.is_expanded(),
+                    session_state: child.session_state().map(|state| state.to_vec()),
                     load_state: LoadState {
                         can_go_back: false,
                         can_go_forward: false,
@@ -112,6 +137,7 @@ impl Store {
             }
         }
 
+        let session = self.session.as_ref()?;
         let mut tree = session.load_tree()
             .expect("session tree loaded from storage");
         tree.compact();
@@ -124,24 +150,48 @@ impl Store {
                 .expect("updated selected page in session for adjusted tree");
         }
 
-        let mut entries = collections::HashMap::new();
-        let tree_store = page_tree_store::create();
+        populate(None, tree.children(), &mut self.entries.borrow_mut(), &self.tree_store);
+        self.last_id.set(last_id);
+        *self.pinned.borrow_mut() = pinned;
+        self.update_session();
+        session.update_all_session_states(self)
+            .expect("session states stored for adjusted tree");
 
-        populate(None, tree.children(), &mut entries, &tree_store);
+        last_selected
+    }
 
-        let store = Store {
-            last_id: cell::Cell::new(last_id),
-            entries: rc::Rc::new(cell::RefCell::new(entries)),
-            tree_store,
-            pinned: cell::RefCell::new(pinned),
-            session: Some(session),
-            recently_closed: recently_closed::State::new(),
-            count_change_notifier: signal::Notifier::new(),
-            load_state_change_notifier: signal::Notifier::new(),
+    /// Closes all pages and loads the page tree of another session into
+    /// the same tree store. Returns the page last selected in it.
+    pub fn switch_session(&self, id: session::Id) -> Result<Option<Id>, storage::Error> {
+        log_debug!("switching page store to session {}", id);
+
+        let session = match self.session {
+            Some(ref session) => session,
+            None => return Ok(None),
         };
-        store.update_session();
+        self.save_all_session_states();
+        session.switch_to(id)?;
+        Ok(self.reload_session())
+    }
 
-        (store, last_selected)
+    /// Closes all pages and loads the page tree of the current session
+    /// again, after it was replaced in storage.
+    pub fn reload_session(&self) -> Option<Id> {
+        use gtk::prelude::*;
+
+        let entries = mem::replace(&mut *self.entries.borrow_mut(), collections::HashMap::new());
+        self.tree_store.clear();
+        self.pinned.borrow_mut().clear();
+        self.recently_closed.clear();
+        for (_id, entry) in entries {
+            if let Some(view) = entry.view {
+                remove_view(view);
+            }
+        }
+
+        let last_selected = self.load_session();
+        self.count_change_notifier.emit(self, &self.get_count());
+        last_selected
     }
 
     pub fn new_stateless() -> Store {
@@ -152,26 +202,48 @@ impl Store {
             tree_store: page_tree_store::create(),
             pinned: cell::RefCell::new(Vec::new()),
             session: None,
+            is_session_update_deferred: cell::Cell::new(false),
             recently_closed: recently_closed::State::new(),
             count_change_notifier: signal::Notifier::new(),
             load_state_change_notifier: signal::Notifier::new(),
         }
     }
 
+    pub fn session(&self) -> Option<&session::Session> { self.session.as_ref() }
+
     pub fn recently_closed_state(&self) -> &recently_closed::State { &self.recently_closed }
 
     pub fn update_session(&self) {
+        if self.is_session_update_deferred.get() {
+            return;
+        }
         self.session
             .as_ref()
             .map(|session| session.update_all(self).expect("session update"));
     }
 
+    /// Runs a batch of changes and stores the page tree once afterwards.
+    pub fn with_session_update_deferred<F, R>(&self, body: F) -> R where F: FnOnce() -> R {
+        let was_deferred = self.is_session_update_deferred.replace(true);
+        let result = body();
+        self.is_session_update_deferred.set(was_deferred);
+        self.update_session();
+        result
+    }
+
     pub fn update_session_node(&self, id: Id) {
         self.session
             .as_ref()
             .map(|session| session.update_node(self, id).expect("node session update"));
     }
 
+    fn update_session_state(&self, id: Id) {
+        let session = unwrap_or_return!(self.session.as_ref());
+        let state = self.map_entry(id, |entry| entry.session_state.clone()).and_then(|state| state);
+        session.update_session_state(id, state.as_ref().map(|state| &state[..]))
+            .expect("session state update");
+    }
+
     pub fn pinned_count(&self) -> usize { self.pinned.borrow().len() }
 
     pub fn tree_store(&self) -> &gtk::TreeStore { &self.tree_store }
@@ -212,6 +284,22 @@ impl Store {
         self.map_entry(id, |entry| entry.is_pinned).unwrap_or(false)
     }
 
+    pub fn get_expanded(&self, id: Id) -> bool {
+        self.map_entry(id, |entry| entry.is_expanded).unwrap_or(false)
+    }
+
+    /// Remembers whether the page's row is expanded in the tree view.
+    pub fn set_expanded(&self, id: Id, is_expanded: bool) {
+        let is_changed = self.map_entry_mut(id, |entry| {
+            let is_changed = entry.is_expanded != is_expanded;
+            entry.is_expanded = is_expanded;
+            is_changed
+        });
+        if is_changed == Some(true) {
+            self.update_session_node(id);
+        }
+    }
+
     pub fn get_noclose(&self, id: Id) -> bool {
         self.map_entry(id, |entry| entry.is_noclose).unwrap_or(false)
     }
@@ -370,22 +458,8 @@ impl Store {
             });
             store.remove(iter);
 
-            let webview = match entry.view {
-                Some(webview) => webview,
-                None => return,
-            };
-
-            let mut widget: gtk::Widget = webview.upcast();
-            while let Some(parent) = widget.get_parent() {
-                if let Some(name) = gtk::WidgetExt::get_name(&parent) {
-                    if &name == "view-space" {
-                        let view_space = parent.downcast::<gtk::Container>()
-                            .expect("view-space to gtk::Container");
-                        view_space.remove(&widget);
-                        break;
-                    }
-                }
-                widget = parent;
+            if let Some(webview) = entry.view {
+                remove_view(webview);
             }
         }
         
@@ -543,9 +617,50 @@ impl Store {
             title: entry.title.clone(),
             uri: entry.uri.clone(),
             is_pinned: entry.is_pinned,
+            is_expanded: entry.is_expanded,
         })
     }
 
+    /// Captures the back/forward list and scroll position of the page's view
+    /// so they survive restarts. Pages without a view keep what they have.
+    pub fn save_session_state(&self, id: Id) {
+        use webkit2gtk::{ WebViewExt };
+
+        let view = unwrap_or_return!(self.try_get_view(id));
+        let state = view.get_session_state()
+            .and_then(|state| state.serialize())
+            .map(|bytes| bytes.to_vec());
+        if state.is_none() {
+            return;
+        }
+        self.map_entry_mut(id, |entry| entry.session_state = state);
+        self.update_session_state(id);
+    }
+
+    /// Captured session states by page.
+    pub fn session_states(&self) -> Vec<(Id, Vec<u8>)> {
+        self.entries.borrow().values()
+            .filter_map(|entry| entry.session_state.clone().map(|state| (entry.id, state)))
+            .collect()
+    }
+
+    pub fn save_all_session_states(&self) {
+        let ids: Vec<Id> = self.entries.borrow().values()
+            .filter(|entry| entry.view.is_some())
+            .map(|entry| entry.id)
+            .collect();
+        for id in ids {
+            self.save_session_state(id);
+        }
+    }
+
+    /// All views created so far for open pages.
+    pub fn views(&self) -> Vec<webkit2gtk::WebView> {
+        self.entries.borrow().values()
+            .filter_map(|entry| entry.view.clone())
+            .collect()
+    }
+
     pub fn try_get_view(&self, id: Id) -> Option<webkit2gtk::WebView> {
         self.map_entry(id, |entry| entry.view.clone()).and_then(|view| view)
     }
@@ -560,10 +675,14 @@ impl Store {
 
         log_debug!("creating webview for page {}", id);
 
-        let uri = self.map_entry(id, |entry| entry.uri.clone())?;
+        let (uri, state) = self.map_entry(id, |entry| {
+            (entry.uri.clone(), entry.session_state.clone())
+        })?;
         let new_view = webview::create(id, app);
         script_dialog::connect(app, &new_view);
-        new_view.load_uri(&uri);
+        if !restore_session_state(&new_view, &uri, state.as_ref().map(|state| &state[..])) {
+            new_view.load_uri(&uri);
+        }
         let ret_view = new_view.clone();
         self.map_entry_mut(id, move |entry| entry.view = Some(new_view));
         let iter = page_tree_store::find_iter_by_id(&self.tree_store, id)
@@ -605,6 +724,8 @@ impl Store {
             favicon: None,
             is_noclose: false,
             is_pinned: false,
+            is_expanded: false,
+            session_state: None,
             load_state: LoadState {
                 can_go_back: false,
                 can_go_forward: false,
@@ -678,6 +799,48 @@ impl Store {
     }
 }
 
+/// Restores a captured back/forward list and loads its current item.
+///
+/// Returns `false` if there is nothing to restore or the state doesn't
+/// match the stored URI anymore, in which case the URI should be loaded.
+fn restore_session_state(view: &webkit2gtk::WebView, uri: &str, state: Option<&[u8]>) -> bool {
+    use webkit2gtk::{ WebViewExt, BackForwardListExt, BackForwardListItemExt };
+
+    let state = match state {
+        Some(state) => state,
+        None => return false,
+    };
+    let state = webkit2gtk::WebViewSessionState::new(&glib::Bytes::from(state));
+    view.restore_session_state(&state);
+    let item = match view.get_back_forward_list().and_then(|list| list.get_current_item()) {
+        Some(item) => item,
+        None => return false,
+    };
+    if item.get_uri().as_ref().map(|item_uri| item_uri.as_str()) != Some(uri) {
+        return false;
+    }
+    view.go_to_back_forward_list_item(&item);
+    true
+}
+
+/// Takes the view of a closed page out of the view space.
+fn remove_view(webview: webkit2gtk::WebView) {
+    use gtk::prelude::*;
+
+    let mut widget: gtk::Widget = webview.upcast();
+    while let Some(parent) = widget.get_parent() {
+        if let Some(name) = gtk::WidgetExt::get_name(&parent) {
+            if &name == "view-space" {
+                let view_space = parent.downcast::<gtk::Container>()
+                    .expect("view-space to gtk::Container");
+                view_space.remove(&widget);
+                break;
+            }
+        }
+        widget = parent;
+    }
+}
+
 #[derive(Debug)]
 pub enum InsertPosition {
     Start,
@@ -739,6 +902,8 @@ struct Entry {
     favicon: Option<cairo::Surface>,
     is_pinned: bool,
     is_noclose: bool,
+    is_expanded: bool,
+    session_state: Option<Vec<u8>>,
 }
 
 #[derive(Debug)]
@@ -747,4 +912,5 @@ pub struct Data {
     pub title: Option<text::RcString>,
     pub uri: text::RcString,
     pub is_pinned: bool,
+    pub is_expanded: bool,
 }
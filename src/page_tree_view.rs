@@ -102,6 +102,22 @@ impl Map {
         let path = unwrap_or_return_false!(self.page_tree_store().get_path(&iter));
         self.widget.row_expanded(&path)
     }
+
+    /// Expands the rows of all pages that were expanded when last stored,
+    /// as long as their parents are expanded as well.
+    pub fn apply_expanded(&self, page_store: &page_store::Store) {
+
+        fn apply(map: &Map, page_store: &page_store::Store, parent: Option<&gtk::TreeIter>) {
+            for (id, iter) in page_store.children(parent) {
+                if page_store.get_expanded(id) {
+                    map.expand_iter(&iter, false);
+                    apply(map, page_store, Some(&iter));
+                }
+            }
+        }
+
+        apply(self, page_store, None);
+    }
 }
 
 pub fn create_tree_view() -> gtk::TreeView {
@@ -155,6 +171,7 @@ pub fn setup(app: &app::Handle) {
     let map = app.page_tree_view();
     let page_tree_view = map.widget();
     map.set_page_tree_store(&app.page_tree_store());
+    map.apply_expanded(&app.page_store());
 
     page_tree_view.connect_drag_begin(with_cloned!(app, move |_view, _| {
         begin_drag_state(&app);
@@ -166,6 +183,15 @@ pub fn setup(app: &app::Handle) {
         save_tree_to_session(&app);
     }));
 
+    page_tree_view.connect_row_expanded(with_cloned!(app, move |_view, iter, _path| {
+        let id = page_tree_store::get_id(&app.page_tree_store(), iter);
+        app.page_store().set_expanded(id, true);
+    }));
+
+    page_tree_view.connect_row_collapsed(with_cloned!(app, move |_view, iter, _path| {
+        on_row_collapsed(&app, iter);
+    }));
+
     page_tree_view.get_selection().connect_changed(with_cloned!(app, move |selection| {
         on_selection_change(&app, selection);
     }));
@@ -202,6 +228,22 @@ fn on_selection_change(app: &app::Handle, selection: &gtk::TreeSelection) {
     map.selection_change_notifier.emit(&map, &id);
 }
 
+/// Collapsing a row also collapses everything below it, so the stored
+/// state of the descendants is reset as well.
+fn on_row_collapsed(app: &app::Handle, iter: &gtk::TreeIter) {
+
+    fn reset(page_store: &page_store::Store, id: page_store::Id, iter: &gtk::TreeIter) {
+        page_store.set_expanded(id, false);
+        for (child_id, child_iter) in page_store.children(Some(iter)) {
+            reset(page_store, child_id, &child_iter);
+        }
+    }
+
+    let page_store = app.page_store();
+    let id = page_tree_store::get_id(&app.page_tree_store(), iter);
+    reset(&page_store, id, iter);
+}
+
 fn save_tree_to_session(app: &app::Handle) {
     log_debug!("save tree to session");
 
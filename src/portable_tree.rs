@@ -0,0 +1,263 @@
+
+//! Portable representations of page trees for handing them to others.
+//!
+//! A JSON document looks like this:
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "pages": [
+//!     {
+//!       "title": "Research",
+//!       "uri": "https://research.example/",
+//!       "pinned": true,
+//!       "children": [
+//!         { "title": "Paper", "uri": "https://research.example/paper.pdf" },
+//!         { "uri": "https://other.example/" }
+//!       ]
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! `title`, `pinned` and `children` are optional. Only pages grafted at
+//! the top level can be pinned, the flag is ignored everywhere else.
+//!
+//! The HTML rendering is a read-only nested list of links and can't be
+//! imported again.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path;
+
+use gtk;
+use serde_json;
+
+use page_store;
+use page_tree_store;
+use session;
+use text;
+
+pub const VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Document {
+    pub version: u32,
+    pub pages: Vec<Page>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Page {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    pub uri: String,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub pinned: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<Page>,
+}
+
+fn is_false(value: &bool) -> bool { !*value }
+
+/// Files ending in `.html` or `.htm` get the HTML rendering.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Html,
+}
+
+impl Format {
+
+    pub fn from_path(path: &path::Path) -> Format {
+        let extension = path.extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase());
+        match extension.as_ref().map(|extension| extension.as_str()) {
+            Some("html") | Some("htm") => Format::Html,
+            _ => Format::Json,
+        }
+    }
+}
+
+impl Page {
+
+    fn from_node(node: &session::Node) -> Page {
+        Page {
+            title: node.title().map(|title| title.into_string()),
+            uri: node.uri().into_string(),
+            pinned: node.is_pinned(),
+            children: node.children().iter().map(Page::from_node).collect(),
+        }
+    }
+
+    fn from_store(page_store: &page_store::Store, id: page_store::Id, iter: &gtk::TreeIter)
+    -> Option<Page> {
+        let data = page_store.get_data(id)?;
+        Some(Page {
+            title: data.title.map(|title| title.into_string()),
+            uri: data.uri.into_string(),
+            pinned: data.is_pinned,
+            children: page_store.children(Some(iter)).iter()
+                .filter_map(|&(child_id, ref child_iter)| {
+                    Page::from_store(page_store, child_id, child_iter)
+                })
+                .collect(),
+        })
+    }
+
+    fn count(&self) -> usize {
+        1 + self.children.iter().map(|child| child.count()).sum::<usize>()
+    }
+
+    fn write_html(&self, html: &mut String, depth: usize) {
+        let indent = "  ".repeat(depth + 1);
+        let uri = text::escape(&self.uri);
+        let title = text::escape(self.title.as_ref().unwrap_or(&self.uri));
+        html.push_str(&format!("{}<li><a href=\"{}\">{}</a>", indent, uri, title));
+        if !self.children.is_empty() {
+            html.push_str("\n");
+            write_html_list(html, &self.children, depth + 1);
+            html.push_str(&indent);
+        }
+        html.push_str("</li>\n");
+    }
+
+    fn graft(
+        &self,
+        page_store: &page_store::Store,
+        parent: Option<page_store::Id>,
+    ) -> Option<page_store::Id> {
+        let id = page_store.insert(
+            page_store::InsertData::new(self.uri.as_str().into())
+                .with_title(self.title.as_ref().map(|title| title.as_str().into()))
+                .with_parent(parent)
+                .with_position(page_store::InsertPosition::End)
+        )?;
+        for child in &self.children {
+            child.graft(page_store, Some(id));
+        }
+        if self.pinned && parent.is_none() {
+            page_store.set_pinned(id, true);
+        }
+        Some(id)
+    }
+}
+
+fn write_html_list(html: &mut String, pages: &[Page], depth: usize) {
+    let indent = "  ".repeat(depth);
+    html.push_str(&format!("{}<ul>\n", indent));
+    for page in pages {
+        page.write_html(html, depth);
+    }
+    html.push_str(&format!("{}</ul>\n", indent));
+}
+
+impl Document {
+
+    /// All pages of a stored session.
+    pub fn from_tree(tree: &session::Tree) -> Document {
+        Document {
+            version: VERSION,
+            pages: tree.children().iter().map(Page::from_node).collect(),
+        }
+    }
+
+    /// A stored page and everything below it.
+    pub fn from_node(node: &session::Node) -> Document {
+        Document {
+            version: VERSION,
+            pages: vec![Page::from_node(node)],
+        }
+    }
+
+    /// A page and everything below it.
+    pub fn from_store(page_store: &page_store::Store, id: page_store::Id) -> Option<Document> {
+        let iter = page_tree_store::find_iter_by_id(page_store.tree_store(), id)?;
+        Some(Document {
+            version: VERSION,
+            pages: vec![Page::from_store(page_store, id, &iter)?],
+        })
+    }
+
+    pub fn parse(content: &str) -> Result<Document, Error> {
+        let document: Document = serde_json::from_str(content)?;
+        if document.version != VERSION {
+            return Err(Error::UnsupportedVersion(document.version));
+        }
+        Ok(document)
+    }
+
+    pub fn read_file<P>(path: P) -> Result<Document, Error>
+    where P: AsRef<path::Path> {
+        Document::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn to_string(&self) -> String {
+        serde_json::to_string_pretty(self).expect("page tree serialization")
+    }
+
+    pub fn to_html(&self) -> String {
+        let mut html = String::from("<!DOCTYPE html>\n<html>\n<head>\n");
+        html.push_str("<meta charset=\"utf-8\">\n<title>Pages</title>\n</head>\n<body>\n");
+        write_html_list(&mut html, &self.pages, 0);
+        html.push_str("</body>\n</html>\n");
+        html
+    }
+
+    /// Writes the document in the format chosen by the file name.
+    pub fn write_file<P>(&self, path: P) -> Result<(), io::Error>
+    where P: AsRef<path::Path> {
+        let path = path.as_ref();
+        let content = match Format::from_path(path) {
+            Format::Json => self.to_string(),
+            Format::Html => self.to_html(),
+        };
+        fs::write(path, content)
+    }
+
+    pub fn page_count(&self) -> usize {
+        self.pages.iter().map(|page| page.count()).sum()
+    }
+
+    /// Inserts all pages as the last children of the parent, or at the end
+    /// of the top level. Returns the ids of the grafted top-level pages.
+    ///
+    /// The session is stored once after all pages were inserted.
+    pub fn graft(&self, page_store: &page_store::Store, parent: Option<page_store::Id>)
+    -> Vec<page_store::Id> {
+        page_store.with_session_update_deferred(|| {
+            self.pages.iter()
+                .filter_map(|page| page.graft(page_store, parent))
+                .collect()
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Json(serde_json::Error),
+    UnsupportedVersion(u32),
+}
+
+impl From<io::Error> for Error {
+
+    fn from(error: io::Error) -> Error { Error::Io(error) }
+}
+
+impl From<serde_json::Error> for Error {
+
+    fn from(error: serde_json::Error) -> Error { Error::Json(error) }
+}
+
+impl fmt::Display for Error {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref error) => write!(f, "{}", error),
+            Error::Json(ref error) => write!(f, "{}", error),
+            Error::UnsupportedVersion(version) =>
+                write!(f, "unsupported page tree document version {}", version),
+        }
+    }
+}
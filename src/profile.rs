@@ -11,9 +11,12 @@ const FILE_DOMAIN_SETTINGS: &str = "domain_settings.db";
 const FILE_SESSION: &str = "session.db";
 const FILE_SHORTCUTS: &str = "shortcuts.db";
 const FILE_BOOKMARKS: &str = "bookmarks.db";
+const FILE_COOKIES: &str = "cookies.db";
+const FILE_SUFFIX_LIST: &str = "public_suffix_list.dat";
 
 const DIR_PROFILE: &str = "brimstone-profile";
 const DIR_CONFIG: &str = "brimstone-config";
+const DIR_BACKUPS: &str = "brimstone-backups";
 
 #[derive(Debug, Clone)]
 pub enum Mode {
@@ -29,6 +32,9 @@ pub struct Profile {
     session: path::PathBuf,
     shortcuts: path::PathBuf,
     bookmarks: path::PathBuf,
+    cookies: path::PathBuf,
+    backups: path::PathBuf,
+    suffix_list: path::PathBuf,
 }
 
 impl Profile {
@@ -45,6 +51,9 @@ impl Profile {
                     session: dir_config.join(FILE_SESSION),
                     shortcuts: dir_config.join(FILE_SHORTCUTS),
                     bookmarks: dir_config.join(FILE_BOOKMARKS),
+                    cookies: dir_config.join(FILE_COOKIES),
+                    backups: dir.join(DIR_BACKUPS),
+                    suffix_list: dir_config.join(FILE_SUFFIX_LIST),
                 }
             },
             Mode::Xdg => {
@@ -61,6 +70,10 @@ impl Profile {
                         .expect("shortcuts storage file"),
                     bookmarks: base.place_config_file(FILE_BOOKMARKS)
                         .expect("bookmarks storage file"),
+                    cookies: base.place_config_file(FILE_COOKIES)
+                        .expect("cookie storage file"),
+                    backups: base.get_data_home().join(DIR_BACKUPS),
+                    suffix_list: base.get_config_home().join(FILE_SUFFIX_LIST),
                 }
             },
             Mode::Custom(ref root) => {
@@ -72,6 +85,9 @@ impl Profile {
                     session: dir_config.join(FILE_SESSION),
                     shortcuts: dir_config.join(FILE_SHORTCUTS),
                     bookmarks: dir_config.join(FILE_BOOKMARKS),
+                    cookies: dir_config.join(FILE_COOKIES),
+                    backups: root.join(DIR_BACKUPS),
+                    suffix_list: dir_config.join(FILE_SUFFIX_LIST),
                 }
             },
         }
@@ -86,4 +102,28 @@ impl Profile {
     pub fn shortcuts(&self) -> &path::Path { &self.shortcuts }
 
     pub fn bookmarks(&self) -> &path::Path { &self.bookmarks }
+
+    /// Cookie storage, owned by WebKit and not part of the backups.
+    pub fn cookies(&self) -> &path::Path { &self.cookies }
+
+    pub fn backups(&self) -> &path::Path { &self.backups }
+
+    /// Optional Public Suffix List replacing the built-in one.
+    pub fn suffix_list(&self) -> Option<&path::Path> {
+        if self.suffix_list.exists() {
+            Some(&self.suffix_list)
+        } else {
+            None
+        }
+    }
+
+    pub fn databases(&self) -> Vec<(&'static str, &path::Path)> {
+        vec![
+            ("history", self.history.as_path()),
+            ("domain_settings", self.domain_settings.as_path()),
+            ("session", self.session.as_path()),
+            ("shortcuts", self.shortcuts.as_path()),
+            ("bookmarks", self.bookmarks.as_path()),
+        ]
+    }
 }
@@ -49,6 +49,12 @@ impl State {
         page
     }
 
+    pub fn clear(&self) {
+        log_debug!("clear");
+        self.items.borrow_mut().clear();
+        self.change_notifier.emit(self, &());
+    }
+
     pub fn pull_most_recent(&self) -> Option<Page> {
         log_debug!("pull most recent");
         let page = self.items.borrow_mut().pop();
@@ -0,0 +1,205 @@
+
+use std::time;
+
+use gtk;
+
+use app;
+use layout;
+use scrolled;
+use text;
+use page_state;
+
+const COL_NAME: u32 = 0;
+const COL_ALLOWED: u32 = 1;
+const COL_BLOCKED: u32 = 2;
+const COL_TIME: u32 = 3;
+
+pub struct Map {
+    container: gtk::Box,
+    summary: gtk::Label,
+    list: gtk::TreeView,
+    model: gtk::TreeStore,
+    refresh_button: gtk::Button,
+}
+
+impl Map {
+
+    pub fn new() -> Map {
+        let icon_size = gtk::IconSize::Button.into();
+        Map {
+            container: layout::vbox(),
+            summary: gtk::Label::new(""),
+            list: gtk::TreeView::new(),
+            refresh_button: gtk::Button::new_from_icon_name("gtk-refresh", icon_size),
+            model: gtk::TreeStore::new(&[
+                <String as gtk::StaticType>::static_type(),
+                <String as gtk::StaticType>::static_type(),
+                <String as gtk::StaticType>::static_type(),
+                <String as gtk::StaticType>::static_type(),
+            ]),
+        }
+    }
+
+    pub fn container(&self) -> &gtk::Box { &self.container }
+
+    pub fn focus(&self) {
+        use gtk::prelude::*;
+
+        self.list.grab_focus();
+    }
+}
+
+fn now() -> u64 {
+    time::SystemTime::now()
+        .duration_since(time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+fn outcome_label(outcome: page_state::Outcome) -> &'static str {
+    match outcome {
+        page_state::Outcome::Allowed => "Allowed",
+        page_state::Outcome::Denied => "Denied by rules",
+        page_state::Outcome::Filtered => "Filtered",
+    }
+}
+
+/// Shows the requests of the active page.
+fn populate(app: &app::Handle) {
+    use gtk::prelude::*;
+    use webkit2gtk::{ WebViewExt };
+
+    let map = app.stored();
+    let map = map.requests();
+    map.model.clear();
+
+    let page_state_store = app.page_state_store();
+    let data = app.active_webview().and_then(|view| {
+        page_state_store.lock()
+            .expect("exclusive access to page state store")
+            .get_data(view.get_page_id())
+    });
+    let data = match data {
+        Some(data) => data,
+        None => {
+            map.summary.set_text("No requests");
+            return;
+        },
+    };
+
+    let now = now();
+    let mut request_count = 0;
+    let mut blocked_count = 0;
+    for stats in data.targets() {
+        request_count += stats.allowed + stats.blocked;
+        blocked_count += stats.blocked;
+
+        let host = text::escape(stats.host.as_str());
+        let host: &str = &host;
+        let allowed = format!("{} allowed", stats.allowed);
+        let blocked = format!("{} blocked", stats.blocked);
+        let time = format!("first {}, last {}",
+            text::format_age(stats.first_request, now),
+            text::format_age(stats.last_request, now),
+        );
+        let iter = map.model.insert_with_values(
+            None,
+            None,
+            &[COL_NAME, COL_ALLOWED, COL_BLOCKED, COL_TIME],
+            &[&host, &allowed, &blocked, &time],
+        );
+
+        for request in data.blocked().iter().filter(|request| request.target == stats.host) {
+            let uri = text::escape(&request.uri);
+            let uri: &str = &uri;
+            map.model.insert_with_values(
+                Some(&iter),
+                None,
+                &[COL_NAME, COL_ALLOWED, COL_BLOCKED, COL_TIME],
+                &[&uri, &"", &outcome_label(request.outcome), &text::format_age(request.time, now)],
+            );
+        }
+    }
+
+    map.summary.set_text(&format!("{} {} from {} to {} {}, {} blocked",
+        request_count,
+        text::pluralize(request_count, "request", "requests"),
+        data.host().as_str(),
+        data.targets().len(),
+        text::pluralize(data.targets().len() as u64, "host", "hosts"),
+        blocked_count,
+    ));
+}
+
+pub fn setup(app: &app::Handle) {
+    use gtk::prelude::*;
+    use layout::{ BuildBox };
+    use pango;
+
+    let map = app.stored();
+    let map = map.requests();
+    map.container().add_start(&layout::hbox()
+        .add_start(&map.summary)
+        .add_end(&map.refresh_button)
+    );
+    map.container().add_start_fill(&scrolled::create(map.list.clone()));
+    map.list.set_model(&map.model);
+
+    let name_column = {
+        let column = gtk::TreeViewColumn::new();
+        let cell = gtk::CellRendererText::new();
+        cell.set_property_ellipsize(pango::EllipsizeMode::End);
+        column.pack_start(&cell, true);
+        column.add_attribute(&cell, "text", COL_NAME as i32);
+        column.set_title("Host");
+        column.set_expand(true);
+        column
+    };
+
+    let allowed_column = {
+        let column = gtk::TreeViewColumn::new();
+        let cell = gtk::CellRendererText::new();
+        column.pack_start(&cell, true);
+        column.add_attribute(&cell, "text", COL_ALLOWED as i32);
+        column.set_title("Allowed");
+        column
+    };
+
+    let blocked_column = {
+        let column = gtk::TreeViewColumn::new();
+        let cell = gtk::CellRendererText::new();
+        column.pack_start(&cell, true);
+        column.add_attribute(&cell, "text", COL_BLOCKED as i32);
+        column.set_title("Blocked");
+        column
+    };
+
+    let time_column = {
+        let column = gtk::TreeViewColumn::new();
+        let cell = gtk::CellRendererText::new();
+        column.pack_start(&cell, true);
+        column.add_attribute(&cell, "text", COL_TIME as i32);
+        column.set_title("Time");
+        column
+    };
+
+    map.list.append_column(&name_column);
+    map.list.append_column(&allowed_column);
+    map.list.append_column(&blocked_column);
+    map.list.append_column(&time_column);
+
+    map.container.connect_map(with_cloned!(app, move |_container| {
+        populate(&app);
+    }));
+
+    map.refresh_button.connect_clicked(with_cloned!(app, move |_button| {
+        populate(&app);
+    }));
+
+    app.page_tree_view().on_selection_change(with_cloned!(app, move |_map, _id| {
+        let map = app.stored();
+        if map.requests().container.get_mapped() {
+            populate(&app);
+        }
+    }));
+}
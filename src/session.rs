@@ -2,6 +2,7 @@
 use std::path;
 use std::collections;
 use std::cmp;
+use std::cell;
 
 use rusqlite;
 use gtk;
@@ -9,13 +10,36 @@ use gtk;
 use text;
 use page_store;
 use page_tree_store;
+use portable_tree;
 use storage;
 
+pub type Id = i64;
+
+pub type SnapshotId = i64;
+
+/// A named page tree stored in the session database.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub id: Id,
+    pub name: String,
+}
+
+/// A stored copy of a session's page tree.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub id: SnapshotId,
+    /// Seconds since the Unix epoch.
+    pub created: u64,
+    pub page_count: u32,
+}
+
 pub struct Node {
     id: page_store::Id,
     title: Option<text::RcString>,
     uri: text::RcString,
     is_pinned: bool,
+    is_expanded: bool,
+    session_state: Option<Vec<u8>>,
     children: Vec<Node>,
     is_selected: bool,
 }
@@ -30,6 +54,13 @@ impl Node {
 
     pub fn is_pinned(&self) -> bool { self.is_pinned }
 
+    pub fn is_expanded(&self) -> bool { self.is_expanded }
+
+    /// Serialized back/forward list of the page, if one was captured.
+    pub fn session_state(&self) -> Option<&[u8]> {
+        self.session_state.as_ref().map(|state| state.as_slice())
+    }
+
     pub fn children(&self) -> &[Node] { &self.children }
 
     pub fn find_selected(&self) -> Option<page_store::Id> {
@@ -51,49 +82,82 @@ pub struct Tree {
 
 impl Tree {
 
-    fn from_storage(conn: &rusqlite::Connection) -> Result<Self, rusqlite::Error> {
-        log_debug!("loading page tree from storage");
+    fn from_storage(conn: &rusqlite::Connection, session: Id) -> Result<Self, rusqlite::Error> {
+        log_debug!("loading page tree for session {} from storage", session);
+
+        let selected = conn.query_row(
+            "SELECT last_selected FROM session WHERE id = ?",
+            &[&session],
+            |row| row.get(0),
+        );
+        let selected: Option<page_store::Id> = match selected {
+            Ok(selected) => selected,
+            Err(rusqlite::Error::QueryReturnedNoRows) => None,
+            Err(error) => return Err(error),
+        };
+        Tree::from_query(conn, "
+            SELECT page.id, page.parent, page.title, page.uri, page.is_pinned,
+                page.is_expanded, state.state
+            FROM page_tree AS page
+            LEFT JOIN page_session_state AS state
+                ON state.session = page.session AND state.id = page.id
+            WHERE page.session = ?
+            ORDER BY page.parent, page.position
+        ", session, selected)
+    }
+
+    fn from_snapshot(conn: &rusqlite::Connection, snapshot: SnapshotId)
+    -> Result<Self, rusqlite::Error> {
+        log_debug!("loading page tree of snapshot {} from storage", snapshot);
+
+        Tree::from_query(conn, "
+            SELECT id, parent, title, uri, is_pinned, is_expanded, NULL
+            FROM page_tree_snapshot_page
+            WHERE snapshot = ?
+            ORDER BY parent, position
+        ", snapshot, None)
+    }
+
+    fn from_query(
+        conn: &rusqlite::Connection,
+        query: &str,
+        key: i64,
+        selected: Option<page_store::Id>,
+    ) -> Result<Self, rusqlite::Error> {
 
         fn inflate_children(
-            parent_map: &mut collections::HashMap<
-                Option<page_store::Id>,
-                Vec<(page_store::Id, Option<text::RcString>, text::RcString, bool)>,
-            >,
+            parent_map: &mut collections::HashMap<Option<page_store::Id>, Vec<Node>>,
             parent: Option<page_store::Id>,
             selected: Option<page_store::Id>,
         ) -> Vec<Node> {
-            let children = parent_map.remove(&parent).unwrap_or_else(|| Vec::new());
-            let mut nodes = Vec::new();
-            for (id, title, uri, is_pinned) in children {
-                nodes.push(Node {
-                    id, title, uri, is_pinned,
-                    children: inflate_children(parent_map, Some(id), selected),
-                    is_selected: Some(id) == selected,
-                });
+            let mut nodes = parent_map.remove(&parent).unwrap_or_else(|| Vec::new());
+            for node in &mut nodes {
+                node.children = inflate_children(parent_map, Some(node.id), selected);
+                node.is_selected = Some(node.id) == selected;
             }
             nodes
         }
 
-        let selected: Option<page_store::Id> = conn
-            .query_row("SELECT id FROM last_selected", &[], |row| row.get(0))
-            .expect("last selected row exists in session storage");
-        let mut stmt = conn.prepare("
-            SELECT id, parent, title, uri, is_pinned
-            FROM page_tree
-            ORDER BY parent, position
-        ")?;
-        let mut rows = stmt.query(&[])?;
+        let mut stmt = conn.prepare(query)?;
+        let mut rows = stmt.query(&[&key])?;
 
         let mut parent_map = collections::HashMap::new();
         while let Some(row) = rows.next() {
             let row = row?;
-            let id: page_store::Id = row.get(0);
             let parent: Option<page_store::Id> = row.get(1);
             let title: Option<String> = row.get(2);
             let uri: String = row.get(3);
-            let is_pinned: bool = row.get(4);
             let entry = parent_map.entry(parent).or_insert_with(|| Vec::new());
-            entry.push((id, title.map(|t| t.into()), uri.into(), is_pinned));
+            entry.push(Node {
+                id: row.get(0),
+                title: title.map(|t| t.into()),
+                uri: uri.into(),
+                is_pinned: row.get(4),
+                is_expanded: row.get(5),
+                session_state: row.get(6),
+                children: Vec::new(),
+                is_selected: false,
+            });
         }
 
         let mut roots = inflate_children(&mut parent_map, None, selected);
@@ -115,6 +179,23 @@ impl Tree {
 
     pub fn children(&self) -> &[Node] { &self.children }
 
+    pub fn find_node(&self, id: page_store::Id) -> Option<&Node> {
+
+        fn find_in_nodes(nodes: &[Node], id: page_store::Id) -> Option<&Node> {
+            for node in nodes {
+                if node.id == id {
+                    return Some(node);
+                }
+                if let Some(found) = find_in_nodes(&node.children, id) {
+                    return Some(found);
+                }
+            }
+            None
+        }
+
+        find_in_nodes(&self.children, id)
+    }
+
     pub fn compact(&mut self) {
         log_debug!("compacting page tree");
 
@@ -160,16 +241,159 @@ impl Tree {
     }
 }
 
+fn add_named_sessions(tx: &mut rusqlite::Transaction) -> Result<(), rusqlite::Error> {
+    tx.execute("
+        CREATE TABLE session (
+            id INTEGER PRIMARY KEY,
+            name TEXT NOT NULL UNIQUE,
+            last_selected INTEGER,
+            is_current INTEGER NOT NULL
+        )
+    ", &[])?;
+    tx.execute("
+        INSERT INTO session (id, name, last_selected, is_current)
+        VALUES (1, ?, (SELECT id FROM last_selected LIMIT 1), 1)
+    ", &[&DEFAULT_NAME])?;
+    tx.execute("
+        CREATE TABLE session_page_tree (
+            session INTEGER NOT NULL,
+            id INTEGER NOT NULL,
+            parent INTEGER,
+            position INTEGER NOT NULL,
+            title TEXT,
+            uri TEXT,
+            is_pinned INTEGER,
+            PRIMARY KEY (session, id)
+        )
+    ", &[])?;
+    tx.execute("
+        INSERT INTO session_page_tree (session, id, parent, position, title, uri, is_pinned)
+        SELECT 1, id, parent, position, title, uri, is_pinned FROM page_tree
+    ", &[])?;
+    tx.execute("DROP TABLE page_tree", &[])?;
+    tx.execute("ALTER TABLE session_page_tree RENAME TO page_tree", &[])?;
+    tx.execute("DROP TABLE last_selected", &[])?;
+    Ok(())
+}
+
+fn add_page_session_state(tx: &mut rusqlite::Transaction) -> Result<(), rusqlite::Error> {
+    tx.execute("ALTER TABLE page_tree ADD COLUMN session_state BLOB", &[])?;
+    Ok(())
+}
+
+fn add_page_expanded_state(tx: &mut rusqlite::Transaction) -> Result<(), rusqlite::Error> {
+    tx.execute("
+        ALTER TABLE page_tree ADD COLUMN is_expanded INTEGER NOT NULL DEFAULT 0
+    ", &[])?;
+    Ok(())
+}
+
+fn add_tree_snapshots(tx: &mut rusqlite::Transaction) -> Result<(), rusqlite::Error> {
+    tx.execute("
+        CREATE TABLE page_tree_snapshot (
+            id INTEGER PRIMARY KEY,
+            session INTEGER NOT NULL,
+            created INTEGER NOT NULL,
+            page_count INTEGER NOT NULL
+        )
+    ", &[])?;
+    tx.execute("
+        CREATE TABLE page_tree_snapshot_page (
+            snapshot INTEGER NOT NULL,
+            id INTEGER NOT NULL,
+            parent INTEGER,
+            position INTEGER NOT NULL,
+            title TEXT,
+            uri TEXT,
+            is_pinned INTEGER,
+            is_expanded INTEGER NOT NULL,
+            PRIMARY KEY (snapshot, id)
+        )
+    ", &[])?;
+    Ok(())
+}
+
+/// Session states are only written when a page is saved, so changes to the
+/// tree don't have to carry them.
+fn move_page_session_state(tx: &mut rusqlite::Transaction) -> Result<(), rusqlite::Error> {
+    tx.execute("
+        CREATE TABLE page_session_state (
+            session INTEGER NOT NULL,
+            id INTEGER NOT NULL,
+            state BLOB NOT NULL,
+            PRIMARY KEY (session, id)
+        )
+    ", &[])?;
+    tx.execute("
+        INSERT INTO page_session_state (session, id, state)
+        SELECT session, id, session_state FROM page_tree WHERE session_state IS NOT NULL
+    ", &[])?;
+    tx.execute("
+        CREATE TABLE page_tree_without_state (
+            session INTEGER NOT NULL,
+            id INTEGER NOT NULL,
+            parent INTEGER,
+            position INTEGER NOT NULL,
+            title TEXT,
+            uri TEXT,
+            is_pinned INTEGER,
+            is_expanded INTEGER NOT NULL DEFAULT 0,
+            PRIMARY KEY (session, id)
+        )
+    ", &[])?;
+    tx.execute("
+        INSERT INTO page_tree_without_state
+        (session, id, parent, position, title, uri, is_pinned, is_expanded)
+        SELECT session, id, parent, position, title, uri, is_pinned, is_expanded
+        FROM page_tree
+    ", &[])?;
+    tx.execute("DROP TABLE page_tree", &[])?;
+    tx.execute("ALTER TABLE page_tree_without_state RENAME TO page_tree", &[])?;
+    Ok(())
+}
+
+const MIGRATIONS: &[storage::Migration] = &[
+    add_named_sessions,
+    add_page_session_state,
+    add_page_expanded_state,
+    add_tree_snapshots,
+    move_page_session_state,
+];
+
+/// Number of page tree snapshots kept per session.
+const SNAPSHOT_KEEP: usize = 50;
+
+/// Name of the session created for existing and new profiles.
+pub const DEFAULT_NAME: &str = "Default";
+
+/// The current session, creating the default one when there are none.
+fn load_current(storage: &storage::Storage) -> Result<Id, storage::Error> {
+    storage.with_connection(|conn| {
+        let current = conn.query_row("
+            SELECT id FROM session ORDER BY is_current DESC, id LIMIT 1
+        ", &[], |row| row.get(0));
+        match current {
+            Err(rusqlite::Error::QueryReturnedNoRows) => {
+                conn.execute("
+                    INSERT INTO session (name, last_selected, is_current) VALUES (?, NULL, 1)
+                ", &[&DEFAULT_NAME])?;
+                Ok(conn.last_insert_rowid())
+            },
+            current => current,
+        }
+    })
+}
+
 pub struct Session {
     storage: storage::Storage,
+    current: cell::Cell<Id>,
 }
 
 impl Session {
 
     pub fn open_or_create<P>(path: P) -> Result<Self, storage::Error>
     where P: AsRef<path::Path> {
-        Ok(Session {
-            storage: storage::Storage::open_or_create(
+        let storage = storage::Storage::open_or_create(
                 path,
                 |conn| {
                     conn.execute("CREATE TABLE last_selected (id INTEGER)", &[])?;
@@ -186,20 +410,346 @@ impl Session {
                     ", &[])?;
                     Ok(())
                 },
+                MIGRATIONS,
                 |_conn| Ok(()),
-            )?,
+            )?;
+        Session::from_storage(storage)
+    }
+
+    /// Opens an existing session database without creating one.
+    pub fn open<P>(path: P) -> Result<Self, storage::Error>
+    where P: AsRef<path::Path> {
+        let storage = storage::Storage::open(path, MIGRATIONS, |_conn| Ok(()))?;
+        Session::from_storage(storage)
+    }
+
+    fn from_storage(storage: storage::Storage) -> Result<Self, storage::Error> {
+        let current = load_current(&storage)?;
+        Ok(Session {
+            storage,
+            current: cell::Cell::new(current),
+        })
+    }
+
+    pub fn backup_to<P>(&self, path: P) -> Result<(), storage::Error>
+    where P: AsRef<path::Path> {
+        self.storage.backup_to(path)
+    }
+
+    pub fn recovery(&self) -> Option<&storage::Recovery> { self.storage.recovery() }
+
+    /// The session the page tree is loaded from and saved to.
+    pub fn current(&self) -> Id { self.current.get() }
+
+    pub fn list(&self) -> Result<Vec<Entry>, storage::Error> {
+        self.storage.with_connection(|conn| {
+            let mut stmt = conn.prepare("SELECT id, name FROM session ORDER BY name")?;
+            let mut rows = stmt.query(&[])?;
+            let mut sessions = Vec::new();
+            while let Some(row) = rows.next() {
+                let row = row?;
+                sessions.push(Entry {
+                    id: row.get_checked(0)?,
+                    name: row.get_checked(1)?,
+                });
+            }
+            Ok(sessions)
+        })
+    }
+
+    pub fn find(&self, name: &str) -> Result<Option<Id>, storage::Error> {
+        self.storage.with_connection(|conn| {
+            let mut stmt = conn.prepare("SELECT id FROM session WHERE name = ?")?;
+            let mut rows = stmt.query(&[&name])?;
+            let id = match rows.next() {
+                Some(row) => Some(row?.get_checked(0)?),
+                None => None,
+            };
+            Ok(id)
+        })
+    }
+
+    pub fn get_name(&self, id: Id) -> Result<Option<String>, storage::Error> {
+        self.storage.with_connection(|conn| {
+            let mut stmt = conn.prepare("SELECT name FROM session WHERE id = ?")?;
+            let mut rows = stmt.query(&[&id])?;
+            let name = match rows.next() {
+                Some(row) => Some(row?.get_checked(0)?),
+                None => None,
+            };
+            Ok(name)
+        })
+    }
+
+    /// Creates an empty session, names have to be unique.
+    pub fn create(&self, name: &str) -> Result<Id, storage::Error> {
+        log_debug!("creating session {:?}", name);
+        self.storage.with_transaction(|tx| {
+            tx.execute("
+                INSERT INTO session (name, last_selected, is_current) VALUES (?, NULL, 0)
+            ", &[&name])?;
+            Ok(tx.last_insert_rowid())
         })
     }
 
+    pub fn rename(&self, id: Id, name: &str) -> Result<(), storage::Error> {
+        log_debug!("renaming session {} to {:?}", id, name);
+        self.storage.with_connection(|conn| {
+            conn.execute("UPDATE session SET name = ? WHERE id = ?", &[&name, &id])?;
+            Ok(())
+        })
+    }
+
+    /// Copies the page tree and selection of a session into a new one.
+    pub fn duplicate(&self, id: Id, name: &str) -> Result<Id, storage::Error> {
+        log_debug!("duplicating session {} as {:?}", id, name);
+        self.storage.with_transaction(|tx| {
+            tx.execute("
+                INSERT INTO session (name, last_selected, is_current)
+                SELECT ?, last_selected, 0 FROM session WHERE id = ?
+            ", &[&name, &id])?;
+            let new_id = tx.last_insert_rowid();
+            tx.execute("
+                INSERT INTO page_tree
+                (session, id, parent, position, title, uri, is_pinned, is_expanded)
+                SELECT ?, id, parent, position, title, uri, is_pinned, is_expanded
+                FROM page_tree
+                WHERE session = ?
+            ", &[&new_id, &id])?;
+            tx.execute("
+                INSERT INTO page_session_state (session, id, state)
+                SELECT ?, id, state FROM page_session_state WHERE session = ?
+            ", &[&new_id, &id])?;
+            Ok(new_id)
+        })
+    }
+
+    /// Removes a session and its page tree. The current session can't be
+    /// deleted.
+    pub fn delete(&self, id: Id) -> Result<(), storage::Error> {
+        log_debug!("deleting session {}", id);
+        if id == self.current() {
+            return Ok(());
+        }
+        self.storage.with_transaction(|tx| {
+            tx.execute("DELETE FROM page_tree WHERE session = ?", &[&id])?;
+            tx.execute("DELETE FROM page_session_state WHERE session = ?", &[&id])?;
+            tx.execute("
+                DELETE FROM page_tree_snapshot_page
+                WHERE snapshot IN (SELECT id FROM page_tree_snapshot WHERE session = ?)
+            ", &[&id])?;
+            tx.execute("DELETE FROM page_tree_snapshot WHERE session = ?", &[&id])?;
+            tx.execute("DELETE FROM session WHERE id = ?", &[&id])?;
+            Ok(())
+        })
+    }
+
+    /// Stores a copy of the current page tree unless it is empty or
+    /// unchanged since the last snapshot. Only the newest `SNAPSHOT_KEEP`
+    /// snapshots of a session are kept.
+    pub fn take_snapshot(&self) -> Result<Option<SnapshotId>, storage::Error> {
+        let session = self.current();
+        self.storage.with_transaction(|tx| {
+            let page_count: u32 = tx.query_row("
+                SELECT COUNT(*) FROM page_tree WHERE session = ?
+            ", &[&session], |row| row.get(0))?;
+            if page_count == 0 {
+                return Ok(None);
+            }
+            let latest: Option<SnapshotId> = tx.query_row("
+                SELECT MAX(id) FROM page_tree_snapshot WHERE session = ?
+            ", &[&session], |row| row.get(0))?;
+            if let Some(latest) = latest {
+                let changes: u32 = tx.query_row("
+                    SELECT COUNT(*) FROM (
+                        SELECT id, parent, position, title, uri, is_pinned
+                        FROM page_tree WHERE session = ?1
+                        EXCEPT
+                        SELECT id, parent, position, title, uri, is_pinned
+                        FROM page_tree_snapshot_page WHERE snapshot = ?2
+                    )
+                ", &[&session, &latest], |row| row.get(0))?;
+                let latest_count: u32 = tx.query_row("
+                    SELECT page_count FROM page_tree_snapshot WHERE id = ?
+                ", &[&latest], |row| row.get(0))?;
+                if changes == 0 && latest_count == page_count {
+                    return Ok(None);
+                }
+            }
+            tx.execute("
+                INSERT INTO page_tree_snapshot (session, created, page_count)
+                VALUES (?, strftime('%s', 'now'), ?)
+            ", &[&session, &page_count])?;
+            let snapshot = tx.last_insert_rowid();
+            tx.execute("
+                INSERT INTO page_tree_snapshot_page
+                (snapshot, id, parent, position, title, uri, is_pinned, is_expanded)
+                SELECT ?, id, parent, position, title, uri, is_pinned, is_expanded
+                FROM page_tree
+                WHERE session = ?
+            ", &[&snapshot, &session])?;
+            tx.execute("
+                DELETE FROM page_tree_snapshot_page
+                WHERE snapshot IN (
+                    SELECT id FROM page_tree_snapshot WHERE session = ?
+                    ORDER BY id DESC LIMIT -1 OFFSET ?
+                )
+            ", &[&session, &(SNAPSHOT_KEEP as i64)])?;
+            tx.execute("
+                DELETE FROM page_tree_snapshot
+                WHERE session = ? AND id NOT IN (
+                    SELECT snapshot FROM page_tree_snapshot_page
+                )
+            ", &[&session])?;
+            Ok(Some(snapshot))
+        })
+    }
+
+    /// Snapshots of the current session, newest first.
+    pub fn list_snapshots(&self) -> Result<Vec<Snapshot>, storage::Error> {
+        let session = self.current();
+        self.storage.with_connection(|conn| {
+            let mut stmt = conn.prepare("
+                SELECT id, created, page_count
+                FROM page_tree_snapshot
+                WHERE session = ?
+                ORDER BY id DESC
+            ")?;
+            let mut rows = stmt.query(&[&session])?;
+            let mut snapshots = Vec::new();
+            while let Some(row) = rows.next() {
+                let row = row?;
+                let created: i64 = row.get_checked(1)?;
+                snapshots.push(Snapshot {
+                    id: row.get_checked(0)?,
+                    created: created as u64,
+                    page_count: row.get_checked(2)?,
+                });
+            }
+            Ok(snapshots)
+        })
+    }
+
+    pub fn load_snapshot_tree(&self, snapshot: SnapshotId) -> Result<Tree, storage::Error> {
+        self.storage.with_connection(|conn| Tree::from_snapshot(conn, snapshot))
+    }
+
+    /// Replaces the page tree of the current session with the one of the
+    /// snapshot. The page tree has to be loaded again afterwards.
+    ///
+    /// Pages keeping their id, address and parent keep their session state.
+    pub fn restore_snapshot(&self, snapshot: SnapshotId) -> Result<(), storage::Error> {
+        log_debug!("restoring snapshot {}", snapshot);
+        let session = self.current();
+        self.storage.with_transaction(|tx| {
+            tx.execute("
+                DELETE FROM page_session_state
+                WHERE session = ?1 AND id NOT IN (
+                    SELECT page.id
+                    FROM page_tree AS page
+                    JOIN page_tree_snapshot_page AS snapshot_page
+                        ON snapshot_page.id = page.id
+                        AND snapshot_page.uri IS page.uri
+                        AND snapshot_page.parent IS page.parent
+                    WHERE page.session = ?1 AND snapshot_page.snapshot = ?2
+                )
+            ", &[&session, &snapshot])?;
+            tx.execute("DELETE FROM page_tree WHERE session = ?", &[&session])?;
+            tx.execute("
+                INSERT INTO page_tree
+                (session, id, parent, position, title, uri, is_pinned, is_expanded)
+                SELECT ?, id, parent, position, title, uri, is_pinned, is_expanded
+                FROM page_tree_snapshot_page
+                WHERE snapshot = ?
+            ", &[&session, &snapshot])?;
+            Ok(())
+        })
+    }
+
+    /// Makes another session current, the page tree has to be loaded again
+    /// afterwards.
+    pub fn switch_to(&self, id: Id) -> Result<(), storage::Error> {
+        log_debug!("switching to session {}", id);
+        self.storage.with_transaction(|tx| {
+            tx.execute("UPDATE session SET is_current = (id = ?)", &[&id])?;
+            Ok(())
+        })?;
+        self.current.set(id);
+        Ok(())
+    }
+
     pub fn load_tree(&self) -> Result<Tree, storage::Error> {
-        self.storage.with_connection(Tree::from_storage)
+        self.load_tree_of(self.current())
+    }
+
+    /// Loads the page tree of any session without switching to it.
+    pub fn load_tree_of(&self, session: Id) -> Result<Tree, storage::Error> {
+        self.storage.with_connection(|conn| Tree::from_storage(conn, session))
+    }
+
+    /// Inserts pages into a stored session as the last children of the
+    /// parent, or at the end of the top level, without loading it into a
+    /// page store. Returns the ids of the grafted top-level pages.
+    pub fn graft(
+        &self,
+        session: Id,
+        parent: Option<page_store::Id>,
+        pages: &[portable_tree::Page],
+    ) -> Result<Vec<page_store::Id>, storage::Error> {
+        log_debug!("grafting {} pages into session {}", pages.len(), session);
+
+        fn insert_pages(
+            tx: &rusqlite::Transaction,
+            session: Id,
+            parent: Option<page_store::Id>,
+            pages: &[portable_tree::Page],
+            last_id: &mut page_store::Id,
+        ) -> Result<Vec<page_store::Id>, rusqlite::Error> {
+            let first_position: i64 = tx.query_row("
+                SELECT COALESCE(MAX(position) + 1, 0)
+                FROM page_tree
+                WHERE session = ? AND parent IS ?
+            ", &[&session, &parent], |row| row.get(0))?;
+            let mut ids = Vec::new();
+            for (index, page) in pages.iter().enumerate() {
+                *last_id += 1;
+                let id = *last_id;
+                tx.execute("
+                    INSERT INTO page_tree
+                    (session, id, parent, position, title, uri, is_pinned, is_expanded)
+                    VALUES
+                    (?, ?, ?, ?, ?, ?, ?, 0)
+                ", &[
+                    &session,
+                    &id,
+                    &parent,
+                    &(first_position + index as i64),
+                    &page.title,
+                    &page.uri,
+                    &(page.pinned && parent.is_none()),
+                ])?;
+                insert_pages(tx, session, Some(id), &page.children, last_id)?;
+                ids.push(id);
+            }
+            Ok(ids)
+        }
+
+        self.storage.with_transaction(|tx| {
+            let mut last_id: page_store::Id = tx.query_row("
+                SELECT COALESCE(MAX(id), 0) FROM page_tree WHERE session = ?
+            ", &[&session], |row| row.get(0))?;
+            insert_pages(tx, session, parent, pages, &mut last_id)
+        })
     }
 
     pub fn update_selected(&self, id: page_store::Id)
     -> Result<(), storage::Error> {
         log_debug!("updating selected page to {}", id);
         self.storage.with_connection(|conn| {
-            conn.execute("UPDATE last_selected SET id = ?", &[&id])?;
+            conn.execute("UPDATE session SET last_selected = ? WHERE id = ?", &[
+                &id,
+                &self.current(),
+            ])?;
             Ok(())
         })
     }
@@ -216,18 +766,58 @@ impl Session {
         self.storage.with_connection(|conn| {
             conn.execute("
                 UPDATE page_tree
-                SET title = ?, uri = ?, is_pinned = ?
-                WHERE id = ?
+                SET title = ?, uri = ?, is_pinned = ?, is_expanded = ?
+                WHERE session = ? AND id = ?
             ", &[
                 &data.title.as_ref().map(|s| s.as_str()),
                 &data.uri.as_str(),
                 &data.is_pinned,
+                &data.is_expanded,
+                &self.current(),
                 &id,
             ])?;
             Ok(())
         })
     }
 
+    /// Stores the serialized back/forward list of a page, or removes it
+    /// when there is none.
+    pub fn update_session_state(&self, id: page_store::Id, state: Option<&[u8]>)
+    -> Result<(), storage::Error> {
+        log_debug!("updating page {} session state", id);
+        let session = self.current();
+        self.storage.with_connection(|conn| {
+            match state {
+                Some(state) => conn.execute("
+                    INSERT OR REPLACE INTO page_session_state (session, id, state)
+                    VALUES (?, ?, ?)
+                ", &[&session, &id, &state])?,
+                None => conn.execute("
+                    DELETE FROM page_session_state WHERE session = ? AND id = ?
+                ", &[&session, &id])?,
+            };
+            Ok(())
+        })
+    }
+
+    /// Replaces all stored session states of the current session, needed
+    /// after page ids changed.
+    pub fn update_all_session_states(&self, page_store: &page_store::Store)
+    -> Result<(), storage::Error> {
+        log_debug!("updating all session states");
+        let session = self.current();
+        self.storage.with_transaction(|tx| {
+            tx.execute("DELETE FROM page_session_state WHERE session = ?", &[&session])?;
+            let mut stmt = tx.prepare_cached("
+                INSERT INTO page_session_state (session, id, state) VALUES (?, ?, ?)
+            ")?;
+            for (id, state) in page_store.session_states() {
+                stmt.execute(&[&session, &id, &state])?;
+            }
+            Ok(())
+        })
+    }
+
     pub fn update_all(&self, page_store: &page_store::Store)
     -> Result<(), storage::Error> {
         use gtk::prelude::*;
@@ -236,6 +826,7 @@ impl Session {
 
         fn insert_children(
             stmt: &mut rusqlite::CachedStatement,
+            session: Id,
             page_store: &page_store::Store,
             page_tree_store: &gtk::TreeStore,
             parent: Option<&gtk::TreeIter>,
@@ -250,28 +841,44 @@ impl Session {
                     None => continue,
                 };
                 stmt.execute(&[
+                    &session,
                     &id,
                     &parent_id,
                     &position,
                     &data.title.as_ref().map(|s| s.as_str()),
                     &data.uri.as_str(),
                     &data.is_pinned,
+                    &data.is_expanded,
                 ])?;
-                insert_children(stmt, page_store, page_tree_store, Some(&iter), Some(id))?;
+                insert_children(
+                    stmt,
+                    session,
+                    page_store,
+                    page_tree_store,
+                    Some(&iter),
+                    Some(id),
+                )?;
             }
             Ok(())
         }
 
         let page_tree_store = page_store.tree_store();
+        let session = self.current();
         self.storage.with_transaction(|tx| {
-            tx.execute("DELETE FROM page_tree", &[])?;
-            let mut stmt = tx.prepare_cached("
-                INSERT INTO page_tree
-                (id, parent, position, title, uri, is_pinned)
-                VALUES
-                (?, ?, ?, ?, ?, ?)
-            ")?;
-            insert_children(&mut stmt, page_store, &page_tree_store, None, None)?;
+            tx.execute("DELETE FROM page_tree WHERE session = ?", &[&session])?;
+            {
+                let mut stmt = tx.prepare_cached("
+                    INSERT INTO page_tree
+                    (session, id, parent, position, title, uri, is_pinned, is_expanded)
+                    VALUES
+                    (?, ?, ?, ?, ?, ?, ?, ?)
+                ")?;
+                insert_children(&mut stmt, session, page_store, page_tree_store, None, None)?;
+            }
+            tx.execute("
+                DELETE FROM page_session_state
+                WHERE session = ?1 AND id NOT IN (SELECT id FROM page_tree WHERE session = ?1)
+            ", &[&session])?;
             Ok(())
         })
     }
@@ -296,6 +296,7 @@ impl Shortcuts {
         let storage = storage::Storage::open_or_create(
             path,
             init_storage,
+            MIGRATIONS,
             storage::do_nothing,
         )?;
 
@@ -318,6 +319,13 @@ impl Shortcuts {
         })
     }
 
+    pub fn backup_to<P>(&self, path: P) -> Result<(), storage::Error>
+    where P: AsRef<path::Path> {
+        self.storage.backup_to(path)
+    }
+
+    pub fn recovery(&self) -> Option<&storage::Recovery> { self.storage.recovery() }
+
     pub fn names(&self) -> Vec<String> {
         self.items.borrow().iter().map(|item| item.0.clone()).collect()
     }
@@ -363,6 +371,8 @@ impl Shortcuts {
     }
 }
 
+const MIGRATIONS: &[storage::Migration] = &[];
+
 fn init_storage(conn: &mut rusqlite::Connection) -> Result<(), rusqlite::Error> {
     conn.execute("
         CREATE TABLE shortcuts (
@@ -0,0 +1,170 @@
+
+use gtk;
+
+use app;
+use cookies;
+use webview;
+use domain_settings;
+
+const RES_OK: i32 = 2;
+const RES_CANCEL: i32 = 3;
+
+fn create_combo(labels: &[&str], active: usize) -> gtk::ComboBoxText {
+    use gtk::prelude::*;
+
+    let combo = gtk::ComboBoxText::new();
+    for label in labels {
+        combo.append_text(label);
+    }
+    combo.set_active(active as i32);
+    combo
+}
+
+fn switch_index(value: Option<bool>) -> usize {
+    match value {
+        None => 0,
+        Some(true) => 1,
+        Some(false) => 2,
+    }
+}
+
+fn switch_value(combo: &gtk::ComboBoxText) -> Option<bool> {
+    use gtk::prelude::*;
+
+    match combo.get_active() {
+        1 => Some(true),
+        2 => Some(false),
+        _ => None,
+    }
+}
+
+fn entry_text(entry: &gtk::Entry) -> Option<String> {
+    use gtk::prelude::*;
+
+    entry.get_text()
+        .map(|text| text.trim().to_string())
+        .and_then(|text| if text.is_empty() { None } else { Some(text) })
+}
+
+/// Zoom levels are entered in percent, an empty entry inherits the level.
+fn parse_zoom(entry: &gtk::Entry) -> Result<Option<f64>, ()> {
+    match entry_text(entry) {
+        None => Ok(None),
+        Some(text) => match text.trim_right_matches('%').trim().parse::<f64>() {
+            Ok(percent) if percent >= 10.0 && percent <= 500.0 => Ok(Some(percent / 100.0)),
+            _ => Err(()),
+        },
+    }
+}
+
+/// Lets the user edit the preferences stored for the host.
+pub fn edit(app: &app::Handle, host: &domain_settings::Host) {
+    use gtk::prelude::*;
+
+    let domains = app.domain_settings();
+    let current = domains.site_preferences(host);
+    let current_cookie_policy = domains.host_cookie_policy(host);
+
+    let dialog = gtk::Dialog::new();
+    dialog.set_title(&format!("Site Preferences for {}", host.as_str()));
+    dialog.set_transient_for(&app.window());
+    let ok_button = dialog.add_button("Ok", RES_OK);
+    dialog.add_button("Cancel", RES_CANCEL);
+    dialog.set_default_response(RES_OK);
+    dialog.set_modal(true);
+    dialog.set_destroy_with_parent(true);
+
+    let javascript_combo = create_combo(
+        &["Inherit", "Enabled", "Disabled"],
+        switch_index(current.javascript),
+    );
+    let images_combo = create_combo(
+        &["Inherit", "Load Automatically", "Don't Load"],
+        switch_index(current.images),
+    );
+    let autoplay_combo = create_combo(
+        &["Inherit", "Allow", "Require User Interaction"],
+        match current.autoplay {
+            None => 0,
+            Some(domain_settings::Autoplay::Allow) => 1,
+            Some(domain_settings::Autoplay::RequireGesture) => 2,
+        },
+    );
+    let cookies_combo = create_combo(
+        &["Inherit", "Always", "No Third-Party", "Never"],
+        match current_cookie_policy {
+            None => 0,
+            Some(domain_settings::CookiePolicy::Always) => 1,
+            Some(domain_settings::CookiePolicy::NoThirdParty) => 2,
+            Some(domain_settings::CookiePolicy::Never) => 3,
+        },
+    );
+
+    cookies_combo.set_tooltip_text(Some(
+        "Decides which new cookies are accepted while the site is in the active page. \
+            Cookies are removed after every page load when set to Never."
+    ));
+
+    let zoom_entry = gtk::Entry::new();
+    zoom_entry.set_placeholder_text("Inherit");
+    if let Some(zoom_level) = current.zoom_level {
+        zoom_entry.set_text(&format!("{}%", (zoom_level * 100.0).round()));
+    }
+
+    let user_agent_entry = gtk::Entry::new();
+    user_agent_entry.set_placeholder_text("Inherit");
+    user_agent_entry.set_text(current.user_agent.as_ref().map(|agent| agent.as_str()).unwrap_or(""));
+
+    zoom_entry.connect_property_text_notify({
+        let ok_button = ok_button.clone();
+        move |entry| ok_button.set_sensitive(parse_zoom(entry).is_ok())
+    });
+
+    let grid = gtk::Grid::new();
+    grid.attach(&gtk::Label::new("JavaScript"), 0, 0, 1, 1);
+    grid.attach(&javascript_combo, 1, 0, 1, 1);
+    grid.attach(&gtk::Label::new("Images"), 0, 1, 1, 1);
+    grid.attach(&images_combo, 1, 1, 1, 1);
+    grid.attach(&gtk::Label::new("Media Autoplay"), 0, 2, 1, 1);
+    grid.attach(&autoplay_combo, 1, 2, 1, 1);
+    grid.attach(&gtk::Label::new("Cookies"), 0, 3, 1, 1);
+    grid.attach(&cookies_combo, 1, 3, 1, 1);
+    grid.attach(&gtk::Label::new("Zoom Level"), 0, 4, 1, 1);
+    grid.attach(&zoom_entry, 1, 4, 1, 1);
+    grid.attach(&gtk::Label::new("User Agent"), 0, 5, 1, 1);
+    grid.attach(&user_agent_entry, 1, 5, 1, 1);
+    grid.show_all();
+    dialog.get_content_area().add(&grid);
+
+    let result = dialog.run();
+    let preferences = domain_settings::SitePreferences {
+        javascript: switch_value(&javascript_combo),
+        images: switch_value(&images_combo),
+        zoom_level: parse_zoom(&zoom_entry).unwrap_or(current.zoom_level),
+        user_agent: entry_text(&user_agent_entry),
+        autoplay: match autoplay_combo.get_active() {
+            1 => Some(domain_settings::Autoplay::Allow),
+            2 => Some(domain_settings::Autoplay::RequireGesture),
+            _ => None,
+        },
+    };
+    let cookie_policy = match cookies_combo.get_active() {
+        1 => Some(domain_settings::CookiePolicy::Always),
+        2 => Some(domain_settings::CookiePolicy::NoThirdParty),
+        3 => Some(domain_settings::CookiePolicy::Never),
+        _ => None,
+    };
+    dialog.destroy();
+
+    if result == RES_OK {
+        domains.set_site_preferences(host, &preferences);
+        if cookie_policy != current_cookie_policy {
+            domains.set_host_cookie_policy(host, cookie_policy);
+            cookies::refresh(app);
+        }
+        if let Some(view) = app.active_webview() {
+            webview::apply_site_preferences(app, &view);
+            webview::apply_cookie_policy(app, &view);
+        }
+    }
+}
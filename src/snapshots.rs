@@ -0,0 +1,342 @@
+
+use std::time;
+
+use gtk;
+use glib;
+
+use app;
+use app_action;
+use layout;
+use portable_tree;
+use scrolled;
+use session;
+use text;
+use window;
+
+/// Interval for storing the page tree if it changed since the last snapshot.
+const SNAPSHOT_INTERVAL_SECONDS: u32 = 60 * 10;
+
+const RES_OK: i32 = 2;
+const RES_CANCEL: i32 = 3;
+
+const COL_SNAPSHOT_ID: u32 = 0;
+const COL_SNAPSHOT_TIME: u32 = 1;
+const COL_SNAPSHOT_PAGES: u32 = 2;
+
+const COL_PAGE_ID: u32 = 0;
+const COL_PAGE_TITLE: u32 = 1;
+
+pub struct Map {
+    container: gtk::Box,
+    summary: gtk::Label,
+    list: gtk::TreeView,
+    model: gtk::ListStore,
+    pages: gtk::TreeView,
+    pages_model: gtk::TreeStore,
+    refresh_button: gtk::Button,
+    restore_button: gtk::Button,
+    reopen_button: gtk::Button,
+}
+
+impl Map {
+
+    pub fn new() -> Map {
+        let icon_size = gtk::IconSize::Button.into();
+        Map {
+            container: layout::vbox(),
+            summary: gtk::Label::new(""),
+            list: gtk::TreeView::new(),
+            pages: gtk::TreeView::new(),
+            refresh_button: gtk::Button::new_from_icon_name("gtk-refresh", icon_size),
+            restore_button: gtk::Button::new_with_label("Restore All Pages"),
+            reopen_button: gtk::Button::new_with_label("Reopen Selected Pages"),
+            model: gtk::ListStore::new(&[
+                <i64 as gtk::StaticType>::static_type(),
+                <String as gtk::StaticType>::static_type(),
+                <String as gtk::StaticType>::static_type(),
+            ]),
+            pages_model: gtk::TreeStore::new(&[
+                <u32 as gtk::StaticType>::static_type(),
+                <String as gtk::StaticType>::static_type(),
+            ]),
+        }
+    }
+
+    pub fn container(&self) -> &gtk::Box { &self.container }
+
+    pub fn focus(&self) {
+        use gtk::prelude::*;
+
+        self.list.grab_focus();
+    }
+}
+
+fn now() -> u64 {
+    time::SystemTime::now()
+        .duration_since(time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+/// Stores the page tree of the current session unless it is unchanged.
+pub fn create_logged(app: &app::Handle) {
+    let page_store = app.page_store();
+    let session = unwrap_or_return!(page_store.session());
+    match session.take_snapshot() {
+        Ok(Some(id)) => log_debug!("created page tree snapshot {}", id),
+        Ok(None) => log_debug!("page tree unchanged since last snapshot"),
+        Err(error) => eprintln!("Unable to create page tree snapshot: {:?}", error),
+    }
+}
+
+fn selected_snapshot(app: &app::Handle) -> Option<session::SnapshotId> {
+    use gtk::prelude::*;
+
+    let map = app.stored();
+    let (model, iter) = map.snapshots().list.get_selection().get_selected()?;
+    model.get_value(&iter, COL_SNAPSHOT_ID as i32).get()
+}
+
+fn selected_page(app: &app::Handle) -> Option<u32> {
+    use gtk::prelude::*;
+
+    let map = app.stored();
+    let (model, iter) = map.snapshots().pages.get_selection().get_selected()?;
+    model.get_value(&iter, COL_PAGE_ID as i32).get()
+}
+
+fn populate(app: &app::Handle) {
+    use gtk::prelude::*;
+
+    let map = app.stored();
+    let map = map.snapshots();
+    map.model.clear();
+    map.pages_model.clear();
+
+    let page_store = app.page_store();
+    let session = unwrap_or_return!(page_store.session());
+    let snapshots = match session.list_snapshots() {
+        Ok(snapshots) => snapshots,
+        Err(error) => {
+            eprintln!("Unable to list page tree snapshots: {:?}", error);
+            return;
+        },
+    };
+
+    let now = now();
+    for snapshot in &snapshots {
+        let pages = format!("{} {}",
+            snapshot.page_count,
+            text::pluralize(snapshot.page_count, "page", "pages"),
+        );
+        map.model.insert_with_values(
+            None,
+            &[COL_SNAPSHOT_ID, COL_SNAPSHOT_TIME, COL_SNAPSHOT_PAGES],
+            &[&snapshot.id, &text::format_age(snapshot.created, now), &pages],
+        );
+    }
+
+    map.summary.set_text(&format!("{} {}",
+        snapshots.len(),
+        text::pluralize(snapshots.len() as u64, "snapshot", "snapshots"),
+    ));
+}
+
+/// Shows the page tree of the selected snapshot.
+fn populate_pages(app: &app::Handle) {
+    use gtk::prelude::*;
+
+    fn insert_nodes(
+        model: &gtk::TreeStore,
+        parent: Option<&gtk::TreeIter>,
+        nodes: &[session::Node],
+    ) {
+        for node in nodes {
+            let title = text::escape(node.title().unwrap_or_else(|| node.uri()));
+            let title: &str = &title;
+            let iter = model.insert_with_values(
+                parent,
+                None,
+                &[COL_PAGE_ID, COL_PAGE_TITLE],
+                &[&node.id(), &title],
+            );
+            insert_nodes(model, Some(&iter), node.children());
+        }
+    }
+
+    let map = app.stored();
+    let map = map.snapshots();
+    map.pages_model.clear();
+
+    let snapshot = unwrap_or_return!(selected_snapshot(app));
+    let page_store = app.page_store();
+    let session = unwrap_or_return!(page_store.session());
+    match session.load_snapshot_tree(snapshot) {
+        Ok(tree) => insert_nodes(&map.pages_model, None, tree.children()),
+        Err(error) => eprintln!("Unable to load page tree snapshot: {:?}", error),
+    }
+}
+
+fn update_buttons(app: &app::Handle) {
+    use gtk::prelude::*;
+
+    let map = app.stored();
+    let map = map.snapshots();
+    map.restore_button.set_sensitive(selected_snapshot(app).is_some());
+    map.reopen_button.set_sensitive(selected_page(app).is_some());
+}
+
+pub fn setup(app: &app::Handle) {
+    use gtk::prelude::*;
+    use layout::{ BuildBox };
+    use pango;
+
+    let map = app.stored();
+    let map = map.snapshots();
+
+    map.container().add_start(&layout::hbox()
+        .add_start(&map.summary)
+        .add_end(&map.refresh_button)
+    );
+    map.container().add_start_fill(&scrolled::create(map.list.clone()));
+    map.container().add_start_fill(&scrolled::create(map.pages.clone()));
+    map.container().add_start(&layout::hbox()
+        .add_start(&map.restore_button)
+        .add_start(&map.reopen_button)
+    );
+    map.list.set_model(&map.model);
+    map.pages.set_model(&map.pages_model);
+
+    let time_column = {
+        let column = gtk::TreeViewColumn::new();
+        let cell = gtk::CellRendererText::new();
+        column.pack_start(&cell, true);
+        column.add_attribute(&cell, "text", COL_SNAPSHOT_TIME as i32);
+        column.set_title("Time");
+        column.set_expand(true);
+        column
+    };
+
+    let pages_column = {
+        let column = gtk::TreeViewColumn::new();
+        let cell = gtk::CellRendererText::new();
+        column.pack_start(&cell, true);
+        column.add_attribute(&cell, "text", COL_SNAPSHOT_PAGES as i32);
+        column.set_title("Pages");
+        column
+    };
+
+    let title_column = {
+        let column = gtk::TreeViewColumn::new();
+        let cell = gtk::CellRendererText::new();
+        cell.set_property_ellipsize(pango::EllipsizeMode::End);
+        column.pack_start(&cell, true);
+        column.add_attribute(&cell, "text", COL_PAGE_TITLE as i32);
+        column.set_expand(true);
+        column
+    };
+
+    map.list.append_column(&time_column);
+    map.list.append_column(&pages_column);
+    map.list.get_selection().set_mode(gtk::SelectionMode::Single);
+    map.pages.append_column(&title_column);
+    map.pages.set_headers_visible(false);
+    map.pages.set_enable_tree_lines(true);
+    map.pages.get_selection().set_mode(gtk::SelectionMode::Single);
+
+    map.restore_button.set_sensitive(false);
+    map.reopen_button.set_sensitive(false);
+
+    map.container.connect_map(with_cloned!(app, move |_container| {
+        populate(&app);
+    }));
+
+    map.refresh_button.connect_clicked(with_cloned!(app, move |_button| {
+        populate(&app);
+    }));
+
+    map.list.get_selection().connect_changed(with_cloned!(app, move |_selection| {
+        populate_pages(&app);
+        update_buttons(&app);
+    }));
+
+    map.pages.get_selection().connect_changed(with_cloned!(app, move |_selection| {
+        update_buttons(&app);
+    }));
+
+    map.restore_button.connect_clicked(with_cloned!(app, move |_button| {
+        restore_selected(&app);
+    }));
+
+    map.reopen_button.connect_clicked(with_cloned!(app, move |_button| {
+        reopen_selected(&app);
+    }));
+
+    create_logged(app);
+
+    glib::timeout_add_seconds_local(SNAPSHOT_INTERVAL_SECONDS, with_cloned!(app, move || {
+        create_logged(&app);
+        glib::Continue(true)
+    }));
+}
+
+/// Replaces all pages with the page tree of the selected snapshot. The
+/// current tree is stored as a new snapshot first.
+fn restore_selected(app: &app::Handle) {
+
+    let snapshot = unwrap_or_return!(selected_snapshot(app));
+    let window = app.window();
+    let result = window::confirm_action(
+        &window,
+        "Replace all pages with the pages of this snapshot? \
+            The current pages are kept in a new snapshot.",
+        &[("Restore", RES_OK), ("Cancel", RES_CANCEL)],
+        RES_OK,
+    );
+    if result != RES_OK {
+        return;
+    }
+
+    create_logged(app);
+    let page_store = app.page_store();
+    {
+        let session = unwrap_or_return!(page_store.session());
+        if let Err(error) = session.restore_snapshot(snapshot) {
+            window::show_warning(&window, &format!("Unable to restore snapshot: {:?}", error));
+            return;
+        }
+    }
+    let last_selected = app.without_select(|| {
+        app.clear_active();
+        page_store.reload_session()
+    });
+    app_action::show_loaded_pages(app, last_selected);
+    populate(app);
+}
+
+/// Grafts the selected page of the snapshot and everything below it at the
+/// end of the current page tree.
+fn reopen_selected(app: &app::Handle) {
+
+    let snapshot = unwrap_or_return!(selected_snapshot(app));
+    let id = unwrap_or_return!(selected_page(app));
+    let page_store = app.page_store();
+    let document = {
+        let session = unwrap_or_return!(page_store.session());
+        let tree = match session.load_snapshot_tree(snapshot) {
+            Ok(tree) => tree,
+            Err(error) => {
+                window::show_warning(
+                    &app.window(),
+                    &format!("Unable to load snapshot: {:?}", error),
+                );
+                return;
+            },
+        };
+        portable_tree::Document::from_node(unwrap_or_return!(tree.find_node(id)))
+    };
+    let ids = document.graft(&page_store, None);
+    if let Some(&id) = ids.first() {
+        app.page_tree_view().select(id);
+    }
+}
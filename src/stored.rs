@@ -5,12 +5,20 @@ use app;
 use history;
 use shortcuts;
 use bookmarks;
+use domain_rules;
+use cookies;
+use requests;
+use snapshots;
 
 #[derive(Debug)]
 pub enum Section {
     History,
     Bookmarks,
     Shortcuts,
+    DomainRules,
+    Cookies,
+    Requests,
+    Snapshots,
 }
 
 pub struct Map {
@@ -18,6 +26,10 @@ pub struct Map {
     history: history::Map,
     shortcuts: shortcuts::Map,
     bookmarks: bookmarks::Map,
+    domain_rules: domain_rules::Map,
+    cookies: cookies::Map,
+    requests: requests::Map,
+    snapshots: snapshots::Map,
 }
 
 impl Map {
@@ -28,6 +40,10 @@ impl Map {
             history: history::Map::new(),
             shortcuts: shortcuts::Map::new(),
             bookmarks: bookmarks::Map::new(),
+            domain_rules: domain_rules::Map::new(),
+            cookies: cookies::Map::new(),
+            requests: requests::Map::new(),
+            snapshots: snapshots::Map::new(),
         }
     }
 
@@ -35,6 +51,10 @@ impl Map {
     pub fn history(&self) -> &history::Map { &self.history }
     pub fn shortcuts(&self) -> &shortcuts::Map { &self.shortcuts }
     pub fn bookmarks(&self) -> &bookmarks::Map { &self.bookmarks }
+    pub fn domain_rules(&self) -> &domain_rules::Map { &self.domain_rules }
+    pub fn cookies(&self) -> &cookies::Map { &self.cookies }
+    pub fn requests(&self) -> &requests::Map { &self.requests }
+    pub fn snapshots(&self) -> &snapshots::Map { &self.snapshots }
 
     pub fn show_section(&self, section: Section) {
         use gtk::prelude::*;
@@ -43,12 +63,20 @@ impl Map {
             Section::History => 0,
             Section::Bookmarks => 1,
             Section::Shortcuts => 2,
+            Section::DomainRules => 3,
+            Section::Cookies => 4,
+            Section::Requests => 5,
+            Section::Snapshots => 6,
         });
         self.container.show();
         match section {
             Section::History => self.history.focus(),
             Section::Shortcuts => self.shortcuts.focus(),
             Section::Bookmarks => self.bookmarks.focus(),
+            Section::DomainRules => self.domain_rules.focus(),
+            Section::Cookies => self.cookies.focus(),
+            Section::Requests => self.requests.focus(),
+            Section::Snapshots => self.snapshots.focus(),
         }
     }
 
@@ -83,6 +111,10 @@ pub fn setup(app: &app::Handle) {
     setup_page(&map, "History", map.history.container());
     setup_page(&map, "Bookmarks", map.bookmarks.container());
     setup_page(&map, "Shortcuts", map.shortcuts.container());
+    setup_page(&map, "Domain Rules", map.domain_rules.container());
+    setup_page(&map, "Cookies", map.cookies.container());
+    setup_page(&map, "Requests", map.requests.container());
+    setup_page(&map, "Snapshots", map.snapshots.container());
     
     map.container.show_all();
     map.container.set_no_show_all(true);
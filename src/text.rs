@@ -13,6 +13,20 @@ where T: Into<u64> {
     if value.into() == 1 { singular } else { plural }
 }
 
+/// Describes how long ago a time in seconds since the Unix epoch was.
+pub fn format_age(time: u64, now: u64) -> String {
+    let age = now.saturating_sub(time);
+    if age < 60 {
+        format!("{}s ago", age)
+    } else if age < 60 * 60 {
+        format!("{}m ago", age / 60)
+    } else if age < 60 * 60 * 24 {
+        format!("{}h ago", age / (60 * 60))
+    } else {
+        format!("{}d ago", age / (60 * 60 * 24))
+    }
+}
+
 pub fn escape<'a>(value: &'a str) -> borrow::Cow<'a, str> {
 
     const REPLACE: &[char] = &['&', '"', '<', '>'];
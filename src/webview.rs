@@ -1,10 +1,18 @@
 
+use std::rc;
+use std::cell;
+use std::path;
+
 use webkit2gtk;
 use gdk;
+use glib;
 
 use app;
+use cookies;
+use domain_settings;
 use page_store;
 use mouse;
+use navigation_bar;
 use window;
 use text;
 use page_state;
@@ -211,6 +219,89 @@ fn on_load_changed(
     log_trace!("load state for {}: {:?}", id, state);
 
     page_store.set_load_state(id, state);
+
+    if event == webkit2gtk::LoadEvent::Finished {
+        page_store.save_session_state(id);
+        cookies::remove_forbidden_cookies(app);
+    }
+}
+
+/// Applies the site preferences of the host currently shown in the view.
+pub fn apply_site_preferences(app: &app::Handle, view: &webkit2gtk::WebView) {
+    use webkit2gtk::{ WebViewExt, SettingsExt };
+
+    let host = view.get_uri().and_then(|uri| domain_settings::Host::from_uri_str(&uri));
+    let preferences = host
+        .map(|host| app.domain_settings().effective_site_preferences(&host))
+        .unwrap_or_else(|| domain_settings::SitePreferences::default());
+
+    log_debug!("site preferences for {:?}: {:?}", view.get_uri(), preferences);
+
+    if let Some(settings) = view.get_settings() {
+        settings.set_enable_javascript(preferences.javascript.unwrap_or(true));
+        settings.set_auto_load_images(preferences.images.unwrap_or(true));
+        settings.set_user_agent(preferences.user_agent.as_ref().map(|agent| agent.as_str()));
+        settings.set_media_playback_requires_user_gesture(match preferences.autoplay {
+            Some(domain_settings::Autoplay::RequireGesture) => true,
+            _ => false,
+        });
+    }
+    view.set_zoom_level(preferences.zoom_level.unwrap_or(1.0));
+}
+
+fn accept_policy(policy: domain_settings::CookiePolicy) -> webkit2gtk::CookieAcceptPolicy {
+    match policy {
+        domain_settings::CookiePolicy::Always => webkit2gtk::CookieAcceptPolicy::Always,
+        domain_settings::CookiePolicy::NoThirdParty => webkit2gtk::CookieAcceptPolicy::NoThirdParty,
+        domain_settings::CookiePolicy::Never => webkit2gtk::CookieAcceptPolicy::Never,
+    }
+}
+
+/// Switches the cookie accept policy to the one of the host shown in the
+/// view.
+///
+/// The cookie manager is shared by all views, so this is applied for the
+/// active view only and pages in other views run under the same policy.
+/// Cookies of hosts set to `Never` are removed after every finished load
+/// by `cookies::remove_forbidden_cookies`.
+pub fn apply_cookie_policy(app: &app::Handle, view: &webkit2gtk::WebView) {
+    use webkit2gtk::{ WebViewExt, WebContextExt, CookieManagerExt };
+
+    let domains = app.domain_settings();
+    let policy = view.get_uri()
+        .and_then(|uri| domain_settings::Host::from_uri_str(&uri))
+        .map(|host| domains.effective_cookie_policy(&host))
+        .unwrap_or_else(|| domains.cookie_policy());
+
+    log_debug!("cookie policy for {:?}: {:?}", view.get_uri(), policy);
+
+    if let Some(cookie_manager) = app.web_context().get_cookie_manager() {
+        cookie_manager.set_accept_policy(accept_policy(policy));
+    }
+}
+
+fn on_site_load_changed(
+    app: &app::Handle,
+    view: &webkit2gtk::WebView,
+    event: webkit2gtk::LoadEvent,
+    site_host: &cell::RefCell<Option<domain_settings::Host>>,
+) {
+    use webkit2gtk::{ WebViewExt };
+
+    match event {
+        webkit2gtk::LoadEvent::Started | webkit2gtk::LoadEvent::Redirected => (),
+        _ => return,
+    }
+
+    let host = view.get_uri().and_then(|uri| domain_settings::Host::from_uri_str(&uri));
+    if *site_host.borrow() == host {
+        return;
+    }
+    apply_site_preferences(app, view);
+    if app.active_webview().as_ref() == Some(view) {
+        apply_cookie_policy(app, view);
+    }
+    *site_host.borrow_mut() = host;
 }
 
 fn on_mouse_target_changed(
@@ -225,7 +316,11 @@ fn on_mouse_target_changed(
     status_bar.set_hover_uri(hit.get_link_uri());
 }
 
+/// Interval for dropping page-state kept for pages without a view.
+const PAGE_STATE_SWEEP_SECONDS: u32 = 60;
+
 pub fn create(id: page_store::Id, app: &app::Handle) -> webkit2gtk::WebView {
+    use gtk::prelude::*;
     use webkit2gtk::{ WebViewExtManual, WebViewExt };
 
     let new_view = webkit2gtk::WebView::new_with_context_and_user_content_manager(
@@ -253,6 +348,27 @@ pub fn create(id: page_store::Id, app: &app::Handle) -> webkit2gtk::WebView {
         on_load_changed(&app, id, view, change);
     }));
 
+    let site_host = rc::Rc::new(cell::RefCell::new(None));
+    new_view.connect_load_changed(with_cloned!(app, move |view, change| {
+        on_site_load_changed(&app, view, change, &site_host);
+    }));
+
+    let page_id = new_view.get_page_id();
+    new_view.connect_destroy(with_cloned!(app, move |_view| {
+        log_debug!("forgetting page state for page {}", id);
+        if let Err(error) = app.page_state_client().forget_page(page_id) {
+            eprintln!("Unable to forget page state: {}", error);
+        }
+    }));
+
+    new_view.connect_web_process_crashed(with_cloned!(app, move |view| {
+        log_debug!("web process of page {} exited", id);
+        if let Err(error) = app.page_state_client().clear_page(view.get_page_id()) {
+            eprintln!("Unable to clear page state: {}", error);
+        }
+        false
+    }));
+
     new_view
 }
 
@@ -265,24 +381,43 @@ pub fn setup(app: &app::Handle) {
         let view_space = app.view_space();
         let page_store = app.page_store();
         let view = unwrap_or_return!(page_store.get_view(id, &app));
+        if let Some(active_id) = app.get_active() {
+            page_store.save_session_state(active_id);
+        }
         match app.active_webview() {
             Some(webview) => webview.hide(),
             None => (),
         }
         app.set_active(id, view.clone());
+        apply_cookie_policy(&app, &view);
+        navigation_bar::update_blocked_badge(&app);
         if view.get_parent().is_none() {
             view_space.pack_start(&view, true, true, 0);
         }
         view_space.show();
         view.show_all();
     }));
+
+    glib::timeout_add_seconds_local(PAGE_STATE_SWEEP_SECONDS, with_cloned!(app, move || {
+        use webkit2gtk::{ WebViewExt };
+
+        let page_ids = app.page_store().views().iter()
+            .map(|view| view.get_page_id())
+            .collect::<Vec<_>>();
+        if let Err(error) = app.page_state_client().retain_pages(&page_ids) {
+            eprintln!("Unable to sweep page state: {}", error);
+        }
+        glib::Continue(true)
+    }));
 }
 
 pub fn create_web_context(
     is_private: bool,
+    cookie_path: &path::Path,
+    cookie_policy: domain_settings::CookiePolicy,
     init_args: page_state::InitArguments,
 ) -> webkit2gtk::WebContext {
-    use webkit2gtk::{ WebContextExt };
+    use webkit2gtk::{ WebContextExt, CookieManagerExt };
     use gtk::prelude::*;
     use serde_json;
 
@@ -302,6 +437,16 @@ pub fn create_web_context(
     web_context.set_process_model(webkit2gtk::ProcessModel::MultipleSecondaryProcesses);
     web_context.set_web_process_count_limit(1);
 
+    if let Some(cookie_manager) = web_context.get_cookie_manager() {
+        if !is_private {
+            cookie_manager.set_persistent_storage(
+                &cookie_path.to_string_lossy(),
+                webkit2gtk::CookiePersistentStorage::Sqlite,
+            );
+        }
+        cookie_manager.set_accept_policy(accept_policy(cookie_policy));
+    }
+
     web_context
 }
 
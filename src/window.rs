@@ -1,7 +1,10 @@
 
+use std::path;
+
 use gtk;
 
 use app;
+use storage;
 
 const APP_NAME: &str = "Brimstone";
 
@@ -36,12 +39,15 @@ pub fn setup(app: &app::Handle) {
 
     window.add(&app.main_paned());
 
-    window.connect_delete_event(|window, _event| {
+    window.connect_delete_event(with_cloned!(app, move |window, _event| {
         match confirm_close(window, "the application") {
-            CloseAnswer::Close => gtk::prelude::Inhibit(false),
+            CloseAnswer::Close => {
+                app.page_store().save_all_session_states();
+                gtk::prelude::Inhibit(false)
+            },
             CloseAnswer::Cancel => gtk::prelude::Inhibit(true),
         }
-    });
+    }));
 
     page_tree_view.on_selection_change(with_cloned!(app, move |_map, &id| {
         let page_store = app.page_store();
@@ -83,6 +89,104 @@ pub fn confirm_action(
     result
 }
 
+pub fn show_warning(window: &gtk::ApplicationWindow, text: &str) {
+    use gtk::prelude::*;
+
+    let dialog = gtk::MessageDialog::new(
+        Some(window),
+        gtk::DialogFlags::MODAL | gtk::DialogFlags::DESTROY_WITH_PARENT,
+        gtk::MessageType::Warning,
+        gtk::ButtonsType::Ok,
+        text,
+    );
+    dialog.run();
+    dialog.destroy();
+}
+
+pub fn show_info(window: &gtk::ApplicationWindow, text: &str) {
+    use gtk::prelude::*;
+
+    let dialog = gtk::MessageDialog::new(
+        Some(window),
+        gtk::DialogFlags::MODAL | gtk::DialogFlags::DESTROY_WITH_PARENT,
+        gtk::MessageType::Info,
+        gtk::ButtonsType::Ok,
+        text,
+    );
+    dialog.run();
+    dialog.destroy();
+}
+
+/// Asks for a single line of text, returns `None` when cancelled or empty.
+pub fn ask_text(
+    window: &gtk::ApplicationWindow,
+    title: &str,
+    label: &str,
+    initial: &str,
+) -> Option<String> {
+    use gtk::prelude::*;
+
+    let dialog = gtk::Dialog::new_with_buttons(
+        Some(title),
+        Some(window),
+        gtk::DialogFlags::MODAL | gtk::DialogFlags::DESTROY_WITH_PARENT,
+        &[
+            ("Cancel", gtk::ResponseType::Cancel.into()),
+            ("Ok", gtk::ResponseType::Accept.into()),
+        ],
+    );
+    dialog.set_default_response(gtk::ResponseType::Accept.into());
+
+    let entry = gtk::Entry::new();
+    entry.set_text(initial);
+    entry.set_activates_default(true);
+    let content = dialog.get_content_area();
+    content.add(&gtk::Label::new(label));
+    content.add(&entry);
+    content.show_all();
+
+    let result = dialog.run();
+    let text = entry.get_text()
+        .map(|text| text.trim().to_string())
+        .unwrap_or_else(|| String::new());
+
+    dialog.destroy();
+
+    if result == gtk::ResponseType::Accept.into() && !text.is_empty() {
+        Some(text)
+    } else {
+        None
+    }
+}
+
+pub fn choose_file(
+    window: &gtk::ApplicationWindow,
+    title: &str,
+    action: gtk::FileChooserAction,
+    accept_label: &str,
+) -> Option<path::PathBuf> {
+    use gtk::prelude::*;
+
+    let dialog = gtk::FileChooserDialog::new(Some(title), Some(window), action);
+    dialog.add_button("_Cancel", gtk::ResponseType::Cancel.into());
+    dialog.add_button(accept_label, gtk::ResponseType::Accept.into());
+    dialog.set_default_response(gtk::ResponseType::Accept.into());
+    if action == gtk::FileChooserAction::Save {
+        dialog.set_do_overwrite_confirmation(true);
+    }
+
+    let result = dialog.run();
+    let path = dialog.get_filename();
+
+    dialog.destroy();
+
+    if result == gtk::ResponseType::Accept.into() {
+        path
+    } else {
+        None
+    }
+}
+
 pub enum CloseAnswer { Close, Cancel }
 
 pub fn confirm_close(window: &gtk::ApplicationWindow, what: &str) -> CloseAnswer {
@@ -106,4 +210,30 @@ pub fn present(app: &app::Handle) {
     use gtk::prelude::*;
 
     app.window().show_all();
+
+    let recovered = app.recovered_storage();
+    if !recovered.is_empty() {
+        show_warning(&app.window(), &format_recovery_warning(&recovered));
+    }
+    if let Some(error) = app.suffix_list_error() {
+        show_warning(&app.window(), &error);
+    }
+}
+
+fn format_recovery_warning(recovered: &[(&str, storage::Recovery)]) -> String {
+
+    let mut text = String::from(
+        "Some profile databases were damaged and have been repaired:\n"
+    );
+    for &(name, ref recovery) in recovered {
+        let rows: usize = recovery.tables.iter().map(|&(_, count)| count).sum();
+        text.push_str(&format!("\n{}: {} {} salvaged{}, damaged file moved to {}",
+            name,
+            rows,
+            if rows == 1 { "row" } else { "rows" },
+            if recovery.is_complete { "" } else { " (some data was lost)" },
+            recovery.moved_to.display(),
+        ));
+    }
+    text
 }